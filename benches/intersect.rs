@@ -0,0 +1,73 @@
+//! One ray against one object, for the objects of the demo scene. Every object gets a ray that hits it and one that misses,
+//! misses are most of the tests in a scene and often take a different (shorter) path.
+//! Run with `cargo bench --bench intersect`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use tinyraytracer::blocks::{Material, Plane, Ray, RayCollision, Rectangle2D, Sphere};
+use tinyraytracer::float::Float;
+use tinyraytracer::vectors::Vec3;
+
+/// A ray from the origin towards `target`
+fn ray_to(target: (Float, Float, Float)) -> Ray {
+    Ray::new(Vec3::new(target))
+}
+
+fn bench_object(c: &mut Criterion, name: &str, object: &dyn RayCollision, hit: Ray, miss: Ray) {
+    let mut group = c.benchmark_group(name);
+    group.bench_function("hit", |b| b.iter(|| object.ray_intersect(black_box(&hit))));
+    group.bench_function("miss", |b| {
+        b.iter(|| object.ray_intersect(black_box(&miss)))
+    });
+    group.finish();
+}
+
+fn bench_sphere(c: &mut Criterion) {
+    let sphere = Sphere {
+        center: Vec3::new((-1., -1.5, -12.)),
+        radius: 2.,
+        material: Material::default(),
+    };
+    bench_object(
+        c,
+        "sphere",
+        &sphere,
+        ray_to((-1., -1.5, -12.)),
+        ray_to((5., 5., -12.)),
+    );
+}
+
+fn bench_plane(c: &mut Criterion) {
+    let plane = Plane {
+        normal: Vec3::new((0., 1., 0.)),
+        point: Vec3::new((0., -4., 0.)),
+    };
+    // Rays going up never reach the floor
+    bench_object(
+        c,
+        "plane",
+        &plane,
+        ray_to((0., -4., -12.)),
+        ray_to((0., 1., -12.)),
+    );
+}
+
+fn bench_rectangle(c: &mut Criterion) {
+    // The floor of the demo scene
+    let rectangle = Rectangle2D::new(
+        Vec3::new((-8., -4., -8.)),
+        Vec3::new((3., -4., -16.)),
+        Vec3::new((1., -0.05, 0.)),
+        Material::default(),
+    );
+    // The second ray hits the plane of the rectangle, but outside of it
+    bench_object(
+        c,
+        "rectangle",
+        &rectangle,
+        ray_to((3., -4., -16.)),
+        ray_to((30., -4., -16.)),
+    );
+}
+
+criterion_group!(benches, bench_sphere, bench_plane, bench_rectangle);
+criterion_main!(benches);
@@ -0,0 +1,70 @@
+//! Intersecting 8 coherent rays one at a time, as a packet with plain loops, and as a packet with the SIMD kernels.
+//! Run with `cargo bench --bench packet`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use tinyraytracer::blocks::{HitPoint, Material, Ray, RayCollision, Sphere, Triangle};
+use tinyraytracer::float::Float;
+use tinyraytracer::packet::{self, RayPacket, LANES};
+use tinyraytracer::vectors::Vec3;
+
+/// Rays from the origin through 8 neighbouring pixels, like the primary rays of a camera
+fn coherent_rays() -> Vec<Ray> {
+    (0..LANES)
+        .map(|k| Ray::new(Vec3::new((k as Float * 0.01 - 0.04, 0.02, -1.))))
+        .collect()
+}
+
+fn intersect_one_by_one(object: &dyn RayCollision, rays: &[Ray]) -> usize {
+    rays.iter()
+        .filter(|ray| !matches!(object.ray_intersect(ray), HitPoint::None))
+        .count()
+}
+
+fn bench_sphere(c: &mut Criterion) {
+    let sphere = Sphere {
+        center: Vec3::new((0., 0., -10.)),
+        radius: 2.,
+        material: Material::default(),
+    };
+    let rays = coherent_rays();
+    let packet = RayPacket::new(&rays);
+
+    let mut group = c.benchmark_group("sphere");
+    group.bench_function("one by one", |b| {
+        b.iter(|| intersect_one_by_one(black_box(&sphere), black_box(&rays)))
+    });
+    group.bench_function("packet, scalar", |b| {
+        b.iter(|| packet::scalar::sphere(black_box(&packet), sphere.center, sphere.radius))
+    });
+    group.bench_function("packet, simd", |b| {
+        b.iter(|| sphere.packet_intersect(black_box(&packet)))
+    });
+    group.finish();
+}
+
+fn bench_triangle(c: &mut Criterion) {
+    let triangle = Triangle::new(
+        Vec3::new((-2., -2., -10.)),
+        Vec3::new((2., -2., -10.)),
+        Vec3::new((0., 2., -10.)),
+        Material::default(),
+    );
+    let rays = coherent_rays();
+    let packet = RayPacket::new(&rays);
+    let (e1, e2) = (triangle.b - triangle.a, triangle.c - triangle.a);
+
+    let mut group = c.benchmark_group("triangle");
+    group.bench_function("one by one", |b| {
+        b.iter(|| intersect_one_by_one(black_box(&triangle), black_box(&rays)))
+    });
+    group.bench_function("packet, scalar", |b| {
+        b.iter(|| packet::scalar::triangle(black_box(&packet), triangle.a, e1, e2))
+    });
+    group.bench_function("packet, simd", |b| {
+        b.iter(|| triangle.packet_intersect(black_box(&packet)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_sphere, bench_triangle);
+criterion_main!(benches);
@@ -0,0 +1,82 @@
+//! Tracing whole rays through whole scenes: [`cast_ray`] through scenes with more and more spheres, and the demo scene rendered at small resolutions.
+//! Without an acceleration structure the time of `cast_ray` grows with the number of objects, these benchmarks show by how much.
+//! Run with `cargo bench --bench render`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tinyraytracer::blocks::{Material, Ray, Sphere};
+use tinyraytracer::camera::ThinLensCamera;
+use tinyraytracer::demo::demo_scene;
+use tinyraytracer::float::consts::FRAC_2_PI;
+use tinyraytracer::float::Float;
+use tinyraytracer::sampling::PixelSampler;
+use tinyraytracer::trace::{cast_ray, render_pixels, SceneObject};
+use tinyraytracer::vectors::Vec3;
+
+/// `count` spheres on a square grid in front of the camera, filling the same part of the view whatever their number
+fn sphere_grid(count: usize) -> Vec<SceneObject> {
+    let side = (count as Float).sqrt().ceil() as usize;
+    let spacing = 16. / side as Float;
+    (0..count)
+        .map(|k| {
+            let (x, y) = ((k % side) as Float, (k / side) as Float);
+            Box::new(Sphere {
+                center: Vec3::new((-8. + (x + 0.5) * spacing, -8. + (y + 0.5) * spacing, -20.)),
+                radius: 0.4 * spacing,
+                material: Material::default(),
+            }) as SceneObject
+        })
+        .collect()
+}
+
+/// Rays from the origin through a 16x16 grid covering the spheres, some hit and some go between the spheres
+fn grid_rays() -> Vec<Ray> {
+    (0..256)
+        .map(|k| {
+            let (x, y) = ((k % 16) as Float, (k / 16) as Float);
+            Ray::new(Vec3::new((
+                (x + 0.3) / 16. - 0.5,
+                (y + 0.3) / 16. - 0.5,
+                -1.,
+            )))
+        })
+        .collect()
+}
+
+fn bench_cast_ray(c: &mut Criterion) {
+    let rays = grid_rays();
+
+    let mut group = c.benchmark_group("cast_ray");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    for count in [1, 4, 16, 64, 256] {
+        let scene = sphere_grid(count);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &scene, |b, scene| {
+            b.iter(|| {
+                rays.iter()
+                    .filter(|&&ray| cast_ray(ray, black_box(scene)).is_some())
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_demo_render(c: &mut Criterion) {
+    let (scene, lights, frame) = demo_scene(0.);
+
+    let mut group = c.benchmark_group("demo render");
+    group.sample_size(10);
+    let sampler = PixelSampler::new(1, 0);
+    for (width, height) in [(64, 48), (160, 120)] {
+        let camera =
+            ThinLensCamera::from_frame(frame, 2. * FRAC_2_PI, width as Float / height as Float);
+        group.throughput(Throughput::Elements(u64::from(width * height)));
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{}x{}", width, height)),
+            |b| b.iter(|| render_pixels(&scene, &lights, &camera, (width, height), &sampler)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_cast_ray, bench_demo_render);
+criterion_main!(benches);
@@ -0,0 +1,90 @@
+//! The vector operations every intersection test and every shading step is made of.
+//! The `vec3 x8` group does them on batches of vectors, 8 at a time: `scalar` is the plain loop, `simd` the AVX kernels where the CPU has them.
+//! Run with `cargo bench --bench vectors`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use tinyraytracer::float::Float;
+use tinyraytracer::packet::{add_all, cross_all, dot_all, normalize_all, scalar, Vec3Lanes, LANES};
+use tinyraytracer::vectors::Vec3;
+
+/// Vectors per batch, divided into groups of [`LANES`]
+const BATCH: usize = 128;
+
+fn bench_vec3(c: &mut Criterion) {
+    let a = Vec3::new((0.3, -1.2, 2.5));
+    let b = Vec3::new((-0.7, 0.4, 1.1));
+    let normal = Vec3::new((0., 1., 0.));
+    let direction = Vec3::new((0.6, -0.8, 0.)).normalized();
+
+    let mut group = c.benchmark_group("vec3");
+    group.bench_function("add", |bench| bench.iter(|| black_box(a) + black_box(b)));
+    group.bench_function("sub", |bench| bench.iter(|| black_box(a) - black_box(b)));
+    group.bench_function("mult", |bench| {
+        bench.iter(|| black_box(a).mult(black_box(1.5)))
+    });
+    group.bench_function("dot", |bench| {
+        bench.iter(|| black_box(a).dot(&black_box(b)))
+    });
+    group.bench_function("cross", |bench| {
+        bench.iter(|| black_box(a).cross(&black_box(b)))
+    });
+    group.bench_function("l2", |bench| bench.iter(|| black_box(a).l2()));
+    group.bench_function("normalized", |bench| {
+        bench.iter(|| black_box(a).normalized())
+    });
+    group.bench_function("reflect", |bench| {
+        bench.iter(|| black_box(direction).reflect(black_box(normal)))
+    });
+    group.bench_function("refract", |bench| {
+        bench.iter(|| black_box(direction).refract(black_box(normal), black_box(1.5)))
+    });
+    group.finish();
+}
+
+fn bench_vec3_lanes(c: &mut Criterion) {
+    let lanes = |offset: Float| {
+        (0..BATCH)
+            .map(|k| {
+                let vectors = (0..LANES)
+                    .map(|i| {
+                        let x = (k * LANES + i) as Float;
+                        Vec3::new((x + offset, 1.5 - x, 0.25 * x))
+                    })
+                    .collect::<Vec<Vec3>>();
+                Vec3Lanes::new(&vectors)
+            })
+            .collect::<Vec<Vec3Lanes>>()
+    };
+    let (a, b) = (lanes(0.3), lanes(-0.7));
+
+    let mut group = c.benchmark_group("vec3 x8");
+    group.throughput(Throughput::Elements((BATCH * LANES) as u64));
+    group.bench_function("add/scalar", |bench| {
+        bench.iter(|| scalar::add_all(black_box(&a), black_box(&b)))
+    });
+    group.bench_function("add/simd", |bench| {
+        bench.iter(|| add_all(black_box(&a), black_box(&b)))
+    });
+    group.bench_function("dot/scalar", |bench| {
+        bench.iter(|| scalar::dot_all(black_box(&a), black_box(&b)))
+    });
+    group.bench_function("dot/simd", |bench| {
+        bench.iter(|| dot_all(black_box(&a), black_box(&b)))
+    });
+    group.bench_function("cross/scalar", |bench| {
+        bench.iter(|| scalar::cross_all(black_box(&a), black_box(&b)))
+    });
+    group.bench_function("cross/simd", |bench| {
+        bench.iter(|| cross_all(black_box(&a), black_box(&b)))
+    });
+    group.bench_function("normalize/scalar", |bench| {
+        bench.iter(|| scalar::normalize_all(black_box(&a)))
+    });
+    group.bench_function("normalize/simd", |bench| {
+        bench.iter(|| normalize_all(black_box(&a)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_vec3, bench_vec3_lanes);
+criterion_main!(benches);
@@ -0,0 +1,270 @@
+//! Things that change over time. A [`Track`] holds values at a few points in time (keyframes) and fills in the values between them.
+//!
+//! Tracks drive moving objects within a single frame (motion blur) as well as anything that changes between frames of an animation:
+//! the camera, objects and lights.
+
+use crate::float::Float;
+use crate::vectors::{Mat4, Quat, Transform, Vec3};
+
+/// Values we can blend: `a.lerp(&b, 0.)` is `a`, `a.lerp(&b, 1.)` is `b` and anything in between is a mix of both.
+pub trait Lerp: Copy {
+    fn lerp(&self, other: &Self, t: Float) -> Self;
+}
+
+impl Lerp for Float {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        Vec3::lerp(self, other, t)
+    }
+}
+
+impl Lerp for Mat4 {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = self.get(r, c).lerp(&other.get(r, c), t);
+            }
+        }
+        Mat4::new(m)
+    }
+}
+
+/// Turns at a constant speed, see [`Quat::slerp`]
+impl Lerp for Quat {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        self.slerp(other, t)
+    }
+}
+
+/// Splits both transforms into translation, rotation and scale (see [`Transform::to_trs`]), blends them one by one and puts them back together.
+/// Translation and scale change linearly and the rotation turns at a constant speed, so an object stays the same shape while it turns.
+/// A transform with shear can't be split like that, then we blend the matrices entry by entry instead.
+///
+/// A scale that passes through zero (from a mirrored transform to an unmirrored one, or overshooting in a [Catmull-Rom](Interpolation::CatmullRom) track)
+/// would flatten the object and make the transform singular, so we keep every scale factor a little away from zero.
+impl Lerp for Transform {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        let ((t1, r1, s1), (t2, r2, s2)) = match (self.to_trs(), other.to_trs()) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Transform::new(self.matrix().lerp(other.matrix(), t)),
+        };
+        let min_scale = Float::EPSILON.sqrt();
+        let s = s1.lerp(&s2, t);
+        let scale = Vec3::new((
+            s.x().abs().max(min_scale).copysign(s.x()),
+            s.y().abs().max(min_scale).copysign(s.y()),
+            s.z().abs().max(min_scale).copysign(s.z()),
+        ));
+        Transform::from_trs(t1.lerp(&t2, t), r1.slerp(&r2, t), scale)
+    }
+}
+
+/// How a [`Track`] fills in values between keyframes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Straight line between keyframes, motion changes direction abruptly at each keyframe
+    Linear,
+    /// Eases in and out of every keyframe, the value comes to a stop at each keyframe
+    Smoothstep,
+    /// [Catmull-Rom spline](https://en.wikipedia.org/wiki/Centripetal_Catmull%E2%80%93Rom_spline) through the keyframes, smooth motion that doesn't stop at keyframes
+    CatmullRom,
+}
+
+/// Keyframes of a value over time, sorted by time. Before the first keyframe and after the last one the value stays put.
+#[derive(Clone, Debug)]
+pub struct Track<T: Lerp> {
+    keys: Vec<(Float, T)>,
+    interpolation: Interpolation,
+}
+
+impl<T: Lerp> Track<T> {
+    /// Linearly interpolated track, panics if there are no keyframes
+    pub fn new(mut keys: Vec<(Float, T)>) -> Self {
+        assert!(!keys.is_empty(), "A track needs at least one keyframe");
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Self {
+            keys,
+            interpolation: Interpolation::Linear,
+        }
+    }
+
+    /// A value that doesn't change
+    pub fn constant(value: T) -> Self {
+        Self::new(vec![(0., value)])
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    pub fn keys(&self) -> &[(Float, T)] {
+        &self.keys
+    }
+
+    /// Values from the first keyframe to the last one, `steps` evenly spaced samples per segment between two keyframes plus the last keyframe.
+    /// Good for finding everywhere the value goes, e.g. the box a moving object sweeps through.
+    pub fn sweep(&self, steps: usize) -> impl Iterator<Item = T> + '_ {
+        let steps = steps.max(1);
+        let last = self.keys[self.keys.len() - 1];
+        self.keys
+            .windows(2)
+            .flat_map(move |pair| {
+                let (t1, t2) = (pair[0].0, pair[1].0);
+                (0..steps).map(move |i| self.sample(t1 + (t2 - t1) * i as Float / steps as Float))
+            })
+            .chain(std::iter::once(last.1))
+    }
+
+    /// Value at `time`, interpolated between the two keyframes around it. At the time of a keyframe it is exactly the keyframe's value.
+    pub fn sample(&self, time: Float) -> T {
+        let next = self.keys.iter().position(|(t, _)| *t > time);
+        let i = match next {
+            Some(0) => return self.keys[0].1,
+            None => return self.keys[self.keys.len() - 1].1,
+            Some(i) => i,
+        };
+
+        let ((t1, v1), (t2, v2)) = (self.keys[i - 1], self.keys[i]);
+        if time == t1 {
+            return v1;
+        }
+        let u = (time - t1) / (t2 - t1);
+
+        match self.interpolation {
+            Interpolation::Linear => v1.lerp(&v2, u),
+            Interpolation::Smoothstep => v1.lerp(&v2, u * u * (3. - 2. * u)),
+            Interpolation::CatmullRom => {
+                // The first and last segments have no outer neighbour, we mirror the segment to make one up
+                let (t0, v0) = match i {
+                    1 => (2. * t1 - t2, v1),
+                    _ => self.keys[i - 2],
+                };
+                let (t3, v3) = match self.keys.get(i + 1) {
+                    Some(&k) => k,
+                    None => (2. * t2 - t1, v2),
+                };
+                catmull_rom((t0, v0), (t1, v1), (t2, v2), (t3, v3), time)
+            }
+        }
+    }
+}
+
+/// Catmull-Rom spline between `p1` and `p2` using the [Barry-Goldman pyramid](https://en.wikipedia.org/wiki/Centripetal_Catmull%E2%80%93Rom_spline#Definition).
+/// It is built only out of (sometimes extrapolating) lerps, so it works for anything we can lerp and handles keyframes that are not evenly spaced.
+fn catmull_rom<T: Lerp>(
+    (t0, p0): (Float, T),
+    (t1, p1): (Float, T),
+    (t2, p2): (Float, T),
+    (t3, p3): (Float, T),
+    t: Float,
+) -> T {
+    let a1 = p0.lerp(&p1, (t - t0) / (t1 - t0));
+    let a2 = p1.lerp(&p2, (t - t1) / (t2 - t1));
+    let a3 = p2.lerp(&p3, (t - t2) / (t3 - t2));
+
+    let b1 = a1.lerp(&a2, (t - t0) / (t2 - t0));
+    let b2 = a2.lerp(&a3, (t - t1) / (t3 - t1));
+
+    b1.lerp(&b2, (t - t1) / (t2 - t1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::consts::{FRAC_PI_2, PI};
+
+    fn assert_same_transform(a: &Transform, b: &Transform) {
+        for r in 0..4 {
+            for c in 0..4 {
+                let (x, y) = (a.matrix().get(r, c), b.matrix().get(r, c));
+                assert!((x - y).abs() < 1e-4, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn transform_lerp_hits_the_keyframes() {
+        let a = Transform::from_trs(
+            Vec3::new((1., 2., 3.)),
+            Quat::from_axis_angle(Vec3::new((1., 1., 0.)), 0.5),
+            Vec3::new((2., 1., 0.5)),
+        );
+        let b = Transform::from_trs(
+            Vec3::new((-1., 0., 4.)),
+            Quat::from_axis_angle(Vec3::new((0., 1., 2.)), 2.),
+            Vec3::new((1., 3., 1.)),
+        );
+        assert_same_transform(&a.lerp(&b, 0.), &a);
+        assert_same_transform(&a.lerp(&b, 1.), &b);
+    }
+
+    /// Blending the matrices of these entry by entry gives a matrix that flattens everything. Half way there we want a quarter turn,
+    /// one way or the other as both are as short.
+    #[test]
+    fn transform_lerp_turns_half_a_turn() {
+        let z = Vec3::new((0., 0., 1.));
+        let (a, b) = (Transform::identity(), Transform::rotate(z, PI));
+        let half = a.lerp(&b, 0.5);
+        let quarter_turns = [
+            Transform::rotate(z, FRAC_PI_2),
+            Transform::rotate(z, -FRAC_PI_2),
+        ];
+        let x = half.vector(Vec3::new((1., 0., 0.)));
+        let closest = quarter_turns
+            .iter()
+            .min_by(|p, q| {
+                let dp = (p.vector(Vec3::new((1., 0., 0.))) - x).l2();
+                let dq = (q.vector(Vec3::new((1., 0., 0.))) - x).l2();
+                dp.partial_cmp(&dq).unwrap()
+            })
+            .unwrap();
+        assert_same_transform(&half, closest);
+    }
+
+    #[test]
+    fn transform_lerp_survives_a_scale_through_zero() {
+        let (a, b) = (
+            Transform::scale(Vec3::new((1., 1., 1.))),
+            Transform::scale(Vec3::new((-1., 1., 1.))),
+        );
+        for i in 0..=10 {
+            a.lerp(&b, i as Float / 10.);
+        }
+        let track = Track::new(vec![(0., a), (1., b), (2., a)])
+            .with_interpolation(Interpolation::CatmullRom);
+        track.sweep(50).for_each(drop);
+    }
+
+    /// Rotating and then scaling along other axes shears, which translation, rotation and scale can't describe.
+    /// The keyframes must still come back as they are, and the blend between them must start and end at them.
+    #[test]
+    fn sheared_keyframes_round_trip() {
+        let z = Vec3::new((0., 0., 1.));
+        let a = Transform::rotate(z, 0.5).then(&Transform::scale(Vec3::new((3., 1., 1.))));
+        let b = Transform::translate(Vec3::new((1., 2., 3.)));
+        assert!(a.to_trs().is_none());
+
+        let track = Track::new(vec![(0., a), (1., b)]);
+        let exactly = |x: &Transform, y: &Transform| {
+            (0..4).all(|r| (0..4).all(|c| x.matrix().get(r, c) == y.matrix().get(r, c)))
+        };
+        assert!(exactly(&track.sample(0.), &a));
+        assert!(exactly(&track.sample(1.), &b));
+        assert_same_transform(&a.lerp(&b, 0.), &a);
+        assert_same_transform(&track.sample(1e-6), &a);
+    }
+
+    #[test]
+    fn sweep_goes_from_first_to_last_keyframe() {
+        let track = Track::new(vec![(0., 0.), (1., 2.), (3., 4.)]);
+        let values = track.sweep(2).collect::<Vec<Float>>();
+        assert_eq!(values, vec![0., 1., 2., 3., 4.]);
+    }
+}
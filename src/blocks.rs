@@ -1,14 +1,220 @@
 //! In our world we have Spheres, Light Sources, Light Rays ang Materials.
 
-use crate::vectors::Vec3;
+use crate::animation::Track;
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::packet::{self, Lanes, RayPacket, LANES};
+use crate::stats::Primitive;
+use crate::vectors::{Onb, Transform, Vec3};
+use std::sync::Arc;
+
+/// Points closer than this to a surface are considered to be on the surface.
+pub(crate) const SURFACE_EPSILON: Float = 1e-4;
+
+/// Closer than this to the origin [`offset_ray_origin`] moves points by a fixed distance instead of a number of ulps
+const OFFSET_ORIGIN: Float = 1. / 32.;
+/// The fixed distance close to the origin, times the normal
+const OFFSET_FLOAT_SCALE: Float = 1. / 65536.;
+/// The number of ulps everywhere else, times the normal
+const OFFSET_INT_SCALE: Float = 256.;
 
 /// We need to determine if a ray of light hits a specific object or not. This trait contains the logic of how to determine that.
 pub trait RayCollision {
     fn ray_intersect(&self, ray: &Ray) -> HitPoint;
 
-    fn collision_normal(&self, hit_point: Vec3) -> Vec3;
+    fn collision_normal(&self, hit_point: Vec3, time: Float) -> Vec3;
+
+    fn collision_material(&self, hit_point: Vec3, time: Float) -> Material;
+
+    /// Texture coordinates of a point on the surface. Bounded surfaces map into the `[0, 1]` range.
+    fn collision_uv(&self, hit_point: Vec3, time: Float) -> (Float, Float);
+
+    /// Smallest axis aligned box containing the object, `None` if the object is unbounded (like an infinite plane).
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// All the parts of the ray that are inside the object, sorted by distance. The distances can be negative (behind the ray origin).
+    ///
+    /// Only closed objects have an inside. Open surfaces like planes, disks and uncapped tubes report nothing, so they can't be used in [constructive solid geometry](crate::csg).
+    fn ray_intervals(&self, _ray: &Ray) -> Vec<Span> {
+        vec![]
+    }
+
+    /// Can the object be seen from behind? See [`Sided`] for changing it.
+    fn sidedness(&self) -> Sidedness {
+        Sidedness::TwoSided
+    }
+
+    /// What kind of object this is, for counting intersection tests in the [render statistics](crate::stats)
+    fn primitive(&self) -> Primitive {
+        Primitive::Other
+    }
+
+    /// Distance along every ray of the packet to where it hits the object, infinity for the rays that miss.
+    /// By default we intersect the rays one by one, objects with a [SIMD kernel](crate::packet) do the whole packet at once.
+    fn packet_intersect(&self, packet: &RayPacket) -> Lanes {
+        let mut t = [Float::INFINITY; LANES];
+        for (lane, t) in t.iter_mut().enumerate() {
+            let ray = packet.ray(lane);
+            if let HitPoint::Point(p) = self.ray_intersect(&ray) {
+                *t = (p - ray.origin).l2();
+            }
+        }
+        t
+    }
+}
+
+/// Which sides of a surface are visible. The front side is the one the normal points to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sidedness {
+    /// Rays hitting the back of the surface go through it ([back-face culling](https://en.wikipedia.org/wiki/Back-face_culling)).
+    /// This also means the back doesn't cast shadows, and closed refractive objects lose their exit surface.
+    OneSided,
+    /// Both sides are visible, the back is shaded as if the normal points towards the viewer.
+    TwoSided,
+}
+
+/// A part of a ray inside a solid object. The ray enters the object at distance `enter` and leaves at distance `exit`.
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+    pub enter: Float,
+    pub exit: Float,
+}
+
+impl Span {
+    /// Pair up sorted surface crossings of a closed object. Every time a ray crosses the surface it goes in or out.
+    /// An odd number of crossings means rounding lost one of them (a ray grazing an edge), we can't tell which one,
+    /// so we report nothing rather than spans that have inside and outside mixed up.
+    pub(crate) fn from_crossings(crossings: &[Float]) -> Vec<Span> {
+        if crossings.len() % 2 == 1 {
+            return vec![];
+        }
+        crossings
+            .chunks_exact(2)
+            .map(|c| Span {
+                enter: c[0],
+                exit: c[1],
+            })
+            .collect()
+    }
+}
+
+/// Axis aligned bounding box. The box sides are parallel to the axes, so two corners are enough to define it.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Smallest box containing all the points. Without any points the box is empty (its min is above its max) and no ray hits it.
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let empty = Self {
+            min: Vec3::new((Float::INFINITY, Float::INFINITY, Float::INFINITY)),
+            max: Vec3::new((Float::NEG_INFINITY, Float::NEG_INFINITY, Float::NEG_INFINITY)),
+        };
+        points.iter().fold(empty, |b, p| Self {
+            min: b.min.min(p),
+            max: b.max.max(p),
+        })
+    }
+
+    /// Box around a disk. Along each axis the disk extends by `radius * sin(angle between the axis and the normal)`.
+    pub fn around_disk(center: Vec3, normal: Vec3, radius: Float) -> Self {
+        let n = normal.normalized();
+        let extent = Vec3::new((
+            radius * (1. - n.x().powf(2.)).max(0.).sqrt(),
+            radius * (1. - n.y().powf(2.)).max(0.).sqrt(),
+            radius * (1. - n.z().powf(2.)).max(0.).sqrt(),
+        ));
+        Self {
+            min: center - extent,
+            max: center + extent,
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(&other.min),
+            max: self.max.max(&other.max),
+        }
+    }
+
+    /// Distances along the ray where it enters and leaves the box, `None` if it misses.
+    /// This is the [slab method](https://en.wikipedia.org/wiki/Slab_method): the box is the intersection of three slabs (one per axis) so the ray is inside
+    /// the box between the last slab it enters and the first slab it leaves.
+    pub fn ray_range(&self, ray: &Ray) -> Option<(Float, Float)> {
+        let axes = [
+            (ray.origin.x(), ray.direction.x(), self.min.x(), self.max.x()),
+            (ray.origin.y(), ray.direction.y(), self.min.y(), self.max.y()),
+            (ray.origin.z(), ray.direction.z(), self.min.z(), self.max.z()),
+        ];
+
+        let mut range = (Float::MIN, Float::MAX);
+        for &(o, d, lo, hi) in axes.iter() {
+            if d == 0. {
+                if o < lo || o > hi {
+                    return None;
+                }
+                continue;
+            }
+            let (t0, t1) = ((lo - o) / d, (hi - o) / d);
+            range = (range.0.max(t0.min(t1)), range.1.min(t0.max(t1)));
+        }
+
+        if range.0 <= range.1 {
+            Some(range)
+        } else {
+            None
+        }
+    }
 
-    fn collision_material(&self, hit_point: Vec3) -> Material;
+    /// Box around the transformed corners of this box
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let (min, max) = (self.min, self.max);
+        let corners = [
+            Vec3::new((min.x(), min.y(), min.z())),
+            Vec3::new((min.x(), min.y(), max.z())),
+            Vec3::new((min.x(), max.y(), min.z())),
+            Vec3::new((min.x(), max.y(), max.z())),
+            Vec3::new((max.x(), min.y(), min.z())),
+            Vec3::new((max.x(), min.y(), max.z())),
+            Vec3::new((max.x(), max.y(), min.z())),
+            Vec3::new((max.x(), max.y(), max.z())),
+        ]
+        .iter()
+        .map(|&c| transform.point(c))
+        .collect::<Vec<Vec3>>();
+        Self::from_points(&corners)
+    }
+
+    /// Box around everything a moving box passes through, given the box at a few closely spaced moments (see [`Track::sweep`]).
+    /// Between two samples the object may bulge out a bit beyond both boxes (it turns, or a spline overshoots its keyframes),
+    /// so we grow the box by half the largest step one of its sides takes between two samples.
+    pub fn swept(boxes: impl Iterator<Item = Aabb>) -> Option<Aabb> {
+        let mut swept: Option<Aabb> = None;
+        let mut previous: Option<Aabb> = None;
+        let mut margin: Float = 0.;
+        for b in boxes {
+            if let Some(p) = previous {
+                let (dmin, dmax) = (b.min - p.min, b.max - p.max);
+                for d in [dmin.x(), dmin.y(), dmin.z(), dmax.x(), dmax.y(), dmax.z()] {
+                    margin = margin.max(d.abs() / 2.);
+                }
+            }
+            swept = Some(swept.map_or(b, |s| s.union(&b)));
+            previous = Some(b);
+        }
+        swept.map(|s| s.expand(margin))
+    }
+
+    /// Grow the box by `margin` in every direction
+    pub fn expand(&self, margin: Float) -> Self {
+        let m = Vec3::new((margin, margin, margin));
+        Self {
+            min: self.min - m,
+            max: self.max + m,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -23,21 +229,37 @@ impl RayCollision for Plane {
         let origin_to_plane_dist = self.normal.dot(&orig_to_point);
         let cos_dir_norm = self.normal.dot(&ray.direction);
 
-        if cos_dir_norm * origin_to_plane_dist < 0. {
-            HitPoint::None
-        } else {
-            let dist_to_collision = origin_to_plane_dist / cos_dir_norm;
+        // Behind the origin the distance is negative, parallel rays get an infinite (or NaN) distance, neither is in range
+        let dist_to_collision = origin_to_plane_dist / cos_dir_norm;
+        if ray.in_range(dist_to_collision) {
             HitPoint::Point(ray.walk_dir(dist_to_collision))
+        } else {
+            HitPoint::None
         }
     }
 
-    fn collision_normal(&self, hit_point: Vec3) -> Vec3 {
+    fn collision_normal(&self, _hit_point: Vec3, _time: Float) -> Vec3 {
         self.normal
     }
 
-    fn collision_material(&self, hit_point: Vec3) -> Material {
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
         Material::default()
     }
+
+    /// Planes are infinite, so the coordinates are not bounded: they are the distances from `point` along two directions in the plane.
+    fn collision_uv(&self, hit_point: Vec3, _time: Float) -> (Float, Float) {
+        let (e1, e2) = tangent_frame(self.normal);
+        let d = hit_point - self.point;
+        (d.dot(&e1), d.dot(&e2))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Plane
+    }
 }
 
 /// 2D rectangle in a 3D space
@@ -53,26 +275,21 @@ impl Rectangle2D {
     /// We need 3 points to define a plane.
     /// Here we use two points on a plane and a vector that is used as the side of the rectangle.
     pub fn new(origin: Vec3, center: Vec3, side_dir: Vec3, material: Material) -> Self {
-        // Distance from
+        // From the corner to the center is half the diagonal. The first side goes along `side_dir`, the second one is the rest of the diagonal.
         let z = center - origin;
-        let e1 = side_dir.normalized();
+        let frame = Onb::from_directions(side_dir, z);
 
-        let u = z.project_on(&e1);
-
-        let e2 = (z - u).normalized();
-
-        let w = 2. * z.project_on(&e1).l2();
-        let h = 2. * z.project_on(&e2).l2();
-        let normal = e1.cross(&e2);
+        let w = 2. * z.dot(&frame.u).abs();
+        let h = 2. * z.dot(&frame.v).abs();
 
         let plane = Plane {
-            normal,
+            normal: frame.w,
             point: origin,
         };
 
         Self {
-            width: e1.mult(w),
-            height: e2.mult(h),
+            width: frame.u.mult(w),
+            height: frame.v.mult(h),
             plane,
             material,
         }
@@ -104,20 +321,42 @@ impl RayCollision for Rectangle2D {
         }
     }
 
-    fn collision_normal(&self, hit_point: Vec3) -> Vec3 {
+    fn collision_normal(&self, _hit_point: Vec3, _time: Float) -> Vec3 {
         self.plane.normal
     }
 
-    fn collision_material(&self, hit_point: Vec3) -> Material {
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
         self.material
     }
+
+    fn collision_uv(&self, hit_point: Vec3, _time: Float) -> (Float, Float) {
+        let d = hit_point - self.plane.point;
+        (
+            d.dot(&self.width) / self.width.dot(&self.width),
+            d.dot(&self.height) / self.height.dot(&self.height),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let o = self.plane.point;
+        Some(Aabb::from_points(&[
+            o,
+            o + self.width,
+            o + self.height,
+            o + self.width + self.height,
+        ]))
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Rectangle
+    }
 }
 
 /// A sphere is a 3-D ball, it has a center point and a radius.
 #[derive(Debug, Clone, Copy)]
 pub struct Sphere {
     pub center: Vec3,
-    pub radius: f32,
+    pub radius: Float,
     pub material: Material,
 }
 
@@ -147,153 +386,1523 @@ impl RayCollision for Sphere {
             dist_orig_proj - dist_proj_intersect1,
             dist_orig_proj + dist_proj_intersect1,
         ) {
-            (o_i1, _) if o_i1 > 0. => HitPoint::Point(ray.walk_dir(o_i1)),
+            (o_i1, _) if ray.in_range(o_i1) => HitPoint::Point(ray.walk_dir(o_i1)),
             // Origin is inside the sphere
             // Assuming light can move thorugh sphere we'll see the other intersection point
-            (_, o_i2) if o_i2 > 0. => HitPoint::Point(ray.walk_dir(o_i2)),
+            (_, o_i2) if ray.in_range(o_i2) => HitPoint::Point(ray.walk_dir(o_i2)),
             _ => HitPoint::None,
         }
     }
 
-    fn collision_normal(&self, hit_point: Vec3) -> Vec3 {
+    fn collision_normal(&self, hit_point: Vec3, _time: Float) -> Vec3 {
         (hit_point - self.center).normalized()
     }
 
-    fn collision_material(&self, hit_point: Vec3) -> Material {
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
         self.material
     }
+
+    /// Longitude and latitude of the point, the poles are on the y axis.
+    fn collision_uv(&self, hit_point: Vec3, _time: Float) -> (Float, Float) {
+        let d = (hit_point - self.center).normalized();
+        (
+            0.5 + d.z().atan2(d.x()) / (2. * PI),
+            0.5 + d.y().clamp(-1., 1.).asin() / PI,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::new((self.radius, self.radius, self.radius));
+        Some(Aabb {
+            min: self.center - r,
+            max: self.center + r,
+        })
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        let o = ray.origin - self.center;
+        let mut ts = solve_quadratic(
+            ray.direction.dot(&ray.direction),
+            2. * o.dot(&ray.direction),
+            o.dot(&o) - self.radius.powf(2.),
+        );
+        ts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Span::from_crossings(&ts)
+    }
+
+    fn packet_intersect(&self, packet: &RayPacket) -> Lanes {
+        packet::sphere(packet, self.center, self.radius)
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Sphere
+    }
 }
 
-#[derive(Clone, Copy)]
-pub struct LightSource {
-    pub position: Vec3,
-    pub intensity: f32,
+/// Samples per keyframe segment when we look for the box a moving object sweeps through
+const MOTION_BOX_STEPS: usize = 16;
+
+/// A sphere with a center that moves over time. Render it with a few samples per pixel and an open shutter to get motion blur.
+#[derive(Debug, Clone)]
+pub struct MovingSphere {
+    pub center: Track<Vec3>,
+    pub radius: Float,
+    pub material: Material,
 }
 
-/// What is the difference between a Vec3 and a Ray? After all Vec3 is a Ray that starts at the origin.
-/// Well Ray is infinite length. That's why the direction can be unit norm. Vec3 length is finite (it's norm).
-#[derive(Clone, Copy)]
-pub struct Ray {
-    pub origin: Vec3,
-    /// Unit norm direction vector
-    pub direction: Vec3,
+impl MovingSphere {
+    pub fn new(center: Track<Vec3>, radius: Float, material: Material) -> Self {
+        Self {
+            center,
+            radius,
+            material,
+        }
+    }
+
+    /// The sphere frozen at `time`
+    fn at(&self, time: Float) -> Sphere {
+        Sphere {
+            center: self.center.sample(time),
+            radius: self.radius,
+            material: self.material,
+        }
+    }
 }
 
-impl Ray {
-    pub fn new(dir: Vec3) -> Self {
+impl RayCollision for MovingSphere {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        self.at(ray.time).ray_intersect(ray)
+    }
+
+    fn collision_normal(&self, hit_point: Vec3, time: Float) -> Vec3 {
+        self.at(time).collision_normal(hit_point, time)
+    }
+
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
+        self.material
+    }
+
+    fn collision_uv(&self, hit_point: Vec3, time: Float) -> (Float, Float) {
+        self.at(time).collision_uv(hit_point, time)
+    }
+
+    /// Box around the sphere all along its track. Between keyframes a spline track may go beyond the keyframes, so we follow the track.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = Vec3::new((self.radius, self.radius, self.radius));
+        Aabb::swept(self.center.sweep(MOTION_BOX_STEPS).map(|center| Aabb {
+            min: center - radius,
+            max: center + radius,
+        }))
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        self.at(ray.time).ray_intervals(ray)
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Sphere
+    }
+}
+
+/// Flat disk, like a coin with no thickness. Defined by its center, the normal of the plane it lies on and a radius.
+#[derive(Debug, Clone, Copy)]
+pub struct Disk {
+    pub center: Vec3,
+    /// Unit norm vector orthogonal to the disk
+    pub normal: Vec3,
+    pub radius: Float,
+    pub material: Material,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: Float, material: Material) -> Self {
         Self {
-            origin: Vec3::orig(),
-            direction: dir.normalized(),
+            center,
+            normal: normal.normalized(),
+            radius,
+            material,
         }
     }
+}
 
-    pub fn set_origin(mut self, origin: Vec3) -> Self {
-        self.origin = origin;
-        self
+impl RayCollision for Disk {
+    /// Same as a rectangle, we hit the plane and check that we are not too far from the center.
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        match disk_crossing(ray, self.center, self.normal, self.radius) {
+            Some(t) if ray.in_range(t) => HitPoint::Point(ray.walk_dir(t)),
+            _ => HitPoint::None,
+        }
     }
 
-    pub fn walk_dir(&self, distance: f32) -> Vec3 {
-        self.origin + self.direction.mult(distance)
+    fn collision_normal(&self, _hit_point: Vec3, _time: Float) -> Vec3 {
+        self.normal
+    }
+
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
+        self.material
+    }
+
+    /// Polar coordinates: `u` is the angle around the center, `v` the distance from the center relative to the radius.
+    fn collision_uv(&self, hit_point: Vec3, _time: Float) -> (Float, Float) {
+        let d = hit_point - self.center;
+        (
+            angle_around(self.normal, d),
+            (d.l2() / self.radius).min(1.),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::around_disk(self.center, self.normal, self.radius))
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Disk
     }
 }
 
-/// Material represents the color and light reflecting properties. (Open the struct page to see images)
-///
-///This is something completely new to me. The wikipedia article is interesting [Phong Reflection Model](https://en.wikipedia.org/wiki/Phong_reflection_model).
-///Particularly this image <p>![](https://upload.wikimedia.org/wikipedia/commons/thumb/0/01/Blinn_Vectors.svg/330px-Blinn_Vectors.svg.png)</p>
-///Another image that provides good explanation about diffused and specular reflection is this: <p> ![](https://upload.wikimedia.org/wikipedia/commons/thumb/b/bd/Lambert2.gif/330px-Lambert2.gif)</p>
-#[derive(Clone, Copy, Debug)]
-pub struct Material {
-    color: (f32, f32, f32),
-    pub pixel: image::Rgb<u8>,
-    /// How strong this material reflects direct light
-    pub specular_exponent: f32,
-    /// How refracting is the material
-    pub refraction_index: f32,
-    /// Whiteness of an object
-    // albedo: (f32, f32),
-    diff_mixing_coef: f32,
-    spec_mixing_coef: f32,
-    reflection_mixing_coef: f32,
-    refraction_mixing_coef: f32,
+/// Flat triangle with corners `a`, `b` and `c`, the building block of meshes.
+/// The normal follows the [right hand rule](https://en.wikipedia.org/wiki/Right-hand_rule): if the corners go around counter clockwise, the normal points at you.
+#[derive(Debug, Clone, Copy)]
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+    pub material: Material,
 }
 
-type MaterialMixingWeights = (f32, f32, f32, f32);
+impl Triangle {
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, material: Material) -> Self {
+        Self { a, b, c, material }
+    }
 
-impl Material {
-    fn _to_pixel(color: (f32, f32, f32)) -> image::Rgb<u8> {
-        let (r, g, b) = color;
-        image::Rgb([(255. * r) as u8, (255. * g) as u8, (255. * b) as u8])
+    /// Every point of the triangle is `a + u * (b - a) + v * (c - a)` with `u, v >= 0` and `u + v <= 1`. These are the `(u, v)` of `p` (which must be on the triangle's plane).
+    fn barycentric(&self, p: Vec3) -> (Float, Float) {
+        let (e1, e2, d) = (self.b - self.a, self.c - self.a, p - self.a);
+        let (d11, d12, d22) = (e1.dot(&e1), e1.dot(&e2), e2.dot(&e2));
+        let (d1, d2) = (d.dot(&e1), d.dot(&e2));
+        let denominator = d11 * d22 - d12 * d12;
+        (
+            (d22 * d1 - d12 * d2) / denominator,
+            (d11 * d2 - d12 * d1) / denominator,
+        )
     }
+}
 
-    pub fn new(
-        color: (f32, f32, f32),
-        weights: MaterialMixingWeights,
-        // albedo: (f32, f32),
-        specular_exponent: f32,
-        refraction_index: f32,
-        // reflection_mixing_coef: f32,
-        // refraction_mixing_coef: f32,
-    ) -> Self {
-        let pixel = Self::_to_pixel(color);
-        let (diff_mixing_coef, spec_mixing_coef, reflection_mixing_coef, refraction_mixing_coef) =
-            weights;
-        Self {
-            color,
-            pixel,
-            specular_exponent,
-            refraction_index,
-            diff_mixing_coef,
-            spec_mixing_coef,
-            reflection_mixing_coef,
-            refraction_mixing_coef,
+impl RayCollision for Triangle {
+    /// The [Möller–Trumbore](https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm) algorithm:
+    /// we solve `origin + t * direction = a + u * (b - a) + v * (c - a)` for `t`, `u` and `v` with [Cramer's rule](https://en.wikipedia.org/wiki/Cramer%27s_rule),
+    /// and the ray hits if `(u, v)` is inside the triangle.
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        let (e1, e2) = (self.b - self.a, self.c - self.a);
+        let p = ray.direction.cross(&e2);
+        let det = e1.dot(&p);
+        // The ray is parallel to the triangle
+        if det.abs() < Float::EPSILON {
+            return HitPoint::None;
+        }
+
+        let s = ray.origin - self.a;
+        let u = s.dot(&p) / det;
+        let q = s.cross(&e1);
+        let v = ray.direction.dot(&q) / det;
+        let t = e2.dot(&q) / det;
+        if u >= 0. && v >= 0. && u + v <= 1. && ray.in_range(t) {
+            HitPoint::Point(ray.walk_dir(t))
+        } else {
+            HitPoint::None
         }
     }
 
-    pub fn adjust_light(mut self, diffuse: f32, specular: f32) -> Self {
-        let (r, g, b) = self.color;
-        let diff_albedo = diffuse * self.diff_mixing_coef;
-        let white_shift = specular * self.spec_mixing_coef;
+    fn collision_normal(&self, _hit_point: Vec3, _time: Float) -> Vec3 {
+        (self.b - self.a).cross(&(self.c - self.a)).normalized()
+    }
 
-        self.color = (
-            (r * diff_albedo + white_shift).max(0.).min(1.),
-            (g * diff_albedo + white_shift).max(0.).min(1.),
-            (b * diff_albedo + white_shift).max(0.).min(1.),
-        );
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
+        self.material
+    }
 
-        self.pixel = Self::_to_pixel(self.color);
-        self
+    /// The barycentric coordinates, `a` is at `(0, 0)`, `b` at `(1, 0)` and `c` at `(0, 1)`.
+    fn collision_uv(&self, hit_point: Vec3, _time: Float) -> (Float, Float) {
+        self.barycentric(hit_point)
     }
 
-    fn _mix_materials(mut self, other: Material, coef: f32) -> Self {
-        let (r1, g1, b1) = self.color;
-        let (r2, g2, b2) = other.color;
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(&[self.a, self.b, self.c]))
+    }
 
-        let mixed_color = (
-            (r1 + coef * r2).max(0.).min(1.),
-            (g1 + coef * g2).max(0.).min(1.),
-            (b1 + coef * b2).max(0.).min(1.),
-        );
+    fn packet_intersect(&self, packet: &RayPacket) -> Lanes {
+        packet::triangle(packet, self.a, self.b - self.a, self.c - self.a)
+    }
 
-        self.color = mixed_color;
-        self.pixel = Self::_to_pixel(self.color);
+    fn primitive(&self) -> Primitive {
+        Primitive::Triangle
+    }
+}
+
+/// Cylinder around a line segment. The segment starts at the center of the bottom cap (`base`) and goes `height` units along `axis`.
+/// An uncapped cylinder is just a tube, you can see through its ends.
+#[derive(Debug, Clone, Copy)]
+pub struct Cylinder {
+    /// Center of the bottom cap
+    pub base: Vec3,
+    /// Unit norm direction from the bottom cap to the top cap
+    pub axis: Vec3,
+    pub radius: Float,
+    pub height: Float,
+    pub capped: bool,
+    pub material: Material,
+}
+
+impl Cylinder {
+    /// Capped cylinder between the centers of the two caps.
+    pub fn new(base: Vec3, top: Vec3, radius: Float, material: Material) -> Self {
+        Self {
+            base,
+            axis: (top - base).normalized(),
+            radius,
+            height: (top - base).l2(),
+            capped: true,
+            material,
+        }
+    }
+
+    /// Remove the caps, leaving an open tube.
+    pub fn uncapped(mut self) -> Self {
+        self.capped = false;
         self
     }
 
-    /// Mix two materials color together, by the amount of reflectiveness of the first material.
-    pub fn mix_reflection(self, other: Material) -> Self {
-        self._mix_materials(other, self.reflection_mixing_coef)
+    /// Every distance along the ray where it crosses the cylinder surface, sorted.
+    fn crossings(&self, ray: &Ray) -> Vec<Float> {
+        let o = ray.origin - self.base;
+        let d = ray.direction;
+        let (o_axis, d_axis) = (o.dot(&self.axis), d.dot(&self.axis));
+
+        // Remove the axis component, what's left is a 2-D circle intersection
+        let o_perp = o - self.axis.mult(o_axis);
+        let d_perp = d - self.axis.mult(d_axis);
+
+        let mut ts = solve_quadratic(
+            d_perp.dot(&d_perp),
+            2. * o_perp.dot(&d_perp),
+            o_perp.dot(&o_perp) - self.radius.powf(2.),
+        )
+        .into_iter()
+        .filter(|t| (0. ..=self.height).contains(&(o_axis + t * d_axis)))
+        .collect::<Vec<Float>>();
+
+        if self.capped {
+            let top = self.base + self.axis.mult(self.height);
+            ts.extend(disk_crossing(ray, self.base, self.axis, self.radius));
+            ts.extend(disk_crossing(ray, top, self.axis, self.radius));
+        }
+
+        ts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        ts
     }
+}
 
-    /// Mix two materials color together, by the amount of refraction of the first material.
-    pub fn mix_refraction(self, other: Material) -> Self {
-        self._mix_materials(other, self.refraction_mixing_coef)
+impl RayCollision for Cylinder {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        nearest_ahead(ray, &self.crossings(ray))
+    }
+
+    fn collision_normal(&self, hit_point: Vec3, _time: Float) -> Vec3 {
+        let d = hit_point - self.base;
+        let h = d.dot(&self.axis);
+        let radial = d - self.axis.mult(h);
+        let inside_rim = radial.l2() < self.radius - SURFACE_EPSILON;
+
+        match h {
+            _ if self.capped && inside_rim && h < SURFACE_EPSILON => self.axis.mult(-1.),
+            _ if self.capped && inside_rim && h > self.height - SURFACE_EPSILON => self.axis,
+            _ => radial.normalized(),
+        }
+    }
+
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
+        self.material
+    }
+
+    /// `u` is the angle around the axis. On the side `v` is the relative height, on the caps it's the relative distance from the axis.
+    fn collision_uv(&self, hit_point: Vec3, _time: Float) -> (Float, Float) {
+        let d = hit_point - self.base;
+        let h = d.dot(&self.axis);
+        let radial = d - self.axis.mult(h);
+        let u = angle_around(self.axis, radial);
+
+        if radial.l2() < self.radius - SURFACE_EPSILON {
+            (u, radial.l2() / self.radius)
+        } else {
+            (u, (h / self.height).clamp(0., 1.))
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let top = self.base + self.axis.mult(self.height);
+        let bottom_cap = Aabb::around_disk(self.base, self.axis, self.radius);
+        Some(bottom_cap.union(&Aabb::around_disk(top, self.axis, self.radius)))
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        if self.capped {
+            Span::from_crossings(&self.crossings(ray))
+        } else {
+            vec![]
+        }
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Cylinder
     }
 }
 
-impl Default for Material {
-    fn default() -> Self {
-        let weights: MaterialMixingWeights = (1.0, 0.0, 0.0, 0.);
-        Self::new((0.2, 0.7, 0.8), weights, 1.0, 1.0)
+/// Cone with a round base. The apex is `height` units from the base center along `axis`.
+#[derive(Debug, Clone, Copy)]
+pub struct Cone {
+    /// Center of the base
+    pub base: Vec3,
+    /// Unit norm direction from the base to the apex
+    pub axis: Vec3,
+    /// Radius of the base
+    pub radius: Float,
+    pub height: Float,
+    pub capped: bool,
+    pub material: Material,
+}
+
+impl Cone {
+    /// Capped cone from the center of its base to the apex.
+    pub fn new(base: Vec3, apex: Vec3, radius: Float, material: Material) -> Self {
+        Self {
+            base,
+            axis: (apex - base).normalized(),
+            radius,
+            height: (apex - base).l2(),
+            capped: true,
+            material,
+        }
+    }
+
+    /// Remove the base cap.
+    pub fn uncapped(mut self) -> Self {
+        self.capped = false;
+        self
+    }
+
+    fn apex(&self) -> Vec3 {
+        self.base + self.axis.mult(self.height)
+    }
+
+    /// Every distance along the ray where it crosses the cone surface, sorted.
+    ///
+    /// Measured from the apex, a point `q` is on the (infinite double) cone if its distance from the axis is `k` times its height,
+    /// where `k = radius / height`. Squaring both sides gives a quadratic in the ray distance.
+    fn crossings(&self, ray: &Ray) -> Vec<Float> {
+        let k2 = 1. + (self.radius / self.height).powf(2.);
+        let o = ray.origin - self.apex();
+        let d = ray.direction;
+        let (o_axis, d_axis) = (o.dot(&self.axis), d.dot(&self.axis));
+
+        let mut ts = solve_quadratic(
+            d.dot(&d) - k2 * d_axis.powf(2.),
+            2. * (o.dot(&d) - k2 * o_axis * d_axis),
+            o.dot(&o) - k2 * o_axis.powf(2.),
+        )
+        .into_iter()
+        // Keep only the half of the double cone that is between the apex and the base
+        .filter(|t| (-self.height..=0.).contains(&(o_axis + t * d_axis)))
+        .collect::<Vec<Float>>();
+
+        if self.capped {
+            ts.extend(disk_crossing(ray, self.base, self.axis, self.radius));
+        }
+
+        ts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        ts
+    }
+}
+
+impl RayCollision for Cone {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        nearest_ahead(ray, &self.crossings(ray))
+    }
+
+    /// The gradient of `|q_perp|^2 - k^2 * h^2`, where `h` is the (negative) height measured from the apex.
+    fn collision_normal(&self, hit_point: Vec3, _time: Float) -> Vec3 {
+        let q = hit_point - self.apex();
+        let h = q.dot(&self.axis);
+        let radial = q - self.axis.mult(h);
+
+        let on_base = h < -self.height + SURFACE_EPSILON
+            && radial.l2() < self.radius - SURFACE_EPSILON;
+        if self.capped && on_base {
+            return self.axis.mult(-1.);
+        }
+
+        let k2 = (self.radius / self.height).powf(2.);
+        (radial - self.axis.mult(k2 * h)).normalized()
+    }
+
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
+        self.material
+    }
+
+    /// `u` is the angle around the axis, `v` is the relative height (`0` on the base).
+    fn collision_uv(&self, hit_point: Vec3, _time: Float) -> (Float, Float) {
+        let d = hit_point - self.base;
+        let h = d.dot(&self.axis);
+        let radial = d - self.axis.mult(h);
+        (
+            angle_around(self.axis, radial),
+            (h / self.height).clamp(0., 1.),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let base = Aabb::around_disk(self.base, self.axis, self.radius);
+        Some(base.union(&Aabb::from_points(&[self.apex()])))
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        if self.capped {
+            Span::from_crossings(&self.crossings(ray))
+        } else {
+            vec![]
+        }
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Cone
+    }
+}
+
+/// A donut. A circle with radius `minor_radius` swept around a circle with radius `major_radius`.
+#[derive(Debug, Clone, Copy)]
+pub struct Torus {
+    pub center: Vec3,
+    /// Unit norm axis of symmetry, the donut hole is along this axis
+    pub axis: Vec3,
+    /// Distance from the center to the middle of the tube
+    pub major_radius: Float,
+    /// Radius of the tube
+    pub minor_radius: Float,
+    pub material: Material,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        axis: Vec3,
+        major_radius: Float,
+        minor_radius: Float,
+        material: Material,
+    ) -> Self {
+        Self {
+            center,
+            axis: axis.normalized(),
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+
+    /// Every distance along the ray where it crosses the torus surface, sorted.
+    ///
+    /// A point `p` (relative to the center) is on the torus if `(|p|^2 - R^2 - r^2)^2 = 4R^2 (r^2 - h^2)`, where `h` is the height of `p` along the axis.
+    /// Plugging in the ray gives a 4th degree polynomial, we solve it in `f64` since the coefficients get big quickly.
+    // Always in `f64`, which is the same type when we build with the `f64` feature
+    #[cfg_attr(feature = "f64", allow(clippy::useless_conversion, clippy::unnecessary_cast))]
+    fn crossings(&self, ray: &Ray) -> Vec<Float> {
+        // Start from the point on the ray closest to the center, this keeps the polynomial coefficients small
+        let shift = (self.center - ray.origin).dot(&ray.direction);
+        let o = ray.walk_dir(shift) - self.center;
+        let d = ray.direction;
+
+        let r2 = f64::from(self.major_radius).powi(2);
+        let m2 = f64::from(self.minor_radius).powi(2);
+        let (o_axis, d_axis) = (f64::from(o.dot(&self.axis)), f64::from(d.dot(&self.axis)));
+        let e = f64::from(o.dot(&o)) - r2 - m2;
+        let f = f64::from(o.dot(&d));
+
+        let mut ts = solve_quartic([
+            e * e - 4. * r2 * (m2 - o_axis * o_axis),
+            4. * e * f + 8. * r2 * o_axis * d_axis,
+            4. * f * f + 2. * e + 4. * r2 * d_axis * d_axis,
+            4. * f,
+            1.,
+        ])
+        .into_iter()
+        .map(|t| t as Float + shift)
+        .collect::<Vec<Float>>();
+
+        ts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        ts
+    }
+}
+
+impl RayCollision for Torus {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        nearest_ahead(ray, &self.crossings(ray))
+    }
+
+    /// Points away from the closest point on the tube's center circle.
+    fn collision_normal(&self, hit_point: Vec3, _time: Float) -> Vec3 {
+        let q = hit_point - self.center;
+        let in_plane = q - self.axis.mult(q.dot(&self.axis));
+        let ring = in_plane.normalized().mult(self.major_radius);
+        (q - ring).normalized()
+    }
+
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
+        self.material
+    }
+
+    /// `u` is the angle around the axis, `v` is the angle around the tube.
+    fn collision_uv(&self, hit_point: Vec3, _time: Float) -> (Float, Float) {
+        let q = hit_point - self.center;
+        let h = q.dot(&self.axis);
+        let in_plane = q - self.axis.mult(h);
+        (
+            angle_around(self.axis, in_plane),
+            0.5 + h.atan2(in_plane.l2() - self.major_radius) / (2. * PI),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let outer = Aabb::around_disk(self.center, self.axis, self.major_radius);
+        Some(outer.expand(self.minor_radius))
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        Span::from_crossings(&self.crossings(ray))
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Torus
+    }
+}
+
+/// An object placed in the world through an affine transform. The wrapped object lives in its own (object space) coordinates,
+/// e.g. a unit sphere at the origin, and the transform scales, rotates and moves it into the world.
+///
+/// Instead of transforming the object we transform the ray into object space, intersect there and bring the hit point back.
+/// Wrap an `Arc` to place the same geometry many times without copying it.
+pub struct Transformed<T: RayCollision> {
+    pub object: T,
+    /// Object space to world space
+    pub transform: Transform,
+}
+
+impl<T: RayCollision> Transformed<T> {
+    pub fn new(object: T, transform: Transform) -> Self {
+        Self { object, transform }
+    }
+
+    fn to_object(&self, point: Vec3) -> Vec3 {
+        self.transform.inverse().point(point)
+    }
+
+    /// The ray in object space. Scaling changes the length of the direction, so along with the ray we return how many object space units
+    /// one world space unit becomes. The range of the ray scales the same way.
+    fn local_ray(&self, ray: &Ray) -> (Ray, Float) {
+        let to_object = self.transform.inverse();
+        let local_dir = to_object.vector(ray.direction);
+        let scale = local_dir.l2();
+        let local_ray = Ray::new(local_dir)
+            .set_origin(to_object.point(ray.origin))
+            .set_time(ray.time)
+            .set_t_min(ray.t_min * scale)
+            .set_t_max(ray.t_max * scale);
+        (local_ray, scale)
+    }
+}
+
+impl<T: RayCollision> RayCollision for Transformed<T> {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        let (local_ray, _) = self.local_ray(ray);
+
+        match self.object.ray_intersect(&local_ray) {
+            HitPoint::Point(p) => HitPoint::Point(self.transform.point(p)),
+            HitPoint::None => HitPoint::None,
+        }
+    }
+
+    fn collision_normal(&self, hit_point: Vec3, time: Float) -> Vec3 {
+        let local_normal = self.object.collision_normal(self.to_object(hit_point), time);
+        self.transform.normal(local_normal).normalized()
+    }
+
+    fn collision_material(&self, hit_point: Vec3, time: Float) -> Material {
+        self.object.collision_material(self.to_object(hit_point), time)
+    }
+
+    fn collision_uv(&self, hit_point: Vec3, time: Float) -> (Float, Float) {
+        self.object.collision_uv(self.to_object(hit_point), time)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.object.bounding_box()?.transformed(&self.transform))
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        let (local_ray, scale) = self.local_ray(ray);
+        self.object
+            .ray_intervals(&local_ray)
+            .into_iter()
+            .map(|s| Span {
+                enter: s.enter / scale,
+                exit: s.exit / scale,
+            })
+            .collect()
+    }
+
+    fn sidedness(&self) -> Sidedness {
+        self.object.sidedness()
+    }
+
+    fn primitive(&self) -> Primitive {
+        self.object.primitive()
+    }
+}
+
+/// A transformed object that moves. The transform is interpolated between keyframes, and each ray sees the object where it is at the ray's time.
+pub struct MotionTransformed<T: RayCollision> {
+    pub object: T,
+    /// Object space to world space over time
+    pub transform: Track<Transform>,
+}
+
+impl<T: RayCollision> MotionTransformed<T> {
+    pub fn new(object: T, transform: Track<Transform>) -> Self {
+        Self { object, transform }
+    }
+
+    /// The object frozen at `time`
+    fn at(&self, time: Float) -> Transformed<&T> {
+        Transformed::new(&self.object, self.transform.sample(time))
+    }
+}
+
+impl<T: RayCollision> RayCollision for MotionTransformed<T> {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        self.at(ray.time).ray_intersect(ray)
+    }
+
+    fn collision_normal(&self, hit_point: Vec3, time: Float) -> Vec3 {
+        self.at(time).collision_normal(hit_point, time)
+    }
+
+    fn collision_material(&self, hit_point: Vec3, time: Float) -> Material {
+        self.at(time).collision_material(hit_point, time)
+    }
+
+    fn collision_uv(&self, hit_point: Vec3, time: Float) -> (Float, Float) {
+        self.at(time).collision_uv(hit_point, time)
+    }
+
+    /// Box around the object all along its track. A turning object moves on arcs between keyframes, so we follow the track.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let object_box = self.object.bounding_box()?;
+        Aabb::swept(
+            self.transform
+                .sweep(MOTION_BOX_STEPS)
+                .map(|t| object_box.transformed(&t)),
+        )
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        self.at(ray.time).ray_intervals(ray)
+    }
+
+    fn sidedness(&self) -> Sidedness {
+        self.object.sidedness()
+    }
+
+    fn primitive(&self) -> Primitive {
+        self.object.primitive()
+    }
+}
+
+/// Shared objects collide like the object they point to, this is what makes instancing cheap.
+impl<T: RayCollision + ?Sized> RayCollision for Arc<T> {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        (**self).ray_intersect(ray)
+    }
+
+    fn collision_normal(&self, hit_point: Vec3, time: Float) -> Vec3 {
+        (**self).collision_normal(hit_point, time)
+    }
+
+    fn collision_material(&self, hit_point: Vec3, time: Float) -> Material {
+        (**self).collision_material(hit_point, time)
+    }
+
+    fn collision_uv(&self, hit_point: Vec3, time: Float) -> (Float, Float) {
+        (**self).collision_uv(hit_point, time)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        (**self).ray_intervals(ray)
+    }
+
+    fn sidedness(&self) -> Sidedness {
+        (**self).sidedness()
+    }
+
+    fn packet_intersect(&self, packet: &RayPacket) -> Lanes {
+        (**self).packet_intersect(packet)
+    }
+
+    fn primitive(&self) -> Primitive {
+        (**self).primitive()
+    }
+}
+
+/// Borrowed objects collide like the object they point to.
+impl<T: RayCollision + ?Sized> RayCollision for &T {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        (**self).ray_intersect(ray)
+    }
+
+    fn collision_normal(&self, hit_point: Vec3, time: Float) -> Vec3 {
+        (**self).collision_normal(hit_point, time)
+    }
+
+    fn collision_material(&self, hit_point: Vec3, time: Float) -> Material {
+        (**self).collision_material(hit_point, time)
+    }
+
+    fn collision_uv(&self, hit_point: Vec3, time: Float) -> (Float, Float) {
+        (**self).collision_uv(hit_point, time)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        (**self).ray_intervals(ray)
+    }
+
+    fn sidedness(&self) -> Sidedness {
+        (**self).sidedness()
+    }
+
+    fn packet_intersect(&self, packet: &RayPacket) -> Lanes {
+        (**self).packet_intersect(packet)
+    }
+
+    fn primitive(&self) -> Primitive {
+        (**self).primitive()
+    }
+}
+
+/// Override the sidedness of an object, e.g. make a floor plane invisible from below.
+pub struct Sided<T: RayCollision> {
+    pub object: T,
+    pub sidedness: Sidedness,
+}
+
+impl<T: RayCollision> Sided<T> {
+    pub fn one_sided(object: T) -> Self {
+        Self {
+            object,
+            sidedness: Sidedness::OneSided,
+        }
+    }
+
+    pub fn two_sided(object: T) -> Self {
+        Self {
+            object,
+            sidedness: Sidedness::TwoSided,
+        }
+    }
+}
+
+impl<T: RayCollision> RayCollision for Sided<T> {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        self.object.ray_intersect(ray)
+    }
+
+    fn collision_normal(&self, hit_point: Vec3, time: Float) -> Vec3 {
+        self.object.collision_normal(hit_point, time)
+    }
+
+    fn collision_material(&self, hit_point: Vec3, time: Float) -> Material {
+        self.object.collision_material(hit_point, time)
+    }
+
+    fn collision_uv(&self, hit_point: Vec3, time: Float) -> (Float, Float) {
+        self.object.collision_uv(hit_point, time)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        self.object.ray_intervals(ray)
+    }
+
+    fn sidedness(&self) -> Sidedness {
+        self.sidedness
+    }
+
+    fn packet_intersect(&self, packet: &RayPacket) -> Lanes {
+        self.object.packet_intersect(packet)
+    }
+
+    fn primitive(&self) -> Primitive {
+        self.object.primitive()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LightSource {
+    pub position: Vec3,
+    pub intensity: Float,
+}
+
+/// What is the difference between a Vec3 and a Ray? After all Vec3 is a Ray that starts at the origin.
+/// Well Ray is infinite length. That's why the direction can be unit norm. Vec3 length is finite (it's norm).
+#[derive(Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// Unit norm direction vector
+    pub direction: Vec3,
+    /// When the ray was shot. Moving objects are intersected where they are at this time
+    pub time: Float,
+    /// Only hits further than this along the ray count
+    pub t_min: Float,
+    /// Only hits closer than this count, a shadow ray stops at the light
+    pub t_max: Float,
+}
+
+impl Ray {
+    pub fn new(dir: Vec3) -> Self {
+        Self {
+            origin: Vec3::orig(),
+            direction: dir.normalized(),
+            time: 0.,
+            t_min: 0.,
+            t_max: Float::INFINITY,
+        }
+    }
+
+    /// A ray going in `direction` from the point `p` on a surface with the given `normal`. The origin is moved off the surface, see [`offset_ray_origin`].
+    /// This is how reflection, refraction and shadow rays start.
+    pub fn leaving_surface(p: Vec3, normal: Vec3, direction: Vec3) -> Self {
+        Ray::new(direction).set_origin(offset_ray_origin(p, normal, direction))
+    }
+
+    pub fn set_origin(mut self, origin: Vec3) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn set_time(mut self, time: Float) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn set_t_min(mut self, t_min: Float) -> Self {
+        self.t_min = t_min;
+        self
+    }
+
+    pub fn set_t_max(mut self, t_max: Float) -> Self {
+        self.t_max = t_max;
+        self
+    }
+
+    /// Does a hit at distance `t` count?
+    pub fn in_range(&self, t: Float) -> bool {
+        t > self.t_min && t < self.t_max
+    }
+
+    pub fn walk_dir(&self, distance: Float) -> Vec3 {
+        self.origin + self.direction.mult(distance)
+    }
+}
+
+/// Material represents the color and light reflecting properties. (Open the struct page to see images)
+///
+///This is something completely new to me. The wikipedia article is interesting [Phong Reflection Model](https://en.wikipedia.org/wiki/Phong_reflection_model).
+///Particularly this image <p>![](https://upload.wikimedia.org/wikipedia/commons/thumb/0/01/Blinn_Vectors.svg/330px-Blinn_Vectors.svg.png)</p>
+///Another image that provides good explanation about diffused and specular reflection is this: <p> ![](https://upload.wikimedia.org/wikipedia/commons/thumb/b/bd/Lambert2.gif/330px-Lambert2.gif)</p>
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    color: (Float, Float, Float),
+    pub pixel: image::Rgb<u8>,
+    /// How strong this material reflects direct light
+    pub specular_exponent: Float,
+    /// How refracting is the material
+    pub refraction_index: Float,
+    /// Whiteness of an object
+    // albedo: (Float, Float),
+    diff_mixing_coef: Float,
+    spec_mixing_coef: Float,
+    reflection_mixing_coef: Float,
+    refraction_mixing_coef: Float,
+}
+
+type MaterialMixingWeights = (Float, Float, Float, Float);
+
+impl Material {
+    pub fn to_pixel(color: (Float, Float, Float)) -> image::Rgb<u8> {
+        let (r, g, b) = color;
+        image::Rgb([(255. * r) as u8, (255. * g) as u8, (255. * b) as u8])
+    }
+
+    pub fn new(
+        color: (Float, Float, Float),
+        weights: MaterialMixingWeights,
+        // albedo: (Float, Float),
+        specular_exponent: Float,
+        refraction_index: Float,
+        // reflection_mixing_coef: Float,
+        // refraction_mixing_coef: Float,
+    ) -> Self {
+        let pixel = Self::to_pixel(color);
+        let (diff_mixing_coef, spec_mixing_coef, reflection_mixing_coef, refraction_mixing_coef) =
+            weights;
+        Self {
+            color,
+            pixel,
+            specular_exponent,
+            refraction_index,
+            diff_mixing_coef,
+            spec_mixing_coef,
+            reflection_mixing_coef,
+            refraction_mixing_coef,
+        }
+    }
+
+    pub fn color(&self) -> (Float, Float, Float) {
+        self.color
+    }
+
+    pub fn adjust_light(mut self, diffuse: Float, specular: Float) -> Self {
+        let (r, g, b) = self.color;
+        let diff_albedo = diffuse * self.diff_mixing_coef;
+        let white_shift = specular * self.spec_mixing_coef;
+
+        self.color = (
+            (r * diff_albedo + white_shift).clamp(0., 1.),
+            (g * diff_albedo + white_shift).clamp(0., 1.),
+            (b * diff_albedo + white_shift).clamp(0., 1.),
+        );
+
+        self.pixel = Self::to_pixel(self.color);
+        self
+    }
+
+    fn _mix_materials(mut self, other: Material, coef: Float) -> Self {
+        let (r1, g1, b1) = self.color;
+        let (r2, g2, b2) = other.color;
+
+        let mixed_color = (
+            (r1 + coef * r2).clamp(0., 1.),
+            (g1 + coef * g2).clamp(0., 1.),
+            (b1 + coef * b2).clamp(0., 1.),
+        );
+
+        self.color = mixed_color;
+        self.pixel = Self::to_pixel(self.color);
+        self
+    }
+
+    /// Mix two materials color together, by the amount of reflectiveness of the first material.
+    pub fn mix_reflection(self, other: Material) -> Self {
+        self._mix_materials(other, self.reflection_mixing_coef)
+    }
+
+    /// Mix two materials color together, by the amount of refraction of the first material.
+    pub fn mix_refraction(self, other: Material) -> Self {
+        self._mix_materials(other, self.refraction_mixing_coef)
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        let weights: MaterialMixingWeights = (1.0, 0.0, 0.0, 0.);
+        Self::new((0.2, 0.7, 0.8), weights, 1.0, 1.0)
+    }
+}
+
+/// Where a ray leaving the surface at `p` should start.
+///
+/// The hit point we computed is only close to the surface, it can end up just below it and the new ray hits the surface it starts from (shadow acne).
+/// [@ssloy moves the point](https://github.com/ssloy/tinyraytracer/wiki/Part-1:-understandable-raytracing#step-6-shadows) a fixed distance along the normal.
+/// That distance is too small far from the origin, where floats are far apart and the rounding errors are large, and too big for small objects, where it jumps over nearby surfaces and light leaks through.
+///
+/// We use [A Fast and Robust Method for Avoiding Self-Intersection](https://link.springer.com/chapter/10.1007/978-1-4842-4427-2_6) from Ray Tracing Gems:
+/// every coordinate moves a fixed number of [ulps](https://en.wikipedia.org/wiki/Unit_in_the_last_place) along the normal, so the offset grows with the coordinate like the rounding error does.
+/// Ulps get tiny near the origin, there we move a small fixed distance instead.
+pub fn offset_ray_origin(p: Vec3, normal: Vec3, direction: Vec3) -> Vec3 {
+    // Move to the side of the surface the ray goes to
+    let n = if normal.dot(&direction) < 0. {
+        -normal
+    } else {
+        normal
+    };
+
+    let offset = |p: Float, n: Float| {
+        if p.abs() < OFFSET_ORIGIN {
+            return p + OFFSET_FLOAT_SCALE * n;
+        }
+        // The bits of a float count ulps away from zero, so a negative coordinate moves the other way
+        let ulps = (OFFSET_INT_SCALE * n) as i64;
+        let ulps = if p < 0. { -ulps } else { ulps };
+        Float::from_bits((p.to_bits() as i64 + ulps) as _)
+    };
+    Vec3::new((offset(p.x(), n.x()), offset(p.y(), n.y()), offset(p.z(), n.z())))
+}
+
+/// Pick the closest intersection in front of the ray origin, within the ray's range. The crossings are sorted.
+pub(crate) fn nearest_ahead(ray: &Ray, crossings: &[Float]) -> HitPoint {
+    match crossings.iter().find(|&&t| ray.in_range(t)) {
+        Some(&t) => HitPoint::Point(ray.walk_dir(t)),
+        None => HitPoint::None,
+    }
+}
+
+/// Distance along the ray to a disk, `None` if the ray is parallel to the disk or misses it.
+fn disk_crossing(ray: &Ray, center: Vec3, normal: Vec3, radius: Float) -> Option<Float> {
+    let cos_dir_norm = normal.dot(&ray.direction);
+    if cos_dir_norm.abs() < Float::EPSILON {
+        return None;
+    }
+    let t = normal.dot(&(center - ray.origin)) / cos_dir_norm;
+    if (ray.walk_dir(t) - center).l2() <= radius {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// Two unit vectors that together with `normal` make an orthonormal basis.
+/// Texture coordinates are measured along these, so we want the same frame for the same normal, and one that doesn't turn when the normal moves a bit.
+/// [`Onb::from_normal`] flips where the normal's `z` changes sign, which is right at the walls of most scenes, so we don't use it here.
+fn tangent_frame(normal: Vec3) -> (Vec3, Vec3) {
+    let n = normal.normalized();
+    // Cross with the axis that is least aligned with the normal, so the result is never too short
+    let helper = if n.x().abs() < 0.9 {
+        Vec3::new((1., 0., 0.))
+    } else {
+        Vec3::new((0., 1., 0.))
+    };
+    let e1 = helper.cross(&n).normalized();
+    (e1, n.cross(&e1))
+}
+
+/// Angle of `v` around `axis`, scaled to `[0, 1]`.
+fn angle_around(axis: Vec3, v: Vec3) -> Float {
+    let (e1, e2) = tangent_frame(axis);
+    0.5 + v.dot(&e2).atan2(v.dot(&e1)) / (2. * PI)
+}
+
+/// Real roots of `a*t^2 + b*t + c = 0`. We always report both roots, so the crossings of a closed object pair up.
+/// When `a` is 0 one of them is at infinity: a ray parallel to the axis of a cylinder meets the side only "at infinity".
+fn solve_quadratic(a: Float, b: Float, c: Float) -> Vec<Float> {
+    if a == 0. && b == 0. {
+        return vec![];
+    }
+
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return vec![];
+    }
+    // Avoid subtracting two close numbers, see "Numerical Recipes" 5.6
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    // Only happens for a double root at 0
+    if q == 0. {
+        return vec![0., 0.];
+    }
+    vec![q / a, c / q]
+}
+
+const POLY_EPSILON: f64 = 1e-9;
+
+/// Real roots of a monic quadratic `t^2 + p*t + q`
+fn solve_monic_quadratic(p: f64, q: f64) -> Vec<f64> {
+    let half_p = p / 2.;
+    let discriminant = half_p * half_p - q;
+    if discriminant.abs() < POLY_EPSILON {
+        vec![-half_p]
+    } else if discriminant < 0. {
+        vec![]
+    } else {
+        let sqrt_d = discriminant.sqrt();
+        vec![-half_p - sqrt_d, -half_p + sqrt_d]
+    }
+}
+
+/// Real roots of a monic cubic `t^3 + a*t^2 + b*t + c` using [Cardano's method](https://en.wikipedia.org/wiki/Cubic_equation#Cardano's_formula).
+/// The largest root comes first.
+fn solve_monic_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // Substitute t = y - a/3 to get rid of the quadratic term: y^3 + 3p*y + 2q = 0
+    let sq_a = a * a;
+    let p = (-sq_a / 3. + b) / 3.;
+    let q = (2. / 27. * a * sq_a - a * b / 3. + c) / 2.;
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let roots = if discriminant.abs() < POLY_EPSILON {
+        if q.abs() < POLY_EPSILON {
+            vec![0.]
+        } else {
+            let u = (-q).cbrt();
+            vec![2. * u, -u]
+        }
+    } else if discriminant < 0. {
+        // Three real roots, trigonometric form
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1., 1.).acos() / 3.;
+        let t = 2. * (-p).sqrt();
+        let third = std::f64::consts::PI / 3.;
+        vec![
+            t * phi.cos(),
+            -t * (phi + third).cos(),
+            -t * (phi - third).cos(),
+        ]
+    } else {
+        let sqrt_d = discriminant.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+
+    roots.into_iter().map(|y| y - a / 3.).collect()
+}
+
+/// Real roots of `c[4]*t^4 + c[3]*t^3 + c[2]*t^2 + c[1]*t + c[0]` using [Ferrari's method](https://en.wikipedia.org/wiki/Quartic_function#Ferrari's_solution).
+/// The closed form loses precision, so every root gets polished with a couple of Newton steps.
+fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let (a, b, cc, d) = (c[3] / c[4], c[2] / c[4], c[1] / c[4], c[0] / c[4]);
+
+    // Substitute t = y - a/4 to get rid of the cubic term: y^4 + p*y^2 + q*y + r = 0
+    let sq_a = a * a;
+    let p = -3. / 8. * sq_a + b;
+    let q = sq_a * a / 8. - a * b / 2. + cc;
+    let r = -3. / 256. * sq_a * sq_a + sq_a * b / 16. - a * cc / 4. + d;
+
+    let roots = if r.abs() < POLY_EPSILON {
+        // y * (y^3 + p*y + q) = 0
+        let mut roots = solve_monic_cubic(0., p, q);
+        roots.push(0.);
+        roots
+    } else {
+        // Any real root of the resolvent cubic splits the quartic into two quadratics
+        let z = solve_monic_cubic(-p / 2., -r, r * p / 2. - q * q / 8.)[0];
+
+        let u = z * z - r;
+        let v = 2. * z - p;
+        let u = if u.abs() < POLY_EPSILON {
+            0.
+        } else if u > 0. {
+            u.sqrt()
+        } else {
+            return vec![];
+        };
+        let v = if v.abs() < POLY_EPSILON {
+            0.
+        } else if v > 0. {
+            v.sqrt()
+        } else {
+            return vec![];
+        };
+        let v = v.copysign(q);
+
+        let mut roots = solve_monic_quadratic(v, z - u);
+        roots.extend(solve_monic_quadratic(-v, z + u));
+        roots
+    };
+
+    let poly = |t: f64| (((c[4] * t + c[3]) * t + c[2]) * t + c[1]) * t + c[0];
+    let deriv = |t: f64| ((4. * c[4] * t + 3. * c[3]) * t + 2. * c[2]) * t + c[1];
+
+    roots
+        .into_iter()
+        .map(|y| {
+            let mut t = y - a / 4.;
+            for _ in 0..2 {
+                let slope = deriv(t);
+                if slope.abs() > POLY_EPSILON {
+                    t -= poly(t) / slope;
+                }
+            }
+            t
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::Interpolation;
+    use crate::float::consts::PI;
+
+    fn contains(b: &Aabb, p: Vec3) -> bool {
+        (b.min.x()..=b.max.x()).contains(&p.x())
+            && (b.min.y()..=b.max.y()).contains(&p.y())
+            && (b.min.z()..=b.max.z()).contains(&p.z())
+    }
+
+    fn matte() -> Material {
+        Material::new((1., 1., 1.), (1., 0., 0., 0.), 1., 1.)
+    }
+
+    /// Where a ray from `origin` towards `target` first hits the object
+    fn hit(
+        object: &dyn RayCollision,
+        origin: (Float, Float, Float),
+        target: (Float, Float, Float),
+    ) -> Option<Vec3> {
+        let (origin, target) = (Vec3::new(origin), Vec3::new(target));
+        match object.ray_intersect(&Ray::new(target - origin).set_origin(origin)) {
+            HitPoint::Point(p) => Some(p),
+            HitPoint::None => None,
+        }
+    }
+
+    fn assert_close(a: Vec3, b: (Float, Float, Float)) {
+        let b = Vec3::new(b);
+        assert!((a - b).l2() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_near(a: Float, b: Float) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    fn assert_uv(a: (Float, Float), b: (Float, Float)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    /// A spline through three points in a row with a turn in the middle overshoots the last keyframe
+    #[test]
+    fn moving_sphere_box_covers_spline_overshoot() {
+        let center = Track::new(vec![
+            (0., Vec3::new((0., 0., 0.))),
+            (1., Vec3::new((4., 0., 0.))),
+            (2., Vec3::new((4., 4., 0.))),
+        ])
+        .with_interpolation(Interpolation::CatmullRom);
+        let material = Material::new((1., 1., 1.), (1., 0., 0., 0.), 1., 1.);
+        let sphere = MovingSphere::new(center.clone(), 1., material);
+        let b = sphere.bounding_box().unwrap();
+
+        for i in 0..=1000 {
+            let c = center.sample(i as Float / 500.);
+            for offset in [(1., 0., 0.), (-1., 0., 0.), (0., 1., 0.), (0., -1., 0.)] {
+                let p = c + Vec3::new(offset);
+                assert!(contains(&b, p), "{:?} is outside {:?}", p, b);
+            }
+        }
+    }
+
+    /// A quarter turn around the origin swings the corners of the box around the sphere out further than at either keyframe
+    #[test]
+    fn motion_transformed_box_covers_the_arc() {
+        let z = Vec3::new((0., 0., 1.));
+        let cube = Aabb {
+            min: Vec3::new((1., 1., -0.5)),
+            max: Vec3::new((2., 2., 0.5)),
+        };
+        let sphere = Sphere {
+            center: Vec3::new((1.5, 1.5, 0.)),
+            radius: 0.5,
+            material: Material::new((1., 1., 1.), (1., 0., 0., 0.), 1., 1.),
+        };
+        let track = Track::new(vec![
+            (0., Transform::identity()),
+            (1., Transform::rotate(z, PI / 2.)),
+        ]);
+        let object = MotionTransformed::new(sphere, track.clone());
+        let b = object.bounding_box().unwrap();
+
+        for i in 0..=100 {
+            let t = track.sample(i as Float / 100.);
+            let moved = cube.transformed(&t);
+            assert!(
+                contains(&b, moved.min) && contains(&b, moved.max),
+                "{:?} is outside {:?}",
+                moved,
+                b
+            );
+        }
+    }
+
+    /// Tilting a wall a tiny bit either way must not turn its texture around
+    #[test]
+    fn plane_uv_is_continuous_in_the_normal() {
+        let hit_point = Vec3::new((0., 3., -2.));
+        let uv = |normal: (Float, Float, Float)| {
+            Plane {
+                normal: Vec3::new(normal),
+                point: Vec3::orig(),
+            }
+            .collision_uv(hit_point, 0.)
+        };
+        let (straight, tilted_back, tilted_front) = (
+            uv((1., 0., 0.)),
+            uv((1., 0., -1e-6)),
+            uv((1., 0., 1e-6)),
+        );
+        for (a, b) in [(straight, tilted_back), (straight, tilted_front)] {
+            assert!(
+                (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4,
+                "{:?} != {:?}",
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn disk_hit_normal_and_uv() {
+        let disk = Disk::new(
+            Vec3::new((0., 0., -5.)),
+            Vec3::new((0., 0., 2.)),
+            2.,
+            matte(),
+        );
+
+        let p = hit(&disk, (0., 0., 0.), (1., 0., -5.)).unwrap();
+        assert_close(p, (1., 0., -5.));
+        assert_close(disk.collision_normal(p, 0.), (0., 0., 1.));
+        assert_near(disk.collision_uv(p, 0.).1, 0.5);
+        assert!(hit(&disk, (0., 0., 0.), (2.5, 0., -5.)).is_none());
+        // Seen from behind
+        assert!(hit(&disk, (0., 0., -10.), (0., 0., -5.)).is_some());
+
+        // Going half way around the center is half the range of `u`
+        let (u, _) = disk.collision_uv(p, 0.);
+        let (u_opposite, _) = disk.collision_uv(Vec3::new((-1., 0., -5.)), 0.);
+        assert_near((u - u_opposite).abs(), 0.5);
+    }
+
+    #[test]
+    fn triangle_hit_normal_and_uv() {
+        let triangle = Triangle::new(
+            Vec3::new((0., 0., -5.)),
+            Vec3::new((2., 0., -5.)),
+            Vec3::new((0., 2., -5.)),
+            matte(),
+        );
+
+        let p = hit(&triangle, (0., 0., 0.), (0.5, 0.5, -5.)).unwrap();
+        assert_close(p, (0.5, 0.5, -5.));
+        assert_close(triangle.collision_normal(p, 0.), (0., 0., 1.));
+        assert_uv(triangle.collision_uv(p, 0.), (0.25, 0.25));
+        assert_uv(triangle.collision_uv(triangle.b, 0.), (1., 0.));
+        assert_uv(triangle.collision_uv(triangle.c, 0.), (0., 1.));
+        // Inside the square the triangle is half of, but on the wrong side of the diagonal
+        assert!(hit(&triangle, (0., 0., 0.), (1.5, 1.5, -5.)).is_none());
+        // The triangle is behind the ray
+        assert!(hit(&triangle, (0.5, 0.5, -6.), (0.5, 0.5, -10.)).is_none());
+    }
+
+    #[test]
+    fn cylinder_hit_normal_and_uv() {
+        let cylinder = Cylinder::new(
+            Vec3::new((0., -1., -5.)),
+            Vec3::new((0., 1., -5.)),
+            1.,
+            matte(),
+        );
+
+        let side = hit(&cylinder, (0., 0., 0.), (0., 0., -5.)).unwrap();
+        assert_close(side, (0., 0., -4.));
+        assert_close(cylinder.collision_normal(side, 0.), (0., 0., 1.));
+        assert_near(cylinder.collision_uv(side, 0.).1, 0.5);
+
+        let cap = hit(&cylinder, (0.5, 5., -5.), (0.5, 0., -5.)).unwrap();
+        assert_close(cap, (0.5, 1., -5.));
+        assert_close(cylinder.collision_normal(cap, 0.), (0., 1., 0.));
+        assert_near(cylinder.collision_uv(cap, 0.).1, 0.5);
+
+        assert!(hit(&cylinder, (1.5, 0., 0.), (1.5, 0., -5.)).is_none());
+        // Without the caps we look straight through the tube
+        assert!(hit(&cylinder.uncapped(), (0.5, 5., -5.), (0.5, 0., -5.)).is_none());
+    }
+
+    #[test]
+    fn cone_hit_normal_and_uv() {
+        let cone = Cone::new(
+            Vec3::new((0., -1., -5.)),
+            Vec3::new((0., 1., -5.)),
+            1.,
+            matte(),
+        );
+
+        // Half way up the cone is half as wide
+        let side = hit(&cone, (0., 0., 0.), (0., 0., -5.)).unwrap();
+        assert_close(side, (0., 0., -4.5));
+        // The side goes up 2 for every 1 it goes in, the normal leans up 1 for every 2 it points out
+        let l = (5. as Float).sqrt();
+        assert_close(cone.collision_normal(side, 0.), (0., 1. / l, 2. / l));
+        assert_near(cone.collision_uv(side, 0.).1, 0.5);
+
+        let base = hit(&cone, (0.5, -5., -5.), (0.5, 0., -5.)).unwrap();
+        assert_close(base, (0.5, -1., -5.));
+        assert_close(cone.collision_normal(base, 0.), (0., -1., 0.));
+        assert_near(cone.collision_uv(base, 0.).1, 0.);
+
+        // Above the apex
+        assert!(hit(&cone, (0., 1.5, 0.), (0., 1.5, -5.)).is_none());
+    }
+
+    #[test]
+    fn torus_hit_normal_and_uv() {
+        let torus = Torus::new(
+            Vec3::new((0., 0., -10.)),
+            Vec3::new((0., 1., 0.)),
+            2.,
+            0.5,
+            matte(),
+        );
+
+        let outside = hit(&torus, (0., 0., 0.), (0., 0., -10.)).unwrap();
+        assert_close(outside, (0., 0., -7.5));
+        assert_close(torus.collision_normal(outside, 0.), (0., 0., 1.));
+        assert_near(torus.collision_uv(outside, 0.).1, 0.5);
+
+        // On top of the tube we are a quarter of the way around it
+        let top = hit(&torus, (0., 5., -8.), (0., 0., -8.)).unwrap();
+        assert_close(top, (0., 0.5, -8.));
+        assert_close(torus.collision_normal(top, 0.), (0., 1., 0.));
+        assert_near(torus.collision_uv(top, 0.).1, 0.75);
+
+        // Through the hole
+        assert!(hit(&torus, (0., 5., -10.), (0., 0., -10.)).is_none());
+    }
+
+    #[test]
+    fn rectangle_hit_normal_and_uv() {
+        let rectangle = Rectangle2D::new(
+            Vec3::new((-1., -1., -5.)),
+            Vec3::new((0., 0., -5.)),
+            Vec3::new((1., 0., 0.)),
+            matte(),
+        );
+
+        let center = hit(&rectangle, (0., 0., 0.), (0., 0., -5.)).unwrap();
+        assert_close(center, (0., 0., -5.));
+        assert_close(rectangle.collision_normal(center, 0.), (0., 0., 1.));
+        assert_uv(rectangle.collision_uv(center, 0.), (0.5, 0.5));
+
+        let p = hit(&rectangle, (0., 0., 0.), (-0.5, 0.5, -5.)).unwrap();
+        assert_uv(rectangle.collision_uv(p, 0.), (0.25, 0.75));
+
+        assert!(hit(&rectangle, (0., 0., 0.), (1.5, 0., -5.)).is_none());
+        assert!(hit(&rectangle, (0., 0., 0.), (0., -1.5, -5.)).is_none());
     }
 }
@@ -0,0 +1,432 @@
+//! The camera decides which ray of light ends up in which pixel.
+//!
+//! A pinhole camera sends all rays through a single point, so everything is perfectly sharp. Real cameras have a lens with some width (the aperture),
+//! and only things at the focus distance are sharp. A point on the focus plane is hit by rays from every part of the lens that all meet at that point,
+//! anything closer or further away is smeared across the image. This is the [thin lens model](https://www.pbr-book.org/3ed-2018/Camera_Models/Projective_Camera_Models#TheThinLensModelandDepthofField),
+//! to see the blur we shoot many rays per pixel, each one from a different spot on the lens.
+//!
+//! Not every camera is a perspective camera. Technical drawings use parallel rays, fisheye lenses squeeze half the world into a circle
+//! and panoramas map all directions onto a rectangle. All of them implement [`Camera`], so the renderer doesn't care which one it gets.
+
+use crate::blocks::Ray;
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::sampling::{sample_disk, sample_polygon};
+use crate::vectors::{Transform, Vec3};
+
+/// Shape of the lens opening. Out of focus highlights (bokeh) take this shape.
+#[derive(Clone, Copy, Debug)]
+pub enum ApertureShape {
+    Disk,
+    /// Regular polygon, like the opening made by the blades of a real aperture
+    Polygon { blades: u32, rotation: Float },
+}
+
+impl ApertureShape {
+    /// Map a point in the unit square to a point on the aperture (of radius 1)
+    fn sample(&self, u: (Float, Float)) -> (Float, Float) {
+        match *self {
+            ApertureShape::Disk => sample_disk(u),
+            ApertureShape::Polygon { blades, rotation } => sample_polygon(u, blades, rotation),
+        }
+    }
+}
+
+/// Maps points on the image to rays. `(0, 0)` is the top left corner of the image and `(1, 1)` the bottom right one.
+///
+/// Different cameras are different [projections](https://en.wikipedia.org/wiki/3D_projection) of the world on the image.
+pub trait Camera {
+    /// Ray through the image point `(u, v)`, `None` if the camera doesn't see anything there (e.g. outside the circle of a fisheye image).
+    /// `lens` picks the point on the aperture the ray starts from, `(0.5, 0.5)` is the center of the lens. Cameras without a lens ignore it.
+    fn ray(&self, u: Float, v: Float, lens: (Float, Float)) -> Option<Ray>;
+
+    /// When the camera is looking, see [`Shutter`]
+    fn shutter(&self) -> Shutter;
+
+    /// Where the camera is and which way it looks
+    fn frame(&self) -> CameraFrame;
+}
+
+/// The time interval the shutter is open. Every ray gets a time in this interval, so objects that move while the shutter is open get blurred.
+/// The default shutter is open for an instant at time `0`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Shutter {
+    pub open: Float,
+    pub close: Float,
+}
+
+impl Shutter {
+    pub fn new(open: Float, close: Float) -> Self {
+        Self { open, close }
+    }
+
+    /// Time for a sample in `[0, 1]`
+    pub fn time(&self, u: Float) -> Float {
+        self.open + (self.close - self.open) * u
+    }
+}
+
+/// Where the camera is and which way it looks. All three directions are unit norm and orthogonal to each other.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraFrame {
+    pub position: Vec3,
+    /// Direction the camera looks at
+    pub forward: Vec3,
+    /// Direction of the image x axis
+    pub right: Vec3,
+    /// Direction of the image y axis
+    pub up: Vec3,
+}
+
+impl CameraFrame {
+    /// Camera at `position` looking at `target`. `up` doesn't have to be orthogonal to the view direction, it just decides which way is up in the image.
+    pub fn look_at(position: Vec3, target: Vec3, up: Vec3) -> Self {
+        let forward = (target - position).normalized();
+        let right = forward.cross(&up).normalized();
+        let up = right.cross(&forward);
+        Self {
+            position,
+            forward,
+            right,
+            up,
+        }
+    }
+
+    /// From camera coordinates (`x` right, `y` up, `z` forward) to the world, for points as well as directions
+    pub fn transform(&self) -> Transform {
+        Transform::look_at(self.position, self.position + self.forward, self.up)
+    }
+
+    /// Direction from camera coordinates (`x` right, `y` up, `z` forward) to the world
+    fn direction(&self, x: Float, y: Float, z: Float) -> Vec3 {
+        self.right.mult(x) + self.up.mult(y) + self.forward.mult(z)
+    }
+}
+
+/// Perspective projection, far things look smaller. With an aperture it is a thin lens camera, without it is a pinhole camera.
+#[derive(Clone, Copy, Debug)]
+pub struct ThinLensCamera {
+    pub frame: CameraFrame,
+    /// Vertical field of view in radians
+    pub fov: Float,
+    /// Image width divided by image height
+    pub aspect_ratio: Float,
+    /// Radius of the lens, `0` makes this a pinhole camera
+    pub aperture_radius: Float,
+    /// Distance (along the view direction) of the plane that is in focus
+    pub focus_distance: Float,
+    pub aperture_shape: ApertureShape,
+    pub shutter: Shutter,
+}
+
+impl ThinLensCamera {
+    /// Pinhole camera at `position` looking at `target`
+    pub fn new(position: Vec3, target: Vec3, up: Vec3, fov: Float, aspect_ratio: Float) -> Self {
+        let frame = CameraFrame::look_at(position, target, up);
+        Self::from_frame(frame, fov, aspect_ratio)
+    }
+
+    /// Pinhole camera placed by `frame`
+    pub fn from_frame(frame: CameraFrame, fov: Float, aspect_ratio: Float) -> Self {
+        Self {
+            frame,
+            fov,
+            aspect_ratio,
+            aperture_radius: 0.,
+            focus_distance: 1.,
+            aperture_shape: ApertureShape::Disk,
+            shutter: Shutter::default(),
+        }
+    }
+
+    /// Open up the lens, things that are `focus_distance` away stay sharp.
+    pub fn with_aperture(mut self, radius: Float, focus_distance: Float) -> Self {
+        self.aperture_radius = radius;
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    pub fn with_aperture_shape(mut self, shape: ApertureShape) -> Self {
+        self.aperture_shape = shape;
+        self
+    }
+}
+
+impl Camera for ThinLensCamera {
+    fn ray(&self, u: Float, v: Float, lens: (Float, Float)) -> Option<Ray> {
+        let tan_fov = (self.fov / 2.).tan();
+        let x = (2. * u - 1.) * tan_fov * self.aspect_ratio;
+        let y = -(2. * v - 1.) * tan_fov;
+
+        let frame = &self.frame;
+        let dir = frame.direction(x, y, 1.).normalized();
+        if self.aperture_radius == 0. {
+            return Some(Ray::new(dir).set_origin(frame.position));
+        }
+
+        // Where the pinhole ray meets the plane of focus. Every ray through the lens for this pixel meets there as well
+        let focus_point = frame.position + dir.mult(self.focus_distance / dir.dot(&frame.forward));
+
+        let (lx, ly) = self.aperture_shape.sample(lens);
+        let origin = frame.position
+            + frame.direction(lx * self.aperture_radius, ly * self.aperture_radius, 0.);
+
+        Some(Ray::new(focus_point - origin).set_origin(origin))
+    }
+
+    fn shutter(&self) -> Shutter {
+        self.shutter
+    }
+
+    fn frame(&self) -> CameraFrame {
+        self.frame
+    }
+}
+
+/// [Orthographic projection](https://en.wikipedia.org/wiki/Orthographic_projection), all rays are parallel so things keep their size no matter how far they are.
+/// This is what technical drawings use.
+#[derive(Clone, Copy, Debug)]
+pub struct OrthographicCamera {
+    /// The position is the center of the image rectangle the rays start from
+    pub frame: CameraFrame,
+    /// Height of the visible area in world units
+    pub view_height: Float,
+    /// Image width divided by image height
+    pub aspect_ratio: Float,
+    pub shutter: Shutter,
+}
+
+impl OrthographicCamera {
+    pub fn new(frame: CameraFrame, view_height: Float, aspect_ratio: Float) -> Self {
+        Self {
+            frame,
+            view_height,
+            aspect_ratio,
+            shutter: Shutter::default(),
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn ray(&self, u: Float, v: Float, _lens: (Float, Float)) -> Option<Ray> {
+        let half_height = self.view_height / 2.;
+        let x = (2. * u - 1.) * half_height * self.aspect_ratio;
+        let y = -(2. * v - 1.) * half_height;
+
+        let origin = self.frame.position + self.frame.direction(x, y, 0.);
+        Some(Ray::new(self.frame.forward).set_origin(origin))
+    }
+
+    fn shutter(&self) -> Shutter {
+        self.shutter
+    }
+
+    fn frame(&self) -> CameraFrame {
+        self.frame
+    }
+}
+
+/// [Equidistant fisheye](https://en.wikipedia.org/wiki/Fisheye_lens#Mapping_function): the distance from the image center is proportional to the angle from the view direction.
+/// The image is a circle touching the top and bottom of the image, pixels outside of it see nothing.
+#[derive(Clone, Copy, Debug)]
+pub struct FisheyeCamera {
+    pub frame: CameraFrame,
+    /// Field of view across the image circle in radians, can go beyond 180 degrees
+    pub fov: Float,
+    /// Image width divided by image height
+    pub aspect_ratio: Float,
+    pub shutter: Shutter,
+}
+
+impl FisheyeCamera {
+    pub fn new(frame: CameraFrame, fov: Float, aspect_ratio: Float) -> Self {
+        Self {
+            frame,
+            fov,
+            aspect_ratio,
+            shutter: Shutter::default(),
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn ray(&self, u: Float, v: Float, _lens: (Float, Float)) -> Option<Ray> {
+        let x = (2. * u - 1.) * self.aspect_ratio;
+        let y = -(2. * v - 1.);
+        let r = (x * x + y * y).sqrt();
+        if r > 1. {
+            return None;
+        }
+
+        let theta = r * self.fov / 2.;
+        let phi = y.atan2(x);
+        let (sin_t, cos_t) = theta.sin_cos();
+        let dir = self
+            .frame
+            .direction(sin_t * phi.cos(), sin_t * phi.sin(), cos_t);
+        Some(Ray::new(dir).set_origin(self.frame.position))
+    }
+
+    fn shutter(&self) -> Shutter {
+        self.shutter
+    }
+
+    fn frame(&self) -> CameraFrame {
+        self.frame
+    }
+}
+
+/// [Equirectangular](https://en.wikipedia.org/wiki/Equirectangular_projection) 360 degree panorama, the image x axis is the longitude and the y axis is the latitude.
+/// The center of the image is the view direction. Use a 2:1 image to get square pixels, this is the format environment maps and VR viewers expect.
+#[derive(Clone, Copy, Debug)]
+pub struct EquirectangularCamera {
+    pub frame: CameraFrame,
+    pub shutter: Shutter,
+}
+
+impl EquirectangularCamera {
+    pub fn new(frame: CameraFrame) -> Self {
+        Self {
+            frame,
+            shutter: Shutter::default(),
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn ray(&self, u: Float, v: Float, _lens: (Float, Float)) -> Option<Ray> {
+        let longitude = (u - 0.5) * 2. * PI;
+        let latitude = (0.5 - v) * PI;
+        let (sin_lat, cos_lat) = latitude.sin_cos();
+        let dir = self.frame.direction(
+            cos_lat * longitude.sin(),
+            sin_lat,
+            cos_lat * longitude.cos(),
+        );
+        Some(Ray::new(dir).set_origin(self.frame.position))
+    }
+
+    fn shutter(&self) -> Shutter {
+        self.shutter
+    }
+
+    fn frame(&self) -> CameraFrame {
+        self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn frame() -> CameraFrame {
+        CameraFrame::look_at(
+            Vec3::new((1., 2., 3.)),
+            Vec3::new((4., 2., -1.)),
+            Vec3::new((0., 1., 0.)),
+        )
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).l2() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn image_center_looks_forward() {
+        let frame = frame();
+        let cameras: Vec<Box<dyn Camera>> = vec![
+            Box::new(ThinLensCamera::from_frame(frame, 1., 1.5)),
+            Box::new(ThinLensCamera::from_frame(frame, 1., 1.5).with_aperture(0.5, 3.)),
+            Box::new(OrthographicCamera::new(frame, 10., 1.5)),
+            Box::new(FisheyeCamera::new(frame, PI, 1.5)),
+            Box::new(EquirectangularCamera::new(frame)),
+        ];
+        for camera in cameras {
+            let ray = camera.ray(0.5, 0.5, (0.5, 0.5)).unwrap();
+            assert_close(ray.origin, frame.position);
+            assert_close(ray.direction, frame.forward);
+        }
+    }
+
+    #[test]
+    fn fisheye_sees_a_circle() {
+        let camera = FisheyeCamera::new(frame(), PI, 2.);
+        // The circle touches the top and the bottom of the image
+        for (u, v) in [(0.5, 0.), (0.5, 1.), (0.3, 0.5), (0.7, 0.5)] {
+            assert!(camera.ray(u, v, (0.5, 0.5)).is_some(), "({}, {})", u, v);
+        }
+        for (u, v) in [(0., 0.), (1., 1.), (0.2, 0.5), (0.8, 0.5), (0.7, 0.05)] {
+            assert!(camera.ray(u, v, (0.5, 0.5)).is_none(), "({}, {})", u, v);
+        }
+
+        // The edge of a 180 degree fisheye looks sideways
+        let edge = camera.ray(0.75, 0.5, (0.5, 0.5)).unwrap();
+        assert_close(edge.direction, frame().right);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let frame = frame();
+        let camera = OrthographicCamera::new(frame, 10., 1.5);
+        let corner = camera.ray(1., 0., (0.5, 0.5)).unwrap();
+        assert_close(corner.direction, frame.forward);
+        assert_close(
+            corner.origin,
+            frame.position + frame.right.mult(7.5) + frame.up.mult(5.),
+        );
+    }
+
+    #[test]
+    fn equirectangular_sees_everything() {
+        let frame = frame();
+        let camera = EquirectangularCamera::new(frame);
+        let look = |u, v| camera.ray(u, v, (0.5, 0.5)).unwrap().direction;
+        assert_close(look(0.75, 0.5), frame.right);
+        assert_close(look(0.25, 0.5), frame.right.mult(-1.));
+        assert_close(look(0., 0.5), frame.forward.mult(-1.));
+        assert_close(look(0.3, 0.), frame.up);
+        assert_close(look(0.3, 1.), frame.up.mult(-1.));
+    }
+
+    /// Rays from all over the lens meet again on the plane of focus, where the pinhole ray of the same pixel goes through
+    #[test]
+    fn thin_lens_rays_meet_at_the_focus_distance() {
+        let frame = frame();
+        let pinhole = ThinLensCamera::from_frame(frame, 1., 1.5);
+        let mut rng = Rng::new(1);
+        for &shape in &[
+            ApertureShape::Disk,
+            ApertureShape::Polygon {
+                blades: 6,
+                rotation: 0.3,
+            },
+        ] {
+            let lens = pinhole.with_aperture(0.5, 4.).with_aperture_shape(shape);
+            for _ in 0..100 {
+                let (u, v) = (rng.next_float(), rng.next_float());
+                let center = pinhole.ray(u, v, (0.5, 0.5)).unwrap();
+                let focus_point =
+                    center.walk_dir(4. / center.direction.dot(&frame.forward));
+
+                let ray = lens
+                    .ray(u, v, (rng.next_float(), rng.next_float()))
+                    .unwrap();
+                // On the lens
+                let offset = ray.origin - frame.position;
+                assert!(offset.l2() <= 0.5 + 1e-4 && offset.dot(&frame.forward).abs() < 1e-4);
+                let along = (focus_point - ray.origin).dot(&ray.direction);
+                assert_close(ray.walk_dir(along), focus_point);
+            }
+        }
+    }
+
+    #[test]
+    fn shutter_times() {
+        let shutter = Shutter::new(1., 3.);
+        assert_eq!(shutter.time(0.), 1.);
+        assert_eq!(shutter.time(0.5), 2.);
+        assert_eq!(shutter.time(1.), 3.);
+        assert_eq!(Shutter::default().time(0.7), 0.);
+    }
+}
@@ -0,0 +1,337 @@
+//! [Constructive solid geometry](https://en.wikipedia.org/wiki/Constructive_solid_geometry) lets us build new solids out of existing ones.
+//! A sphere with a bite taken out of it is the difference between two spheres, a lens is the intersection of two spheres.
+//!
+//! Knowing where a ray first hits each of the objects is not enough for this. To take a bite out of a sphere we need to know where the ray
+//! is inside the sphere and where it is inside the bite, so we work with the [spans](crate::blocks::Span) of the ray that are inside each object.
+//! Combining the spans is a set operation on intervals of the ray, the first span boundary in front of the ray is where we hit the new solid.
+//! Every boundary of the new solid is a boundary of one of the objects, and that object's surface gives the normal, material and texture at the hit.
+
+use std::cell::RefCell;
+
+use crate::blocks::{Aabb, HitPoint, Material, Ray, RayCollision, Span};
+use crate::float::Float;
+use crate::packet::{Lanes, RayPacket, LANES};
+use crate::stats::Primitive;
+use crate::vectors::Vec3;
+
+#[derive(Clone, Copy, Debug)]
+pub enum CsgOperation {
+    /// Inside either object
+    Union,
+    /// Inside both objects
+    Intersection,
+    /// Inside the left object but not inside the right one
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+
+    /// Walk over the span boundaries of both objects in order, keeping track of which objects we are inside.
+    /// A new span starts when we move into the combined solid and ends when we move out of it.
+    fn combine(&self, left: &[Span], right: &[Span]) -> Vec<Span> {
+        self.boundaries(left, right)
+            .chunks_exact(2)
+            .map(|b| Span {
+                enter: b[0].0,
+                exit: b[1].0,
+            })
+            .collect()
+    }
+
+    /// The enter and exit distances of the [combined](Self::combine) spans in order, each with the object it comes from (`true` for the left one).
+    fn boundaries(&self, left: &[Span], right: &[Span]) -> Vec<(Float, bool)> {
+        let mut boundaries = left
+            .iter()
+            .flat_map(|s| vec![(s.enter, true), (s.exit, true)])
+            .chain(
+                right
+                    .iter()
+                    .flat_map(|s| vec![(s.enter, false), (s.exit, false)]),
+            )
+            .collect::<Vec<(Float, bool)>>();
+        boundaries.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter = None;
+        let mut combined = vec![];
+
+        for (t, is_left) in boundaries {
+            if is_left {
+                in_left = !in_left;
+            } else {
+                in_right = !in_right;
+            }
+
+            match (enter, self.inside(in_left, in_right)) {
+                (None, true) => enter = Some((t, is_left)),
+                (Some(e), false) => {
+                    if t > e.0 {
+                        combined.push(e);
+                        combined.push((t, is_left));
+                    }
+                    enter = None;
+                }
+                _ => {}
+            }
+        }
+
+        combined
+    }
+}
+
+/// Two closed objects combined into a new solid. `Csg` is itself a closed object, so you can keep combining.
+///
+/// The operands' [sidedness](crate::blocks::Sidedness) doesn't carry over: the combined solid is built from the spans inside the operands,
+/// which don't depend on the side a ray comes from, and a surface of the result can belong to either operand.
+/// A `Csg` is two sided, wrap the whole object in [`Sided`](crate::blocks::Sided) to make it one sided.
+pub struct Csg<L: RayCollision, R: RayCollision> {
+    pub left: L,
+    pub right: R,
+    pub operation: CsgOperation,
+    /// The points the last intersection test hit, with the time of the ray and whether the point is on the left object.
+    /// The renderer asks for the normal and material of a hit right after finding it, this way we don't have to work out the object again.
+    hits: RefCell<Vec<(Vec3, Float, bool)>>,
+}
+
+impl<L: RayCollision, R: RayCollision> Csg<L, R> {
+    pub fn new(left: L, right: R, operation: CsgOperation) -> Self {
+        Self {
+            left,
+            right,
+            operation,
+            hits: RefCell::new(Vec::with_capacity(LANES)),
+        }
+    }
+
+    pub fn union(left: L, right: R) -> Self {
+        Self::new(left, right, CsgOperation::Union)
+    }
+
+    pub fn intersection(left: L, right: R) -> Self {
+        Self::new(left, right, CsgOperation::Intersection)
+    }
+
+    pub fn difference(left: L, right: R) -> Self {
+        Self::new(left, right, CsgOperation::Difference)
+    }
+
+    /// Distance along the ray to where it first hits the solid, and whether that's on the surface of the left object
+    fn first_hit(&self, ray: &Ray) -> Option<(Float, bool)> {
+        self.operation
+            .boundaries(&self.left.ray_intervals(ray), &self.right.ray_intervals(ray))
+            .into_iter()
+            .find(|&(t, _)| ray.in_range(t))
+    }
+
+    /// Is the hit point on the surface of the left object (or the right one)?
+    ///
+    /// For the points we found ourselves we know, for other points (an enclosing `Csg` hands down the points it found) we look along
+    /// the lines through the point in the direction of each object's normal. The object with a span boundary closest to the point is the one we hit.
+    fn on_left(&self, hit_point: Vec3, time: Float) -> bool {
+        let known = self
+            .hits
+            .borrow()
+            .iter()
+            .find(|&&(p, t, _)| p == hit_point && t == time)
+            .map(|&(_, _, on_left)| on_left);
+        if let Some(on_left) = known {
+            return on_left;
+        }
+
+        let surface_distances = |normal: Vec3| {
+            let line = Ray::new(normal).set_origin(hit_point).set_time(time);
+            let distance = |obj: &dyn RayCollision| {
+                obj.ray_intervals(&line)
+                    .iter()
+                    .flat_map(|s| vec![s.enter.abs(), s.exit.abs()])
+                    .fold(Float::MAX, Float::min)
+            };
+            (distance(&self.left), distance(&self.right))
+        };
+        let (left_1, right_1) = surface_distances(self.left.collision_normal(hit_point, time));
+        let (left_2, right_2) = surface_distances(self.right.collision_normal(hit_point, time));
+        left_1.min(left_2) <= right_1.min(right_2)
+    }
+}
+
+impl<L: RayCollision, R: RayCollision> RayCollision for Csg<L, R> {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        let mut hits = self.hits.borrow_mut();
+        hits.clear();
+        match self.first_hit(ray) {
+            Some((t, on_left)) => {
+                let p = ray.walk_dir(t);
+                hits.push((p, ray.time, on_left));
+                HitPoint::Point(p)
+            }
+            None => HitPoint::None,
+        }
+    }
+
+    /// The normal of the object we hit. The right object of a difference is the hole, so its normal is flipped to point out of the solid.
+    fn collision_normal(&self, hit_point: Vec3, time: Float) -> Vec3 {
+        match (self.on_left(hit_point, time), self.operation) {
+            (true, _) => self.left.collision_normal(hit_point, time),
+            (false, CsgOperation::Difference) => self.right.collision_normal(hit_point, time).mult(-1.),
+            (false, _) => self.right.collision_normal(hit_point, time),
+        }
+    }
+
+    fn collision_material(&self, hit_point: Vec3, time: Float) -> Material {
+        if self.on_left(hit_point, time) {
+            self.left.collision_material(hit_point, time)
+        } else {
+            self.right.collision_material(hit_point, time)
+        }
+    }
+
+    fn collision_uv(&self, hit_point: Vec3, time: Float) -> (Float, Float) {
+        if self.on_left(hit_point, time) {
+            self.left.collision_uv(hit_point, time)
+        } else {
+            self.right.collision_uv(hit_point, time)
+        }
+    }
+
+    /// Intersection and difference are contained in the left object, so its box is good enough.
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.operation {
+            CsgOperation::Union => {
+                let (left, right) = (self.left.bounding_box()?, self.right.bounding_box()?);
+                Some(left.union(&right))
+            }
+            CsgOperation::Intersection => {
+                self.left.bounding_box().or_else(|| self.right.bounding_box())
+            }
+            CsgOperation::Difference => self.left.bounding_box(),
+        }
+    }
+
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        self.operation
+            .combine(&self.left.ray_intervals(ray), &self.right.ray_intervals(ray))
+    }
+
+    /// Every lane on its own, remembering the hits of all the lanes
+    fn packet_intersect(&self, packet: &RayPacket) -> Lanes {
+        let mut hits = self.hits.borrow_mut();
+        hits.clear();
+        let mut t = [Float::INFINITY; LANES];
+        for (lane, t) in t.iter_mut().enumerate() {
+            let ray = packet.ray(lane);
+            if let Some((distance, on_left)) = self.first_hit(&ray) {
+                hits.push((ray.walk_dir(distance), ray.time, on_left));
+                *t = distance;
+            }
+        }
+        t
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Csg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::Sphere;
+
+    fn spans(pairs: &[(Float, Float)]) -> Vec<Span> {
+        pairs
+            .iter()
+            .map(|&(enter, exit)| Span { enter, exit })
+            .collect()
+    }
+
+    fn pairs(spans: &[Span]) -> Vec<(Float, Float)> {
+        spans.iter().map(|s| (s.enter, s.exit)).collect()
+    }
+
+    fn sphere(center: (Float, Float, Float), radius: Float) -> Sphere {
+        Sphere {
+            center: Vec3::new(center),
+            radius,
+            material: Material::default(),
+        }
+    }
+
+    #[test]
+    fn spans_combine() {
+        let left = spans(&[(0., 4.), (6., 8.)]);
+        let right = spans(&[(2., 7.)]);
+
+        let union = CsgOperation::Union.combine(&left, &right);
+        assert_eq!(pairs(&union), vec![(0., 8.)]);
+
+        let intersection = CsgOperation::Intersection.combine(&left, &right);
+        assert_eq!(pairs(&intersection), vec![(2., 4.), (6., 7.)]);
+
+        let difference = CsgOperation::Difference.combine(&left, &right);
+        assert_eq!(pairs(&difference), vec![(0., 2.), (7., 8.)]);
+
+        // Nothing is left of a span the right object covers, and touching spans leave no empty span behind
+        let covered = CsgOperation::Difference.combine(&spans(&[(1., 2.)]), &spans(&[(1., 2.)]));
+        assert!(covered.is_empty());
+    }
+
+    /// The boundaries of the combined spans remember the object they come from
+    #[test]
+    fn boundaries_know_their_object() {
+        let left = spans(&[(0., 4.)]);
+        let right = spans(&[(2., 6.)]);
+
+        let difference = CsgOperation::Difference.boundaries(&left, &right);
+        assert_eq!(difference, vec![(0., true), (2., false)]);
+        let intersection = CsgOperation::Intersection.boundaries(&left, &right);
+        assert_eq!(intersection, vec![(2., false), (4., true)]);
+    }
+
+    /// Looking into the bite taken out of a sphere we see the inside of the small sphere, its normal has to point out of the solid, at any scale
+    #[test]
+    fn normal_of_subtracted_surface() {
+        for &scale in &[1e-3, 1., 1e3] {
+            let bitten = Csg::difference(
+                sphere((0., 0., 0.), scale),
+                sphere((0., 0., scale), 0.5 * scale),
+            );
+            let ray = Ray::new(Vec3::new((0., 0., -1.))).set_origin(Vec3::new((0., 0., 10. * scale)));
+
+            let p = match bitten.ray_intersect(&ray) {
+                HitPoint::Point(p) => p,
+                HitPoint::None => panic!("Missed the bitten sphere at scale {}", scale),
+            };
+            assert!((p.z() - 0.5 * scale).abs() < 1e-3 * scale, "Hit {:?} at scale {}", p, scale);
+
+            let n = bitten.collision_normal(p, 0.);
+            assert!(n.z() > 0.99, "Normal {:?} at scale {}", n, scale);
+
+            // The same answer for a point the Csg didn't find itself
+            let aside = Ray::new(Vec3::new((1., 0., 0.))).set_origin(Vec3::new((-10. * scale, 0., 0.)));
+            bitten.ray_intersect(&aside);
+            assert!(bitten.collision_normal(p, 0.).z() > 0.99);
+        }
+    }
+
+    /// A ray along the axis of a lens made of two spheres goes into the second sphere first, the lens starts where it goes into the first one
+    #[test]
+    fn lens_surface_belongs_to_the_later_object() {
+        let lens = Csg::intersection(sphere((0., 0., 0.), 1.), sphere((0., 0., 1.5), 1.));
+        let ray = Ray::new(Vec3::new((0., 0., -1.))).set_origin(Vec3::new((0., 0., 5.)));
+
+        match lens.ray_intersect(&ray) {
+            HitPoint::Point(p) => {
+                assert!((p.z() - 1.).abs() < 1e-4, "Hit {:?}", p);
+                assert!(lens.collision_normal(p, 0.).z() > 0.99);
+            }
+            HitPoint::None => panic!("Missed the lens"),
+        }
+    }
+}
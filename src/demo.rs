@@ -0,0 +1,121 @@
+//! The scene we render by default: the spheres, the floor and the lights from [the wiki](https://github.com/ssloy/tinyraytracer/wiki),
+//! animated so there is something to see in an animation. The command line renderer, the benchmarks and the tests all render it.
+
+use crate::animation::{Interpolation, Track};
+use crate::blocks::*;
+use crate::camera::CameraFrame;
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::trace::SceneObject;
+use crate::vectors::Vec3;
+
+struct SphereBuilder {
+    spheres: Vec<Sphere>,
+}
+
+impl SphereBuilder {
+    fn new() -> Self {
+        Self { spheres: vec![] }
+    }
+
+    fn add(mut self, center: (Float, Float, Float), radius: Float, material: Material) -> Self {
+        self.spheres.push(Sphere {
+            center: Vec3::new(center),
+            radius,
+            material,
+        });
+        self
+    }
+
+    fn build(self) -> Vec<Sphere> {
+        self.spheres
+    }
+}
+
+struct LightBuilder {
+    lights: Vec<LightSource>,
+}
+
+impl LightBuilder {
+    fn new() -> Self {
+        Self { lights: vec![] }
+    }
+
+    fn add(mut self, center: (Float, Float, Float), intensity: Float) -> Self {
+        self.lights.push(LightSource {
+            position: Vec3::new(center),
+            intensity,
+        });
+        self
+    }
+
+    fn build(self) -> Vec<LightSource> {
+        self.lights
+    }
+}
+
+
+/// The demo scene at `time` seconds. At time `0` this is the scene from the wiki, after that the ivory sphere bounces,
+/// the first light dims and comes back, and the camera circles around the spheres.
+///
+/// The ivory sphere keeps bouncing while the shutter is open: a ray at time `t` sees it where it is `t` seconds after `time`.
+pub fn demo_scene(time: Float) -> (Vec<SceneObject>, Vec<LightSource>, CameraFrame) {
+    let w_ivory = (0.6, 0.3, 0.1, 0.0);
+    let w_glass = (0., 0.5, 0.1, 0.8);
+    let w_rubber = (0.9, 0.1, 0.0, 0.0);
+    let w_mirror = (0., 10., 0.8, 0.0);
+
+    let ivory = Material::new((0.4, 0.4, 0.3), w_ivory, 50., 1.0);
+    let glass = Material::new((0.6, 0.7, 0.8), w_glass, 125., 1.5);
+    let red_rubber = Material::new((0.3, 0.1, 0.1), w_rubber, 10., 1.0);
+    let mirror = Material::new((1., 1., 1.), w_mirror, 1425., 1.0);
+
+    let bounce = [(0., -0.), (0.5, 2.), (1., -0.), (1.5, 2.), (2., -0.)]
+        .iter()
+        .map(|&(t, height)| (t - time, Vec3::new((-3., height, -16.))))
+        .collect();
+    let ivory_center = Track::new(bounce).with_interpolation(Interpolation::Smoothstep);
+
+    let spheres = SphereBuilder::new()
+        .add((-1., -1.5, -12.), 2.0, glass)
+        .add((1.5, -0.5, -18.), 3.0, red_rubber)
+        .add((7., 5., -18.), 4., mirror)
+        .build();
+
+    let mut scene = vec![Box::new(MovingSphere::new(ivory_center, 2.0, ivory)) as SceneObject];
+    scene.extend(spheres.iter().map(|&v| Box::new(v) as SceneObject));
+
+    let plain = Rectangle2D::new(
+        Vec3::new((-8., -4., -8.)),
+        Vec3::new((3., -4., -16.)),
+        Vec3::new((1., -0.05, 0.)),
+        red_rubber,
+    );
+
+    scene.push(Box::new(plain));
+
+    let first_light = Track::new(vec![(0., 1.5), (2., 0.3), (4., 1.5)])
+        .with_interpolation(Interpolation::Smoothstep);
+
+    let lights = LightBuilder::new()
+        .add((-20., 20., 20.), first_light.sample(time))
+        .add((30., 50., -25.), 1.3)
+        .add((30., 20., 30.), 1.3)
+        .build();
+
+    // Turntable around the middle of the scene, a Catmull-Rom spline through 4 points on a circle is close enough to a circle
+    let target = Vec3::new((0., 0., -16.));
+    let orbit = (0..=4)
+        .map(|k| {
+            let angle = k as Float * PI / 2.;
+            let offset = Vec3::new((16. * angle.sin(), 0., 16. * angle.cos()));
+            (k as Float, target + offset)
+        })
+        .collect();
+    let position = Track::new(orbit)
+        .with_interpolation(Interpolation::CatmullRom)
+        .sample(time);
+    let frame = CameraFrame::look_at(position, target, Vec3::new((0., 1., 0.)));
+
+    (scene, lights, frame)
+}
@@ -0,0 +1,230 @@
+//! With only a few rays per pixel, blurry effects like depth of field and motion blur come out grainy. Shooting more rays fixes that, slowly.
+//! A denoiser smooths the grain away after the fact, the trick is to do it without smoothing away the edges of objects.
+//!
+//! We use the [edge-avoiding À-Trous wavelet filter](https://jo.dreggn.org/home/2010_atrous.pdf) of Dammertz et al.
+//! It blurs every pixel with its neighbours, but a neighbour only counts if it looks like the same surface: similar normal, position and albedo
+//! (we get those from the first hit of each pixel, they have no noise). Running a small blur many times with holes between the taps ("à trous")
+//! covers a large area cheaply: every pass doubles the distance between the taps.
+
+use crate::float::to_f32;
+use crate::vectors::Vec3;
+
+/// What a pixel sees, used to decide if two pixels belong to the same surface
+#[derive(Clone, Copy, Debug)]
+pub struct Guide {
+    pub normal: Vec3,
+    pub albedo: (f32, f32, f32),
+    pub position: Vec3,
+}
+
+/// The [B3 spline](https://en.wikipedia.org/wiki/B-spline) kernel, applied along x and y
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Settings of the filter. A larger sigma lets more different neighbours blend in.
+#[derive(Clone, Copy, Debug)]
+pub struct ATrousFilter {
+    /// Number of passes, the last pass reaches `2^iterations` pixels away (its taps are `2^(iterations - 1)` apart, two on each side)
+    pub iterations: u32,
+    /// How different colors can be, halved after every pass so the wide passes only smooth what is left of the noise
+    pub color_sigma: f32,
+    pub normal_sigma: f32,
+    /// In world units
+    pub position_sigma: f32,
+    pub albedo_sigma: f32,
+}
+
+impl Default for ATrousFilter {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            color_sigma: 0.6,
+            normal_sigma: 0.3,
+            position_sigma: 1.,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+impl ATrousFilter {
+    pub fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Denoise a `width` x `height` image stored row by row. `guides` has the same layout, `None` for pixels that don't hit anything.
+    /// Pixels that hit something are never mixed with pixels that don't.
+    pub fn apply(
+        &self,
+        color: &[(f32, f32, f32)],
+        guides: &[Option<Guide>],
+        width: usize,
+        height: usize,
+    ) -> Vec<(f32, f32, f32)> {
+        assert_eq!(color.len(), width * height);
+        assert_eq!(guides.len(), width * height);
+
+        let mut current = color.to_vec();
+        let mut color_sigma = self.color_sigma;
+
+        for iteration in 0..self.iterations {
+            let step = 1i64 << iteration;
+            let mut next = Vec::with_capacity(current.len());
+
+            for y in 0..height as i64 {
+                for x in 0..width as i64 {
+                    let p = (y * width as i64 + x) as usize;
+                    let mut sum = (0f32, 0f32, 0f32);
+                    let mut total_weight = 0f32;
+
+                    for (ky, hy) in KERNEL.iter().enumerate() {
+                        for (kx, hx) in KERNEL.iter().enumerate() {
+                            // Clamp at the borders, the edge pixels get repeated
+                            let qx = (x + (kx as i64 - 2) * step).clamp(0, width as i64 - 1);
+                            let qy = (y + (ky as i64 - 2) * step).clamp(0, height as i64 - 1);
+                            let q = (qy * width as i64 + qx) as usize;
+
+                            let w = hx
+                                * hy
+                                * gaussian(distance2(current[p], current[q]), color_sigma)
+                                * self.guide_weight(&guides[p], &guides[q]);
+                            let c = current[q];
+                            sum = (sum.0 + w * c.0, sum.1 + w * c.1, sum.2 + w * c.2);
+                            total_weight += w;
+                        }
+                    }
+
+                    // The pixel itself always has a weight, so the total is never 0
+                    next.push((
+                        sum.0 / total_weight,
+                        sum.1 / total_weight,
+                        sum.2 / total_weight,
+                    ));
+                }
+            }
+
+            current = next;
+            color_sigma /= 2.;
+        }
+
+        current
+    }
+
+    /// How much the guides say two pixels are on the same surface, between 0 and 1
+    fn guide_weight(&self, p: &Option<Guide>, q: &Option<Guide>) -> f32 {
+        match (p, q) {
+            (Some(p), Some(q)) => {
+                gaussian(vector_distance2(p.normal, q.normal), self.normal_sigma)
+                    * gaussian(vector_distance2(p.position, q.position), self.position_sigma)
+                    * gaussian(distance2(p.albedo, q.albedo), self.albedo_sigma)
+            }
+            (None, None) => 1.,
+            _ => 0.,
+        }
+    }
+}
+
+fn distance2(a: (f32, f32, f32), b: (f32, f32, f32)) -> f32 {
+    (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)
+}
+
+/// Squared distance between two points, in the image precision
+fn vector_distance2(a: Vec3, b: Vec3) -> f32 {
+    let d = a - b;
+    to_f32(d.dot(&d))
+}
+
+/// `exp(-d2 / sigma^2)`, 1 for identical values and falls off as they get further apart
+fn gaussian(d2: f32, sigma: f32) -> f32 {
+    (-d2 / (sigma * sigma)).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::Float;
+    use crate::sampling::Rng;
+
+    const SIZE: usize = 16;
+
+    fn guide(normal: (Float, Float, Float), albedo: (f32, f32, f32)) -> Option<Guide> {
+        Some(Guide {
+            normal: Vec3::new(normal),
+            albedo,
+            position: Vec3::orig(),
+        })
+    }
+
+    /// Left half of the image one way, right half the other
+    fn halves<T: Copy>(left: T, right: T) -> Vec<T> {
+        (0..SIZE * SIZE)
+            .map(|p| if p % SIZE < SIZE / 2 { left } else { right })
+            .collect()
+    }
+
+    fn variance(color: &[(f32, f32, f32)]) -> f32 {
+        let mean = color.iter().map(|c| c.0).sum::<f32>() / color.len() as f32;
+        color.iter().map(|c| (c.0 - mean).powi(2)).sum::<f32>() / color.len() as f32
+    }
+
+    #[test]
+    fn flat_image_stays_the_same() {
+        let color = vec![(0.3, 0.5, 0.7); SIZE * SIZE];
+        let guides = vec![guide((0., 0., 1.), (0.5, 0.5, 0.5)); SIZE * SIZE];
+        for c in ATrousFilter::default().apply(&color, &guides, SIZE, SIZE) {
+            assert!(distance2(c, (0.3, 0.5, 0.7)) < 1e-10, "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn noise_gets_smoothed() {
+        let mut rng = Rng::new(1);
+        let color = (0..SIZE * SIZE)
+            .map(|_| {
+                let v = 0.5 + (to_f32(rng.next_float()) - 0.5) * 0.2;
+                (v, v, v)
+            })
+            .collect::<Vec<(f32, f32, f32)>>();
+        let guides = vec![guide((0., 0., 1.), (0.5, 0.5, 0.5)); SIZE * SIZE];
+
+        let denoised = ATrousFilter::default().apply(&color, &guides, SIZE, SIZE);
+        assert!(
+            variance(&denoised) < variance(&color) / 10.,
+            "Variance went from {} to {}",
+            variance(&color),
+            variance(&denoised)
+        );
+    }
+
+    /// Two surfaces of almost the same color side by side. Only the guides tell them apart, so only the guides keep the edge sharp
+    #[test]
+    fn edges_between_guides_survive() {
+        let (dark, light) = ((0.4, 0.4, 0.4), (0.6, 0.6, 0.6));
+        let color = halves(dark, light);
+        let grey = (0.5, 0.5, 0.5);
+        let filter = ATrousFilter::default();
+        // The two pixels on either side of the edge
+        let edge = |denoised: &[(f32, f32, f32)]| {
+            let row = SIZE * SIZE / 2;
+            (denoised[row + SIZE / 2 - 1], denoised[row + SIZE / 2])
+        };
+
+        let same = vec![guide((0., 0., 1.), grey); SIZE * SIZE];
+        let (left, right) = edge(&filter.apply(&color, &same, SIZE, SIZE));
+        assert!(
+            distance2(left, dark) > 1e-3 && distance2(right, light) > 1e-3,
+            "The edge of a single surface stayed sharp"
+        );
+
+        let normals = halves(guide((0., 0., 1.), grey), guide((1., 0., 0.), grey));
+        let albedos = halves(
+            guide((0., 0., 1.), grey),
+            guide((0., 0., 1.), (0.9, 0.9, 0.9)),
+        );
+        let hits = halves(guide((0., 0., 1.), grey), None);
+        for guides in [normals, albedos, hits] {
+            let (left, right) = edge(&filter.apply(&color, &guides, SIZE, SIZE));
+            assert!(distance2(left, dark) < 1e-6, "{:?} != {:?}", left, dark);
+            assert!(distance2(right, light) < 1e-6, "{:?} != {:?}", right, light);
+        }
+    }
+}
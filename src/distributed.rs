@@ -0,0 +1,559 @@
+//! A frame that takes an hour on one machine takes a few minutes on twenty. The coordinator splits the image into [tiles](crate::tiles),
+//! workers connect to it, get told what scene to render and then get one tile at a time until all tiles are done.
+//! The coordinator puts the tiles back together into the final image.
+//!
+//! Machines go away in the middle of a render. When a worker disconnects, hangs for longer than the worker timeout or sends garbage,
+//! the tile it was working on goes back into the queue for another worker.
+//!
+//! Workers connect over TCP (`tcp:host:port`), or a Unix socket (`unix:/path/to/socket`) to try things out on one machine.
+//! The messages are a byte saying what kind of message it is, followed by its fields in little endian.
+//!
+//! Our scenes are Rust code, not data, so we can't send them over the wire. What we send is a description of the scene, which the worker turns back into the scene.
+//! It's up to the coordinator and worker to agree on what the description means.
+
+use crate::tiles::Rect;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+/// How often the coordinator checks for new workers
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Longest scene description we accept. Descriptions are a few lines of text, this only keeps a broken peer from making us allocate gigabytes.
+const MAX_SCENE_BYTES: usize = 1 << 20;
+
+/// A connection to the other side, TCP or Unix socket
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Where the coordinator listens and workers connect to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Endpoint {
+    /// `host:port`
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    /// Parse `tcp:host:port` or `unix:/path/to/socket`
+    pub fn parse(endpoint: &str) -> Result<Self, String> {
+        if let Some(address) = endpoint.strip_prefix("tcp:") {
+            return Ok(Endpoint::Tcp(address.to_string()));
+        }
+        #[cfg(unix)]
+        if let Some(path) = endpoint.strip_prefix("unix:") {
+            return Ok(Endpoint::Unix(PathBuf::from(path)));
+        }
+        Err(format!(
+            "Unknown endpoint {}, expected tcp:host:port or unix:/path",
+            endpoint
+        ))
+    }
+
+    pub fn listen(&self) -> io::Result<Listener> {
+        match self {
+            Endpoint::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address)?)),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Ok(Listener::Unix(UnixListener::bind(path)?)),
+        }
+    }
+
+    pub fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        match self {
+            Endpoint::Tcp(address) => Ok(Box::new(TcpStream::connect(address)?)),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Next worker that connected, `None` if nobody is waiting. The listener must be non blocking.
+    /// Reading from or writing to the worker fails after `timeout`, so a worker that hangs can't keep its tile forever.
+    fn try_accept(&self, timeout: Duration) -> io::Result<Option<Box<dyn Stream>>> {
+        let accepted: io::Result<Box<dyn Stream>> = match self {
+            Listener::Tcp(listener) => listener.accept().and_then(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Ok(Box::new(stream) as Box<dyn Stream>)
+            }),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().and_then(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                Ok(Box::new(stream) as Box<dyn Stream>)
+            }),
+        };
+
+        match accepted {
+            Ok(stream) => Ok(Some(stream)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+/// The rendered pixels of a tile, row by row, with the number of rays that went into each of them
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileResult {
+    pub tile: Rect,
+    pub colors: Vec<(f32, f32, f32)>,
+    pub samples: Vec<u32>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Message {
+    /// Coordinator to worker, what to render
+    Scene(String),
+    /// Coordinator to worker, render this tile
+    Tile(Rect),
+    /// Worker to coordinator, the rendered tile
+    Result(TileResult),
+    /// Coordinator to worker, all tiles are done
+    Done,
+}
+
+fn write_u32(w: &mut dyn Write, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_f32(w: &mut dyn Write, v: f32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn read_u32(r: &mut dyn Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(r: &mut dyn Read) -> io::Result<f32> {
+    Ok(f32::from_bits(read_u32(r)?))
+}
+
+fn write_rect(w: &mut dyn Write, rect: &Rect) -> io::Result<()> {
+    for v in [rect.x, rect.y, rect.width, rect.height] {
+        write_u32(w, v)?;
+    }
+    Ok(())
+}
+
+fn read_rect(r: &mut dyn Read) -> io::Result<Rect> {
+    Ok(Rect::new(read_u32(r)?, read_u32(r)?, read_u32(r)?, read_u32(r)?))
+}
+
+impl Message {
+    /// Messages are small except for tile results, so we build the whole message in memory and write it in one go
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        let mut bytes = vec![];
+        match self {
+            Message::Scene(description) => {
+                bytes.push(0);
+                write_u32(&mut bytes, description.len() as u32)?;
+                bytes.extend_from_slice(description.as_bytes());
+            }
+            Message::Tile(rect) => {
+                bytes.push(1);
+                write_rect(&mut bytes, rect)?;
+            }
+            Message::Result(result) => {
+                bytes.push(2);
+                write_rect(&mut bytes, &result.tile)?;
+                for (&(r, g, b), &n) in result.colors.iter().zip(result.samples.iter()) {
+                    write_f32(&mut bytes, r)?;
+                    write_f32(&mut bytes, g)?;
+                    write_f32(&mut bytes, b)?;
+                    write_u32(&mut bytes, n)?;
+                }
+            }
+            Message::Done => bytes.push(3),
+        }
+        w.write_all(&bytes)?;
+        w.flush()
+    }
+
+    /// `expected` is the tile we are waiting for, if any. The other side could send us anything, so sizes are checked before we allocate:
+    /// a result must be for the expected tile and a scene description can't be longer than [`MAX_SCENE_BYTES`].
+    fn read(r: &mut dyn Read, expected: Option<&Rect>) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut tag = [0u8; 1];
+        r.read_exact(&mut tag)?;
+        match tag[0] {
+            0 => {
+                let len = read_u32(r)? as usize;
+                if len > MAX_SCENE_BYTES {
+                    return Err(invalid(format!(
+                        "Scene description of {} bytes, at most {} allowed",
+                        len, MAX_SCENE_BYTES
+                    )));
+                }
+                let mut description = vec![0u8; len];
+                r.read_exact(&mut description)?;
+                String::from_utf8(description)
+                    .map(Message::Scene)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            1 => Ok(Message::Tile(read_rect(r)?)),
+            2 => {
+                let tile = read_rect(r)?;
+                if expected != Some(&tile) {
+                    return Err(invalid(format!(
+                        "Result for tile {:?}, expected {:?}",
+                        tile, expected
+                    )));
+                }
+                let pixels = (tile.width as usize)
+                    .checked_mul(tile.height as usize)
+                    .ok_or_else(|| invalid(format!("Tile {:?} is too large", tile)))?;
+                let mut colors = Vec::with_capacity(pixels);
+                let mut samples = Vec::with_capacity(pixels);
+                for _ in 0..pixels {
+                    colors.push((read_f32(r)?, read_f32(r)?, read_f32(r)?));
+                    samples.push(read_u32(r)?);
+                }
+                Ok(Message::Result(TileResult {
+                    tile,
+                    colors,
+                    samples,
+                }))
+            }
+            3 => Ok(Message::Done),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown message type {}", other),
+            )),
+        }
+    }
+}
+
+/// Tiles waiting for a worker and tiles that are done, shared by the threads talking to the workers
+struct WorkQueue {
+    pending: VecDeque<Rect>,
+    results: Vec<TileResult>,
+    total: usize,
+}
+
+impl WorkQueue {
+    fn finished(&self) -> bool {
+        self.results.len() == self.total
+    }
+}
+
+/// The queue and a condition variable that is notified whenever a tile comes back or goes back into the queue
+struct SharedQueue {
+    state: Mutex<WorkQueue>,
+    changed: Condvar,
+}
+
+impl SharedQueue {
+    /// A thread that panicked while holding the lock leaves the queue as it was (we only push and pop whole tiles), so we carry on with it
+    fn lock(&self) -> MutexGuard<'_, WorkQueue> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A tile handed out to a worker. Unless it is [finished](TileLease::finish), it goes back into the queue when the lease is dropped,
+/// whether the worker went away, sent garbage or the thread talking to it panicked.
+struct TileLease<'a> {
+    tile: Rect,
+    queue: &'a SharedQueue,
+    done: bool,
+}
+
+impl<'a> TileLease<'a> {
+    fn finish(mut self, result: TileResult) {
+        let mut state = self.queue.lock();
+        state.results.push(result);
+        self.done = true;
+        self.queue.changed.notify_all();
+    }
+}
+
+impl<'a> Drop for TileLease<'a> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.queue.lock().pending.push_front(self.tile);
+            self.queue.changed.notify_all();
+        }
+    }
+}
+
+/// Hand out `tiles` to workers connecting to `listener` until every tile is rendered, and return the rendered tiles (in the order they came back).
+/// Workers can come and go at any time, the render finishes as long as some worker is around.
+/// A worker that doesn't answer within `worker_timeout` is dropped, so the timeout must be longer than rendering a tile takes.
+pub fn coordinate(
+    listener: Listener,
+    scene: &str,
+    tiles: Vec<Rect>,
+    worker_timeout: Duration,
+) -> io::Result<Vec<TileResult>> {
+    let queue = Arc::new(SharedQueue {
+        state: Mutex::new(WorkQueue {
+            total: tiles.len(),
+            pending: tiles.into_iter().collect(),
+            results: vec![],
+        }),
+        changed: Condvar::new(),
+    });
+
+    listener.set_nonblocking(true)?;
+    let mut workers = vec![];
+    loop {
+        {
+            let state = queue.lock();
+            if state.finished() {
+                break;
+            }
+            // Sleeps until a tile comes back or it's time to look for new workers
+            let _ = queue
+                .changed
+                .wait_timeout(state, ACCEPT_POLL_INTERVAL)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+
+        loop {
+            match listener.try_accept(worker_timeout) {
+                Ok(Some(stream)) => {
+                    let queue = Arc::clone(&queue);
+                    let scene = scene.to_string();
+                    workers.push(thread::spawn(move || serve_worker(stream, &scene, &queue)));
+                }
+                Ok(None) => break,
+                // A worker that failed to connect is no reason to give up the render, the others keep going
+                Err(e) => {
+                    eprintln!("Failed accepting a worker: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let mut state = queue.lock();
+    Ok(std::mem::take(&mut state.results))
+}
+
+/// Talk to a single worker: send it the scene, then tiles until there are none left.
+/// If the worker goes away (or sends garbage) its tile goes back into the queue.
+fn serve_worker(mut stream: Box<dyn Stream>, scene: &str, queue: &SharedQueue) {
+    if Message::Scene(scene.to_string()).write(&mut stream).is_err() {
+        return;
+    }
+
+    loop {
+        let tile = {
+            let mut state = queue.lock();
+            loop {
+                if let Some(tile) = state.pending.pop_front() {
+                    break Some(tile);
+                }
+                if state.finished() {
+                    break None;
+                }
+                // Another worker might drop its tile, so we wait around until everything is done
+                state = queue.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        };
+
+        let lease = match tile {
+            Some(tile) => TileLease {
+                tile,
+                queue,
+                done: false,
+            },
+            None => {
+                let _ = Message::Done.write(&mut stream);
+                return;
+            }
+        };
+
+        let result = Message::Tile(lease.tile)
+            .write(&mut stream)
+            .and_then(|_| Message::read(&mut stream, Some(&lease.tile)));
+        match result {
+            Ok(Message::Result(result)) => lease.finish(result),
+            // Dropping the lease puts the tile back
+            _ => return,
+        }
+    }
+}
+
+/// Work for the coordinator at the other end of `stream` until it says we are done.
+/// `prepare` gets the scene description and returns the function that renders a tile of that scene, or why it can't render the scene.
+pub fn work<F, T>(mut stream: Box<dyn Stream>, prepare: F) -> io::Result<()>
+where
+    F: FnOnce(&str) -> Result<T, String>,
+    T: FnMut(Rect) -> TileResult,
+{
+    let mut render_tile = match Message::read(&mut stream, None)? {
+        Message::Scene(description) => prepare(&description)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected the scene, got {:?}", other),
+            ))
+        }
+    };
+
+    loop {
+        match Message::read(&mut stream, None)? {
+            Message::Tile(tile) => Message::Result(render_tile(tile)).write(&mut stream)?,
+            Message::Done => return Ok(()),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Expected a tile, got {:?}", other),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "tinyraytracer-{}-{}.sock",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn render(tile: Rect) -> TileResult {
+        let pixels = (tile.width * tile.height) as usize;
+        TileResult {
+            tile,
+            colors: vec![(tile.x as f32, tile.y as f32, 1.); pixels],
+            samples: vec![1; pixels],
+        }
+    }
+
+    fn read_bytes(bytes: &[u8], expected: Option<&Rect>) -> io::Result<Message> {
+        Message::read(&mut &bytes[..], expected)
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let tile = Rect::new(4, 8, 3, 2);
+        for message in [
+            Message::Scene("--samples\n4".to_string()),
+            Message::Tile(tile),
+            Message::Result(render(tile)),
+            Message::Done,
+        ] {
+            let mut bytes = vec![];
+            message.write(&mut bytes).unwrap();
+            assert_eq!(read_bytes(&bytes, Some(&tile)).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn result_for_another_tile_is_rejected() {
+        let mut bytes = vec![];
+        Message::Result(render(Rect::new(0, 0, 2, 2)))
+            .write(&mut bytes)
+            .unwrap();
+        assert!(read_bytes(&bytes, Some(&Rect::new(2, 0, 2, 2))).is_err());
+        assert!(read_bytes(&bytes, None).is_err());
+    }
+
+    /// Neither of these may allocate what the header claims
+    #[test]
+    fn huge_sizes_are_rejected() {
+        let mut scene = vec![0];
+        scene.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_bytes(&scene, None).is_err());
+
+        let huge = Rect::new(0, 0, u32::MAX, u32::MAX);
+        let mut result = vec![2];
+        write_rect(&mut result, &huge).unwrap();
+        assert!(read_bytes(&result, Some(&Rect::new(0, 0, 8, 8))).is_err());
+    }
+
+    /// One worker hangs, one answers for the wrong tile, one panics and drops the connection: their tiles must go to the good worker
+    #[test]
+    fn tiles_of_failing_workers_are_rendered_by_others() {
+        let path = socket_path("failing-workers");
+        let _ = std::fs::remove_file(&path);
+        let endpoint = Endpoint::Unix(path.clone());
+        let listener = endpoint.listen().unwrap();
+        let tiles = (0..8)
+            .map(|i| Rect::new(i * 4, 0, 4, 4))
+            .collect::<Vec<Rect>>();
+
+        let coordinator = {
+            let tiles = tiles.clone();
+            thread::spawn(move || coordinate(listener, "scene", tiles, Duration::from_millis(200)))
+        };
+
+        let hanging = endpoint.connect().unwrap();
+        let hanging = thread::spawn(move || {
+            let _ = work(hanging, |_| {
+                Ok(|tile| {
+                    thread::sleep(Duration::from_secs(1));
+                    render(tile)
+                })
+            });
+        });
+        let wrong = endpoint.connect().unwrap();
+        let wrong = thread::spawn(move || {
+            let _ = work(wrong, |_| Ok(|_| render(Rect::new(100, 100, 4, 4))));
+        });
+        let panicking = endpoint.connect().unwrap();
+        let panicking = thread::spawn(move || {
+            work(panicking, |_| {
+                Ok(|_: Rect| -> TileResult { panic!("Worker crashed") })
+            })
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let good = endpoint.connect().unwrap();
+        work(good, |scene| {
+            assert_eq!(scene, "scene");
+            Ok(render)
+        })
+        .unwrap();
+
+        let mut results = coordinator.join().unwrap().unwrap();
+        results.sort_by_key(|r| r.tile.x);
+        assert_eq!(
+            results,
+            tiles.into_iter().map(render).collect::<Vec<TileResult>>()
+        );
+
+        let _ = hanging.join();
+        let _ = wrong.join();
+        assert!(panicking.join().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,22 @@
+//! The precision of all the geometry. `f32` is plenty for scenes that fit in a room and it's fast,
+//! but a scene the size of a planet with details the size of a pebble runs out of digits: hit points land on the wrong side of surfaces
+//! and objects get holes. Build with `--features f64` to do all the geometry in double precision.
+//!
+//! Code that works with geometry uses [`Float`] instead of `f32` and takes constants like `PI` from [`consts`], so it works in both precisions.
+//! Colors and the image buffers stay in `f32`, that's more precision than any image format keeps.
+
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
+
+/// Go from the geometry precision to the image precision. This is a no-op unless we build with `f64`.
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f32(x: Float) -> f32 {
+    x as f32
+}
@@ -1,333 +1,1103 @@
-//! This is a basic ray tracer. It is based on [Understandable RayTracing in 256 lines of bare C++](https://github.com/ssloy/tinyraytracer/wiki)
-//! Coming from a computer vision background, I had some idea how ray tracing might work, this is for me to actually understand it.
-//! To make it more fun, I wanna try and write documentation that's going to cover some fundamental concepts as part of it.
-//!
-//! ( _This is a work in progress and I don't add documentation in a linear fashion, so it might be a bit early to try and read it_)
-//! If there is anything you want to fix/comment on/request etc. [Github Discussions](https://github.com/gaxler/tinyraytrace-rs/discussions/) is the best way to get in touch
-//!
-//! ## What is Ray Tracing?
-//! (_caveat: I'm learning about it pretty much for the first time as I implement this._)
-//!
-//!
-//! We have a 3-D model of some object in the world, and we want to express this model as a 2-D computer image. To construct our image we need to decide what color to assign to each pixel of the image.
-//! Since the real world is complex we don't have an easy method to determine the color we should assign to each image pixel and we relay on simulations of light rays.
-//! Now, lets say more about what we mean by complex world and by simulation of a light ray.
-//!
-//! The world is complex because objects have many colors, those colors change with lighting and reflection properties of the materials they are made of.
-//! Also, those properties interact with each other (light sources act in tandem on an object, light reflects from one object to another etc..).
-//! We do light ray simulation is by modeling how a ray of light behaves in the world. Direction at which it meets an object, how it is reflect from the object, how it eventually hits our eye etc...
-//!
-//! To see how complex this can get, let's take a look at our goal with this project vs. where we are now:
-//! <p style="text-align:center;"><img src="https://raw.githubusercontent.com/ssloy/tinyraytracer/homework_assignment/out-envmap-duck.jpg"  width="400"/>
-//! <img src="https://raw.githubusercontent.com/gaxler/tinyraytrace-rs/main/static/assets/current.png"  width="400"/>
-//!</p>
-//!
-//! ## How we trace rays?
-//! Since we are going to project from 3 to 2 dimension we need to pick a viewing angle (projection plane).
-//! This plane is going to We going to call this viewing angle a camera.
-//!
-//! [Ray-Sphere intersection](struct.Sphere.html#method.ray_intersect)
-//!
-//!
-//! ## Question for future explorations
-//! ### What if we have millions of objects in a scene?
-//! I guess you can avoid checking most of the objects and limit your intersection checks based on light rays' direction. How is it done in actual ray tracers?
-//! ### How to make ray tracing differentiable?
-//! Quick search got me this [paper](https://people.csail.mit.edu/tzumao/diffrt/)
-
-mod blocks;
-mod vectors;
+//! Renders the demo scene into `static/assets/current.png`, or wherever `--output <path>` says.
 
 extern crate image;
 
-use blocks::*;
-use std::f32::consts::FRAC_2_PI;
-use vectors::Vec3;
+use tinyraytracer::float::consts::{FRAC_2_PI, PI};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tinyraytracer::blocks::*;
+use tinyraytracer::camera::{
+    ApertureShape, Camera, CameraFrame, EquirectangularCamera, FisheyeCamera, OrthographicCamera,
+    Shutter, ThinLensCamera,
+};
+use tinyraytracer::demo::demo_scene;
+use tinyraytracer::distributed::{self, Endpoint, TileResult};
+use tinyraytracer::float::{to_f32, Float};
+use tinyraytracer::denoise::{ATrousFilter, Guide};
+use tinyraytracer::packet::LANES;
+use tinyraytracer::progressive::Accumulator;
+use tinyraytracer::sampling::{hash_to_unit, hash_u64s, PixelSampler};
+use tinyraytracer::stats::{self, RayKind};
+use tinyraytracer::tiles::{tiles, Rect, TileOrder};
+use tinyraytracer::trace::{
+    cast_ray, light_contributions, pixel_ray, render_pixels, trace_packet, CollisionState,
+    SceneObject,
+};
+use tinyraytracer::vectors::Vec3;
 
-const DEFAULT_JITTER: f32 = 0.001;
-const MAX_RAY_BOUNCES: u32 = 4;
 const CANVAS_WIDTH_HEIGHT: (u32, u32) = (1024, 768);
+const OUTPUT: &str = "static/assets/current.png";
+const SAMPLES_PER_PIXEL: u32 = 1;
+const ADAPTIVE_MIN_SAMPLES: u32 = 4;
+const TILE_SIZE: u32 = 32;
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
 
-type SceneObject = Box<dyn RayCollision>;
-
-struct CollisionState {
-    hit_point: Vec3,
-    normal: Vec3,
-    material: Material,
-    ray: Ray,
+/// Arbitrary output variables: extra images written next to the final image, each showing one property of the first thing a pixel sees.
+/// They help when debugging a scene, and compositing tools use them to tweak the image after rendering.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Aov {
+    /// Distance along the camera view direction, brighter is further away
+    Depth,
+    /// World space normal, each axis mapped from `[-1, 1]` to `[0, 1]`
+    Normal,
+    /// Color of the material before any lighting
+    Albedo,
+    /// World space position, scaled to fit the part of the scene that is visible
+    Position,
+    /// A different flat color for every object
+    ObjectId,
+    /// A different flat color for every material
+    MaterialId,
+    /// One image per light source, with only that light's direct light
+    Lights,
 }
 
-impl CollisionState {
-    fn _jitter(&self, dir: Vec3, jitter: f32) -> Vec3 {
-        jitter_along_normal(self.hit_point, dir, self.normal, jitter)
-    }
+impl Aov {
+    const ALL: [Aov; 7] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Lights,
+    ];
 
-    fn reflected_ray(&self, jitter: f32) -> Ray {
-        let reflect_dir = self.ray.direction.reflect(self.normal);
-        let ref_orig = self._jitter(reflect_dir, jitter);
+    fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::ObjectId => "object",
+            Aov::MaterialId => "material",
+            Aov::Lights => "light",
+        }
+    }
 
-        Ray::new(reflect_dir).set_origin(ref_orig)
+    /// Parse a comma separated list like `depth,normal`, `all` turns on every AOV.
+    fn parse_list(list: &str) -> Result<Vec<Aov>, String> {
+        if list == "all" {
+            return Ok(Aov::ALL.to_vec());
+        }
+        list.split(',')
+            .map(|name| {
+                Aov::ALL
+                    .iter()
+                    .copied()
+                    .find(|aov| aov.name() == name)
+                    .ok_or_else(|| format!("Unknown AOV {}", name))
+            })
+            .collect()
     }
+}
 
-    fn refracted_ray(&self, jitter: f32) -> Ray {
-        let refract_dir = self
-            .ray
-            .direction
-            .refract(self.normal, self.material.refraction_index)
-            .normalized();
-        let ref_orig = self._jitter(refract_dir, jitter);
+/// Everything the AOVs need to know about the first collision of a pixel
+struct FirstHit {
+    depth: Float,
+    normal: Vec3,
+    albedo: (Float, Float, Float),
+    position: Vec3,
+    object: usize,
+    material: usize,
+    /// Color the collision point gets from each light alone
+    lights: Vec<(Float, Float, Float)>,
+}
 
-        Ray::new(refract_dir).set_origin(ref_orig)
-    }
+/// A flat color for an id, neighbouring ids get very different colors.
+fn id_color(id: usize) -> (Float, Float, Float) {
+    let id = id as u32;
+    (
+        hash_to_unit(id * 3),
+        hash_to_unit(id * 3 + 1),
+        hash_to_unit(id * 3 + 2),
+    )
 }
 
-/// This is the light ray simulation. We go over the objects in the scene and check if our light ray intersect with them.
-/// If there is an intersection, we get the point of intersection and assign the color of the object the ray intersect with.
-/// Next we use the point of intersection and the lighting source in the scene to determine how lighting should affect the color at intersection point.
-fn cast_ray(ray: Ray, scene: &[SceneObject]) -> Option<CollisionState> {
-    let mut dist = f32::MAX;
-    let mut hit_point: Option<Vec3> = None;
-    let mut normal = Vec3::orig();
-    let mut material = Material::default();
-
-    for s in scene.iter() {
-        match s.ray_intersect(&ray) {
-            // Hit is the point where our ray hits the sphere
-            HitPoint::Point(p) if (p - ray.origin).l2() < dist => {
-                dist = (p - ray.origin).l2();
-                material = s.collision_material(p);
-                normal = s.collision_normal(p);
-                hit_point = Some(p);
+/// Write the AOV images next to `output`, `current.png` gets `current.depth.png`, `current.normal.png` and so on.
+/// Depth and position are scaled to the range of values in the image. Pixels that hit nothing are black.
+fn save_aovs(
+    hits: &[Option<FirstHit>],
+    aovs: &[Aov],
+    light_count: usize,
+    (imgx, imgy): (u32, u32),
+    output: &str,
+) {
+    let save = |name: String, color: &dyn Fn(&FirstHit) -> (Float, Float, Float)| {
+        let imgbuf = image::ImageBuffer::from_fn(imgx, imgy, |i, j| {
+            let hit = &hits[(j * imgx + i) as usize];
+            Material::to_pixel(hit.as_ref().map_or((0., 0., 0.), color))
+        });
+        let path = Path::new(output).with_extension(format!("{}.png", name));
+        imgbuf.save(path).expect("Failed saving AOV");
+    };
+
+    let hit_iter = || hits.iter().flatten();
+    let max_depth = hit_iter()
+        .map(|h| h.depth)
+        .fold(Float::MIN_POSITIVE, Float::max);
+    let (low, high) = hit_iter().fold(
+        (
+            Vec3::new((Float::MAX, Float::MAX, Float::MAX)),
+            Vec3::new((Float::MIN, Float::MIN, Float::MIN)),
+        ),
+        |(low, high), h| (low.min(&h.position), high.max(&h.position)),
+    );
+    let extent = high - low;
+    let scale = |v: Float, extent: Float| if extent > 0. { v / extent } else { 0. };
+
+    for aov in aovs {
+        match aov {
+            Aov::Depth => save(aov.name().to_string(), &|h| {
+                let d = (h.depth / max_depth).clamp(0., 1.);
+                (d, d, d)
+            }),
+            Aov::Normal => save(aov.name().to_string(), &|h| {
+                let n = h.normal;
+                (0.5 + 0.5 * n.x(), 0.5 + 0.5 * n.y(), 0.5 + 0.5 * n.z())
+            }),
+            Aov::Albedo => save(aov.name().to_string(), &|h| h.albedo),
+            Aov::Position => save(aov.name().to_string(), &|h| {
+                let p = h.position - low;
+                (
+                    scale(p.x(), extent.x()),
+                    scale(p.y(), extent.y()),
+                    scale(p.z(), extent.z()),
+                )
+            }),
+            Aov::ObjectId => save(aov.name().to_string(), &|h| id_color(h.object)),
+            Aov::MaterialId => save(aov.name().to_string(), &|h| id_color(h.material)),
+            Aov::Lights => {
+                for l in 0..light_count {
+                    save(format!("{}{}", aov.name(), l), &|h| h.lights[l]);
+                }
             }
-            _ => continue,
         }
     }
+}
 
-    // The question mark checks if hit_point is None or Some if it is None then function returns None otherwise it unpacks the Some
-    Some(CollisionState {
-        hit_point: hit_point?,
-        normal,
+/// What the AOVs and the denoiser need to know about a collision. Materials get an id in the order we first see them.
+fn first_hit(
+    collision: &CollisionState,
+    scene: &[SceneObject],
+    lights: &[LightSource],
+    frame: &CameraFrame,
+    materials: &mut Vec<Material>,
+) -> FirstHit {
+    let material = match materials.iter().position(|m| *m == collision.material) {
+        Some(id) => id,
+        None => {
+            materials.push(collision.material);
+            materials.len() - 1
+        }
+    };
+
+    FirstHit {
+        depth: (collision.hit_point - frame.position).dot(&frame.forward),
+        normal: collision.outward_normal,
+        albedo: collision.material.color(),
+        position: collision.hit_point,
+        object: collision.object,
         material,
-        ray,
-    })
+        lights: light_contributions(collision, scene, lights)
+            .iter()
+            .map(|&(d, s)| collision.material.adjust_light(d, s).color())
+            .collect(),
+    }
 }
 
-/// This function jitters a point along a noraml vector. Why do we need that? [@ssloy explains](https://github.com/ssloy/tinyraytracer/wiki/Part-1:-understandable-raytracing#step-6-shadows):
-///"Why is that? It's just that our point lies on the surface of the object, and (except for the question of numerical errors) any ray from this point will intersect the object itself."
-fn jitter_along_normal(pt: Vec3, direction: Vec3, normal: Vec3, jitter: f32) -> Vec3 {
-    let _shift = jitter.copysign(direction.dot(&normal));
-    pt + normal.mult(_shift)
+/// Colors are computed in the geometry precision, the image buffers are always `f32`
+fn to_image_color((r, g, b): (Float, Float, Float)) -> (f32, f32, f32) {
+    (to_f32(r), to_f32(g), to_f32(b))
 }
 
-/// Shadow is like a negative light, we "cast a ray of shadow" for a certain hit point and light source.
-/// If the shadow ray hits the object, we know that the object is in shadow and we can't see the light source. ([Github Copilot](https://copilot.github.com/) wrote this line for me, how cool is that?)
-fn light_is_shadowed(
-    hit_point: Vec3,
-    hit_normal: Vec3,
-    light_position: Vec3,
-    scene: &[SceneObject],
-) -> bool {
-    let ldir = (light_position - hit_point).normalized();
-    let ldist = (light_position - hit_point).l2();
+/// We write to a temporary file and move it over `output`, like [`Accumulator::save`]: stopping halfway through a save never leaves a truncated image behind.
+fn save_image(framebuffer: &[(f32, f32, f32)], (imgx, imgy): (u32, u32), output: &Path) {
+    let imgbuf = image::ImageBuffer::from_fn(imgx, imgy, |i, j| {
+        let (r, g, b) = framebuffer[(j * imgx + i) as usize];
+        Material::to_pixel((Float::from(r), Float::from(g), Float::from(b)))
+    });
+    let temporary = output.with_extension("tmp.png");
+    imgbuf.save(&temporary).expect("Failed saving canvas");
+    fs::rename(temporary, output).expect("Failed saving canvas");
+}
 
-    let shadow_orig = jitter_along_normal(hit_point, ldir, hit_normal, DEFAULT_JITTER);
-    let shadow_ray = Ray::new(ldir).set_origin(shadow_orig);
+/// Where the image so far goes while rendering: `current.png` gets `current.partial.png`.
+/// Only finished images are saved at the output path itself, so an image there is always complete.
+fn partial_path(output: &str) -> PathBuf {
+    Path::new(output).with_extension("partial.png")
+}
+
+/// The parts of rendering shared by rendering on this machine and rendering tiles for a coordinator
+struct Renderer<'a> {
+    scene: &'a [SceneObject],
+    lights: &'a [LightSource],
+    camera: &'a dyn Camera,
+    settings: &'a RenderSettings,
+    sampler: PixelSampler,
+}
 
-    if let Some(shadow) = cast_ray(shadow_ray, scene) {
-        if (shadow.hit_point - shadow_orig).l2() < ldist {
-            return true;
+impl<'a> Renderer<'a> {
+    fn new(
+        scene: &'a [SceneObject],
+        lights: &'a [LightSource],
+        camera: &'a dyn Camera,
+        settings: &'a RenderSettings,
+    ) -> Self {
+        stats::set_scene(scene.iter().map(|s| s.primitive()));
+        Self {
+            scene,
+            lights,
+            camera,
+            settings,
+            sampler: PixelSampler::new(settings.samples_per_pixel, settings.seed),
         }
     }
-    false
-}
 
-fn get_light_adjustments(
-    collision: &CollisionState,
-    scene: &[SceneObject],
-    lights: &[LightSource],
-) -> (f32, f32) {
-    let (normal, p, ray) = (collision.normal, collision.hit_point, collision.ray);
+    /// The ray of sample number `s` of the pixel `(i, j)`
+    fn sample_ray(&self, i: u32, j: u32, s: u32) -> Option<Ray> {
+        pixel_ray(self.camera, &self.sampler, self.settings.size, (i, j), s)
+    }
 
-    let mut diffuse = 0f32;
-    let mut specular = 0f32;
+    /// Shoot one more ray through each of the pixels, at most [`LANES`] of them. The primary rays go through the scene as one packet.
+    fn add_samples(&self, accumulator: &mut Accumulator, pixels: &[(u32, u32)]) {
+        let rays = pixels
+            .iter()
+            .map(|&(i, j)| {
+                let index = (j * self.settings.size.0 + i) as usize;
+                (index, self.sample_ray(i, j, accumulator.samples(index)))
+            })
+            .collect::<Vec<(usize, Option<Ray>)>>();
 
-    for cur in lights.iter() {
-        let ldir = (cur.position - p).normalized();
-        let diff_coef = ldir.dot(&normal).max(0.);
+        let traced = rays.iter().filter_map(|&(_, ray)| ray).collect::<Vec<Ray>>();
+        let mut colors = trace_packet(&traced, self.scene, self.lights).into_iter();
 
-        if light_is_shadowed(p, normal, cur.position, scene) {
-            continue;
+        for (index, ray) in rays {
+            // Pixels the camera doesn't see stay black
+            let color = match ray {
+                Some(_) => colors.next().unwrap(),
+                None => (0., 0., 0.),
+            };
+            accumulator.add(index, to_image_color(color));
         }
+    }
 
-        let spec_coef = ldir
-            .reflect(normal)
-            .dot(&ray.direction)
-            .max(0.)
-            .powf(collision.material.specular_exponent);
+    /// Tells renders apart, so we only resume from a checkpoint of the same render: the seed, samples per pixel, image size and camera,
+    /// and the colors of a few rays through the scene. The scene objects can't be compared directly, but a changed scene or light shows up in those colors.
+    fn fingerprint(&self) -> u64 {
+        let (imgx, imgy) = self.settings.size;
+        let bits = |x: Float| u64::from(to_f32(x).to_bits());
+        let vector = |v: Vec3| [bits(v.x()), bits(v.y()), bits(v.z())];
+        let frame = self.camera.frame();
+        let shutter = self.camera.shutter();
 
-        diffuse += cur.intensity * diff_coef;
-        specular += cur.intensity * spec_coef;
+        let mut values = vec![
+            self.settings.seed,
+            u64::from(self.settings.samples_per_pixel),
+            u64::from(imgx),
+            u64::from(imgy),
+            bits(shutter.open),
+            bits(shutter.close),
+        ];
+        for v in [frame.position, frame.forward, frame.right, frame.up] {
+            values.extend(vector(v));
+        }
+        for (r, g, b) in render_pixels(
+            self.scene,
+            self.lights,
+            self.camera,
+            (8, 6),
+            &PixelSampler::new(1, 0),
+        ) {
+            values.extend([bits(r), bits(g), bits(b)]);
+        }
+        hash_u64s(&values)
     }
 
-    (diffuse, specular)
-    // material.adjust_light(diffuse, specular)
+    /// The part of the image we render, everything outside the crop window stays black
+    fn region(&self) -> Rect {
+        let (imgx, imgy) = self.settings.size;
+        let canvas = Rect::new(0, 0, imgx, imgy);
+        self.settings
+            .crop
+            .map_or(Some(canvas), |crop| crop.intersect(&canvas))
+            .expect("The crop window is outside the image")
+    }
+
+    /// Number of pixels in `rect` that need more samples
+    fn pending(&self, accumulator: &Accumulator, rect: &Rect) -> usize {
+        rect.pixels()
+            .filter(|&(i, j)| {
+                self.settings
+                    .needs_sample(accumulator, (j * self.settings.size.0 + i) as usize)
+            })
+            .count()
+    }
+
+    /// One pass over a tile: every pixel that needs more samples gets one more
+    fn sample_tile(&self, accumulator: &mut Accumulator, tile: &Rect) {
+        let pending = tile
+            .pixels()
+            .filter(|&(i, j)| {
+                self.settings
+                    .needs_sample(accumulator, (j * self.settings.size.0 + i) as usize)
+            })
+            .collect::<Vec<(u32, u32)>>();
+
+        for pixels in pending.chunks(LANES) {
+            self.add_samples(accumulator, pixels);
+        }
+    }
+
+    /// Denoise the image and save it, along with the AOVs and the sample heatmap if we want them.
+    /// The AOVs and the denoiser guides are taken from the first ray of every pixel.
+    fn finish(&self, mut framebuffer: Vec<(f32, f32, f32)>, samples: &[u32], output: &str) {
+        let settings = self.settings;
+        let (imgx, imgy) = settings.size;
+        let need_first_hits = !settings.aovs.is_empty() || settings.denoise.is_some();
+
+        let region = self.region();
+        let frame = self.camera.frame();
+        let mut materials: Vec<Material> = vec![];
+        let first_hits = if need_first_hits {
+            stats::timed("first hits", || {
+                Rect::new(0, 0, imgx, imgy)
+                    .pixels()
+                    .map(|(i, j)| {
+                        if !region.contains(i, j) {
+                            return None;
+                        }
+                        let ray = self.sample_ray(i, j, 0)?;
+                        stats::count_rays(RayKind::FirstHit, 1);
+                        let collision = cast_ray(ray, self.scene)?;
+                        Some(first_hit(
+                            &collision,
+                            self.scene,
+                            self.lights,
+                            &frame,
+                            &mut materials,
+                        ))
+                    })
+                    .collect::<Vec<Option<FirstHit>>>()
+            })
+        } else {
+            vec![]
+        };
+
+        if let Some(filter) = &settings.denoise {
+            let guides = first_hits
+                .iter()
+                .map(|hit| {
+                    hit.as_ref().map(|h| Guide {
+                        normal: h.normal,
+                        albedo: to_image_color(h.albedo),
+                        position: h.position,
+                    })
+                })
+                .collect::<Vec<Option<Guide>>>();
+            framebuffer = stats::timed("denoise", || {
+                filter.apply(&framebuffer, &guides, imgx as usize, imgy as usize)
+            });
+        }
+
+        stats::timed("save", || {
+            save_image(&framebuffer, settings.size, Path::new(output));
+            if !settings.aovs.is_empty() {
+                save_aovs(
+                    &first_hits,
+                    &settings.aovs,
+                    self.lights.len(),
+                    settings.size,
+                    output,
+                );
+            }
+            if settings.adaptive_threshold.is_some() {
+                save_sample_heatmap(samples, settings.size, output);
+            }
+        });
+    }
 }
 
-/// Our ray of lights don't stay in the same spot. If the hit some reflective material, they bounce off it like a ball.
-/// The is a recursive process. We start with a ray of light and cast it through the scene. Every time a ray hits some object and bounces off, well that's a new ray.
-/// In real life ( I guess ) this process can go on until light losses energy, here we put a hard limit on the number of bounces.
-fn reflective_ray_cast(
-    ray: Ray,
-    scene: &[SceneObject],
-    lights: &[LightSource],
-    depth: u32,
-) -> Material {
-    match cast_ray(ray, scene) {
-        Some(collision) if depth < MAX_RAY_BOUNCES => {
-            // refLECted ray cast
-            let reflected_ = reflective_ray_cast(
-                collision.reflected_ray(DEFAULT_JITTER),
-                scene,
-                lights,
-                depth + 1,
+/// This function builds an image by simulating light rays.
+/// Each pixel of an image is translated into a light ray. For each pixel, the light ray simulation returns the color the pixel should get.
+///
+/// With more than one sample per pixel, every ray goes through a different point in the pixel (anti-aliasing), starts from a different point on the lens (depth of field)
+/// and is shot at a different time while the shutter is open (motion blur).
+/// The pixel color is the average of all the rays.
+///
+/// We render progressively: every pass over the image adds one ray to each pixel, and every now and then the image so far is saved to `<output>.partial.png`
+/// (and to a checkpoint if asked to). Rendering stops when every pixel has its samples or when the time budget runs out.
+/// Only a finished image is saved to `output`, then the partial image and the checkpoint are removed. A render that ran out of time leaves them for `--resume` and returns `false`.
+/// A checkpoint we can't resume from (unreadable, or of another render) is an error.
+/// With adaptive sampling, pixels stop getting rays as soon as their error estimate drops below the threshold.
+/// Each pass goes over the image tile by tile, only the tiles inside the crop window are rendered.
+fn render(
+    spheres: Vec<SceneObject>,
+    lights: Vec<LightSource>,
+    camera: &dyn Camera,
+    settings: &RenderSettings,
+    output: &str,
+) -> Result<bool, String> {
+    let started = Instant::now();
+    let (imgx, imgy) = settings.size;
+    let renderer = Renderer::new(&spheres, &lights, camera, settings);
+
+    let checkpoint = if settings.checkpoint {
+        Some(Path::new(output).with_extension("checkpoint"))
+    } else {
+        None
+    };
+    let fingerprint = match &checkpoint {
+        Some(_) => stats::timed("fingerprint", || renderer.fingerprint()),
+        None => 0,
+    };
+    let mut accumulator = match &checkpoint {
+        Some(path) if settings.resume && path.exists() => {
+            let resumed = Accumulator::load(path)
+                .map_err(|e| format!("Failed reading checkpoint {}: {}", path.display(), e))?;
+            if resumed.width() != imgx || resumed.height() != imgy {
+                return Err(format!(
+                    "The checkpoint {} is for a {}x{} image, delete it or render without --resume",
+                    path.display(),
+                    resumed.width(),
+                    resumed.height()
+                ));
+            }
+            if resumed.fingerprint() != fingerprint {
+                return Err(format!(
+                    "The checkpoint {} is of a different render (scene, camera, seed or samples per pixel changed), delete it or render without --resume",
+                    path.display()
+                ));
+            }
+            println!(
+                "Resuming {} at {} samples per pixel",
+                path.display(),
+                resumed.min_samples()
             );
+            resumed
+        }
+        _ => Accumulator::new(imgx, imgy).with_fingerprint(fingerprint),
+    };
+
+    let save_progress = |accumulator: &Accumulator| {
+        stats::timed("progress", || {
+            if let Some(path) = &checkpoint {
+                accumulator.save(path).expect("Failed saving checkpoint");
+            }
+            save_image(&accumulator.average(), settings.size, &partial_path(output));
+        })
+    };
+
+    let out_of_time = || settings.time_budget.is_some_and(|b| started.elapsed() >= b);
+    let mut last_save = Instant::now();
 
-            // refRACted ray cast
-            let refracted_ = reflective_ray_cast(
-                collision.refracted_ray(DEFAULT_JITTER),
-                scene,
-                lights,
-                depth + 1,
+    let region = renderer.region();
+    let tiles = tiles(region, settings.tile_size, settings.tile_order);
+
+    while renderer.pending(&accumulator, &region) > 0 && !out_of_time() {
+        for tile in tiles.iter() {
+            if out_of_time() {
+                break;
+            }
+            stats::timed("render", || renderer.sample_tile(&mut accumulator, tile));
+        }
+
+        if last_save.elapsed() >= settings.save_interval {
+            save_progress(&accumulator);
+            last_save = Instant::now();
+            println!(
+                "{} pixels need more samples after {:.1?}",
+                renderer.pending(&accumulator, &region),
+                started.elapsed()
             );
+        }
+    }
 
-            let (diff, spec) = get_light_adjustments(&collision, scene, lights);
+    let pending = renderer.pending(&accumulator, &region);
+    if pending > 0 {
+        save_progress(&accumulator);
+        println!(
+            "Out of time with {} pixels short of their samples, the image so far is in {}",
+            pending,
+            partial_path(output).display()
+        );
+        report_stats(settings, output);
+        return Ok(false);
+    }
 
-            collision
-                .material
-                .adjust_light(diff, spec)
-                .mix_reflection(reflected_)
-                .mix_refraction(refracted_)
+    renderer.finish(accumulator.average(), accumulator.sample_counts(), output);
+    // The image is done, there is nothing left to resume
+    for leftover in std::iter::once(partial_path(output)).chain(checkpoint) {
+        if leftover.exists() {
+            fs::remove_file(&leftover).expect("Failed removing the partial render");
         }
-        Some(intersection) => {
-            let (diff, spec) = get_light_adjustments(&intersection, scene, lights);
-            intersection.material.adjust_light(diff, spec)
+    }
+    report_stats(settings, output);
+    Ok(true)
+}
+
+/// Print what the render spent its time on. With `--stats-json` the numbers are also saved next to `output`, `current.png` gets `current.stats.json`.
+fn report_stats(settings: &RenderSettings, output: &str) {
+    let report = stats::take();
+    println!("{}", report);
+    if settings.stats_json {
+        let path = Path::new(output).with_extension("stats.json");
+        fs::write(path, report.to_json()).expect("Failed saving render statistics");
+    }
+}
+
+/// Render the still image with workers doing the actual work: start with `--coordinator tcp:0.0.0.0:7878` and the usual render flags,
+/// then start workers with `tinyraytracer worker --connect tcp:<coordinator host>:7878`. Workers can join and leave at any time.
+///
+/// The scene description we send to the workers is our command line (without the coordinator flag), they build the same scene and camera from it.
+/// Time budgets and checkpoints are for rendering on a single machine, the coordinator waits for every tile.
+/// A worker that takes longer than `--worker-timeout <seconds>` (10 minutes by default) to render a tile is dropped and its tile goes to another worker.
+fn coordinate_render(args: &[String], endpoint: &str) -> Result<(), String> {
+    let worker_timeout = seconds_arg(args, "--worker-timeout")?.unwrap_or(Duration::from_secs(600));
+    let mut description = vec![];
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        if arg == "--coordinator" || arg == "--worker-timeout" {
+            rest.next();
+        } else {
+            description.push(arg.as_str());
+        }
+    }
+
+    let settings = RenderSettings::from_args(args)?;
+    let (imgx, imgy) = settings.size;
+    let (scene, lights, frame) = stats::timed("scene", || demo_scene(0.));
+    let camera = camera_from_args(args, frame, settings.size)?;
+    let renderer = Renderer::new(&scene, &lights, camera.as_ref(), &settings);
+    let tiles = tiles(renderer.region(), settings.tile_size, settings.tile_order);
+
+    let listener = Endpoint::parse(endpoint)?
+        .listen()
+        .map_err(|e| format!("Failed listening for workers on {}: {}", endpoint, e))?;
+    println!("Waiting for workers on {}", endpoint);
+    let results = stats::timed("workers", || {
+        distributed::coordinate(listener, &description.join("\n"), tiles, worker_timeout)
+    })
+    .map_err(|e| format!("Failed coordinating workers: {}", e))?;
+
+    let mut framebuffer = vec![(0., 0., 0.); (imgx * imgy) as usize];
+    let mut samples = vec![0; (imgx * imgy) as usize];
+    for result in results {
+        let pixels = result.colors.iter().zip(result.samples.iter());
+        for ((i, j), (&color, &n)) in result.tile.pixels().zip(pixels) {
+            let index = (j * imgx + i) as usize;
+            framebuffer[index] = color;
+            samples[index] = n;
         }
-        _ => Material::default(),
     }
+
+    let output = arg_value(args, "--output").unwrap_or(OUTPUT);
+    renderer.finish(framebuffer, &samples, output);
+    report_stats(&settings, output);
+    Ok(())
 }
 
-/// This function builds an image by simulating light rays.
-/// Each pixel of an image is translated into a light ray. For each pixel, the light ray simulation returns the color the pixel should get.
-fn render(spheres: Vec<SceneObject>, lights: Vec<LightSource>, output: &str) {
-    let (imgx, imgy) = CANVAS_WIDTH_HEIGHT;
-    let mut imgbuf = image::ImageBuffer::new(imgx, imgy);
+/// Render tiles for the coordinator at `--connect <endpoint>` until it has all the tiles it needs.
+fn worker(args: &[String]) -> Result<(), String> {
+    let endpoint =
+        Endpoint::parse(arg_value(args, "--connect").ok_or("Workers need --connect <endpoint>")?)?;
+    let stream = endpoint
+        .connect()
+        .map_err(|e| format!("Failed connecting to the coordinator: {}", e))?;
+
+    distributed::work(stream, |description| {
+        // The description is the coordinator's command line, one argument per line
+        let args = std::iter::once("tinyraytracer")
+            .chain(description.lines())
+            .map(String::from)
+            .collect::<Vec<String>>();
+        let settings = RenderSettings::from_args(&args)?;
+        let (imgx, imgy) = settings.size;
+        let (scene, lights, frame) = stats::timed("scene", || demo_scene(0.));
+        let camera = camera_from_args(&args, frame, settings.size)?;
+        let mut accumulator = Accumulator::new(imgx, imgy);
+
+        Ok(move |tile: Rect| {
+            let renderer = Renderer::new(&scene, &lights, camera.as_ref(), &settings);
+            while renderer.pending(&accumulator, &tile) > 0 {
+                stats::timed("render", || renderer.sample_tile(&mut accumulator, &tile));
+            }
+
+            let indices = tile
+                .pixels()
+                .map(|(i, j)| (j * imgx + i) as usize)
+                .collect::<Vec<usize>>();
+            println!("Rendered tile {:?}", tile);
+            TileResult {
+                tile,
+                colors: indices.iter().map(|&index| accumulator.pixel(index)).collect(),
+                samples: indices.iter().map(|&index| accumulator.samples(index)).collect(),
+            }
+        })
+    })
+    .map_err(|e| format!("Stopped working for the coordinator: {}", e))?;
 
-    let width = imgx as f32;
-    let height = imgy as f32;
-    let wh_ratio = width / height;
-    let tan_fov = FRAC_2_PI.tan();
+    // The workers' share of the rays, the coordinator only knows about the first hits
+    println!("{}", stats::take());
+    Ok(())
+}
 
-    // Iterate over the coordinates and pixels of the image
-    for (i, j, pixel) in imgbuf.enumerate_pixels_mut() {
-        let rel_w = (i as f32 + 0.5) / width;
-        let rel_h = (j as f32 + 0.5) / height;
+/// Where the rays went: `current.png` gets `current.samples.png`, black pixels got the fewest rays, white ones the most.
+fn save_sample_heatmap(samples: &[u32], (imgx, imgy): (u32, u32), output: &str) {
+    let min = samples.iter().copied().min().unwrap_or(0);
+    let max = samples.iter().copied().max().unwrap_or(0);
+    let range = (max - min).max(1) as Float;
 
-        let x = (2.0 * rel_w - 1.0) * tan_fov * wh_ratio;
-        let y = -(2.0 * rel_h - 1.0) * tan_fov;
+    // Black to red to yellow to white
+    let heat = |t: Float| {
+        (
+            (3. * t).clamp(0., 1.),
+            (3. * t - 1.).clamp(0., 1.),
+            (3. * t - 2.).clamp(0., 1.),
+        )
+    };
+    let imgbuf = image::ImageBuffer::from_fn(imgx, imgy, |i, j| {
+        let n = samples[(j * imgx + i) as usize];
+        Material::to_pixel(heat((n - min) as Float / range))
+    });
 
-        let dir = Vec3::new((x, y, -1.0)).normalized();
+    let path = Path::new(output).with_extension("samples.png");
+    imgbuf.save(path).expect("Failed saving sample heatmap");
+    println!("Samples per pixel between {} and {}", min, max);
+}
+
+/// Everything about rendering an image besides the scene and the camera
+struct RenderSettings {
+    /// Width and height of the image in pixels
+    size: (u32, u32),
+    /// Rays per pixel to stop at
+    samples_per_pixel: u32,
+    /// Stop sampling a pixel once its error estimate is below this
+    adaptive_threshold: Option<f32>,
+    /// Rays every pixel gets before we trust its error estimate
+    min_samples: u32,
+    /// Only render this part of the image
+    crop: Option<Rect>,
+    tile_size: u32,
+    tile_order: TileOrder,
+    /// Stop once the render took this long, even if not all the samples are in
+    time_budget: Option<Duration>,
+    /// Save the accumulated samples next to the output, so the render can be resumed
+    checkpoint: bool,
+    /// Continue from the checkpoint if there is one
+    resume: bool,
+    /// How often the image so far (and the checkpoint) is saved while rendering
+    save_interval: Duration,
+    /// Extra images saved next to the final image
+    aovs: Vec<Aov>,
+    /// Smooth the noise out of the final image
+    denoise: Option<ATrousFilter>,
+    /// Every random number comes from this seed, the same seed gives the same image
+    seed: u64,
+    /// Save the render statistics next to the output as JSON
+    stats_json: bool,
+}
+
+impl RenderSettings {
+    /// `--size <width>x<height>` picks the image size (1024x768 by default), `--samples <count>` rays per pixel, `--adaptive <threshold>` to stop sampling pixels with a lower error (after `--min-samples <count>` rays), `--time-budget <seconds>` to stop early and `--save-interval <seconds>` to save the image so far in `<output>.partial.png`.
+    /// `--crop <x>,<y>,<width>,<height>` renders only part of the image, `--tile-size <pixels>` and `--tile-order <scanline|spiral|hilbert>` pick the tiles.
+    /// `--checkpoint` keeps the samples in `<output>.checkpoint` until the render is done, `--resume` picks the render up from there.
+    /// `--aov depth,normal,albedo,position,object,material,light` (or `--aov all`) writes extra images
+    /// and `--denoise` runs the denoiser, `--denoise-iterations <count>` picks the number of filter passes. `--seed <number>` picks the random numbers.
+    /// `--stats-json` saves the render statistics as `<output>.stats.json`.
+    /// A flag with a value we can't use is an error, the message says which flag and what it takes.
+    fn from_args(args: &[String]) -> Result<Self, String> {
+        let denoise = if args.iter().any(|a| a == "--denoise") {
+            let filter = ATrousFilter::default();
+            Some(match parse_arg(args, "--denoise-iterations", "a number")? {
+                Some(n) => filter.with_iterations(n),
+                None => filter,
+            })
+        } else {
+            None
+        };
+        let resume = args.iter().any(|a| a == "--resume");
+
+        let size = match arg_value(args, "--size") {
+            Some(size) => {
+                let mut v = size.splitn(2, 'x').map(|v| v.parse::<u32>().ok());
+                match (v.next().flatten(), v.next().flatten()) {
+                    (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+                    _ => {
+                        return Err(format!(
+                            "--size should be <width>x<height>, e.g. 1024x768, not {}",
+                            size
+                        ))
+                    }
+                }
+            }
+            None => CANVAS_WIDTH_HEIGHT,
+        };
+        let crop = match arg_value(args, "--crop") {
+            Some(crop) => {
+                let v = crop
+                    .split(',')
+                    .map(|v| v.parse::<u32>().ok())
+                    .collect::<Option<Vec<u32>>>();
+                let rect = match v.as_deref() {
+                    Some(&[x, y, width, height]) => Rect::new(x, y, width, height),
+                    _ => {
+                        return Err(format!(
+                            "--crop should be <x>,<y>,<width>,<height>, not {}",
+                            crop
+                        ))
+                    }
+                };
+                if rect.intersect(&Rect::new(0, 0, size.0, size.1)).is_none() {
+                    return Err(format!(
+                        "--crop {} is outside the {}x{} image",
+                        crop, size.0, size.1
+                    ));
+                }
+                Some(rect)
+            }
+            None => None,
+        };
 
-        let ray = Ray::new(dir);
+        let settings = Self {
+            size,
+            samples_per_pixel: parse_arg(args, "--samples", "a number")?
+                .unwrap_or(SAMPLES_PER_PIXEL),
+            adaptive_threshold: parse_arg(args, "--adaptive", "a number")?,
+            min_samples: parse_arg(args, "--min-samples", "a number")?
+                .unwrap_or(ADAPTIVE_MIN_SAMPLES),
+            crop,
+            tile_size: parse_arg(args, "--tile-size", "a number")?.unwrap_or(TILE_SIZE),
+            tile_order: match arg_value(args, "--tile-order").unwrap_or("scanline") {
+                "scanline" => TileOrder::Scanline,
+                "spiral" => TileOrder::Spiral,
+                "hilbert" => TileOrder::Hilbert,
+                other => return Err(format!("Unknown tile order {}", other)),
+            },
+            time_budget: seconds_arg(args, "--time-budget")?,
+            checkpoint: resume || args.iter().any(|a| a == "--checkpoint"),
+            resume,
+            save_interval: seconds_arg(args, "--save-interval")?.unwrap_or(SAVE_INTERVAL),
+            aovs: arg_value(args, "--aov").map_or(Ok(vec![]), Aov::parse_list)?,
+            denoise,
+            seed: parse_arg(args, "--seed", "a number")?.unwrap_or(0),
+            stats_json: args.iter().any(|a| a == "--stats-json"),
+        };
+        // Every pixel gets the minimum, adaptive sampling only decides about the rays between the minimum and the maximum
+        if settings.adaptive_threshold.is_some()
+            && settings.samples_per_pixel <= settings.min_samples
+        {
+            return Err(format!(
+                "--adaptive stops pixels between --min-samples ({}) and --samples ({}) rays, use more samples",
+                settings.min_samples, settings.samples_per_pixel
+            ));
+        }
+        Ok(settings)
+    }
 
-        let reflected_material = reflective_ray_cast(ray, &spheres, &lights, 0);
-        *pixel = reflected_material.pixel;
+    /// Does the pixel need another ray?
+    fn needs_sample(&self, accumulator: &Accumulator, index: usize) -> bool {
+        let n = accumulator.samples(index);
+        match self.adaptive_threshold {
+            _ if n >= self.samples_per_pixel => false,
+            Some(threshold) => n < self.min_samples || accumulator.error(index) > threshold,
+            None => true,
+        }
     }
+}
 
-    imgbuf.save(output).expect("Failed saving canvas");
+/// Value following a command line flag, e.g. `arg_value(args, "--projection")` for `--projection fisheye`
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(|v| v.as_str())
 }
 
-struct SphereBuilder {
-    spheres: Vec<Sphere>,
+/// The value following `flag`, parsed. `what` says which values we take, e.g. `parse_arg::<u32>(args, "--samples", "a number")`.
+fn parse_arg<T: FromStr>(args: &[String], flag: &str, what: &str) -> Result<Option<T>, String> {
+    arg_value(args, flag)
+        .map(|v| {
+            v.parse::<T>()
+                .map_err(|_| format!("{} should be {}, not {}", flag, what, v))
+        })
+        .transpose()
+}
+
+/// The time following `flag`, in seconds
+fn seconds_arg(args: &[String], flag: &str) -> Result<Option<Duration>, String> {
+    arg_value(args, flag)
+        .map(|v| {
+            v.parse::<f32>()
+                .ok()
+                .and_then(|s| Duration::try_from_secs_f32(s).ok())
+                .ok_or_else(|| format!("{} should be in seconds, not {}", flag, v))
+        })
+        .transpose()
+}
+
+/// Pick the camera projection with `--projection <perspective|orthographic|fisheye|equirectangular>`, perspective is the default.
+/// `--shutter <seconds>` keeps the shutter open that long, things that move meanwhile are blurred.
+/// The perspective camera is a pinhole camera until `--aperture <radius>` opens up its lens, then only things `--focus-distance <distance>` away are sharp.
+/// `--aperture-blades <count>` makes the lens opening a polygon instead of a disk.
+fn camera_from_args(
+    args: &[String],
+    frame: CameraFrame,
+    (width, height): (u32, u32),
+) -> Result<Box<dyn Camera>, String> {
+    let aspect_ratio = width as Float / height as Float;
+    let shutter = Shutter::new(
+        0.,
+        parse_arg(args, "--shutter", "in seconds")?.unwrap_or(0.),
+    );
+
+    let camera: Box<dyn Camera> = match arg_value(args, "--projection").unwrap_or("perspective") {
+        "perspective" => {
+            let mut camera = ThinLensCamera::from_frame(frame, 2. * FRAC_2_PI, aspect_ratio);
+            if let Some(radius) = parse_arg(args, "--aperture", "a number")? {
+                let focus_distance = parse_arg(args, "--focus-distance", "a number")?.ok_or(
+                    "The lens needs to focus somewhere, pass --focus-distance with --aperture",
+                )?;
+                camera = camera.with_aperture(radius, focus_distance);
+            }
+            if let Some(blades) = parse_arg(args, "--aperture-blades", "a number")? {
+                camera = camera.with_aperture_shape(ApertureShape::Polygon {
+                    blades,
+                    rotation: 0.,
+                });
+            }
+            Box::new(ThinLensCamera { shutter, ..camera })
+        }
+        "orthographic" => Box::new(OrthographicCamera {
+            shutter,
+            ..OrthographicCamera::new(frame, 20., aspect_ratio)
+        }),
+        "fisheye" => Box::new(FisheyeCamera {
+            shutter,
+            ..FisheyeCamera::new(frame, PI, aspect_ratio)
+        }),
+        "equirectangular" => Box::new(EquirectangularCamera {
+            shutter,
+            ..EquirectangularCamera::new(frame)
+        }),
+        other => return Err(format!("Unknown projection {}", other)),
+    };
+    Ok(camera)
 }
 
-impl SphereBuilder {
-    fn new() -> Self {
-        Self { spheres: vec![] }
+/// Render the frames `--frames <first>-<last>` of the demo animation at `--fps` frames per second into `--output-dir`, as `frame_0001.png`, `frame_0002.png` ...
+/// Frames are numbered from 1. Finished frames are skipped, so an interrupted run picks up where it stopped: a frame is only saved under its name once it is done,
+/// and a frame with a checkpoint next to it (from `--checkpoint`) is resumed from there. Pass `--overwrite` to render every frame again from scratch.
+fn animate(args: &[String]) -> Result<(), String> {
+    let (first, last) = match arg_value(args, "--frames") {
+        Some(range) => {
+            let invalid = || format!("--frames should be a range like 1-48, not {}", range);
+            let mut ends = range
+                .splitn(2, '-')
+                .map(|f| f.parse::<u32>().map_err(|_| invalid()));
+            let first = ends.next().unwrap()?;
+            (first, ends.next().transpose()?.unwrap_or(first))
+        }
+        None => (1, 48),
+    };
+    if first < 1 {
+        return Err("Frames are numbered from 1, there is no frame 0".to_string());
     }
+    if first > last {
+        return Err(format!(
+            "The last frame {} comes before the first frame {}",
+            last, first
+        ));
+    }
+    let fps: Float = parse_arg(args, "--fps", "a number")?.unwrap_or(12.);
+    if fps.is_nan() || fps <= 0. {
+        return Err("Frames per second should be more than 0".to_string());
+    }
+    let output_dir = Path::new(arg_value(args, "--output-dir").unwrap_or("frames"));
+    let overwrite = args.iter().any(|a| a == "--overwrite");
+    let mut settings = RenderSettings::from_args(args)?;
+    let (checkpoint, resume) = (settings.checkpoint, settings.resume);
 
-    fn add(mut self, center: (f32, f32, f32), radius: f32, material: Material) -> Self {
-        self.spheres.push(Sphere {
-            center: Vec3::new(center),
-            radius,
-            material,
-        });
-        self
+    fs::create_dir_all(output_dir).map_err(|e| {
+        format!(
+            "Failed creating output directory {}: {}",
+            output_dir.display(),
+            e
+        )
+    })?;
+
+    for frame_number in first..=last {
+        let output = output_dir.join(format!("frame_{:04}.png", frame_number));
+        if output.exists() && !overwrite {
+            println!("Skipping {}, already rendered", output.display());
+            continue;
+        }
+
+        // Frame 1 is at time 0
+        let time = (frame_number - 1) as Float / fps;
+        let (scene, lights, frame) = stats::timed("scene", || demo_scene(time));
+        let camera = camera_from_args(args, frame, settings.size)?;
+
+        settings.resume = resume || (!overwrite && output.with_extension("checkpoint").exists());
+        settings.checkpoint = checkpoint || settings.resume;
+        let finished = render(
+            scene,
+            lights,
+            camera.as_ref(),
+            &settings,
+            output
+                .to_str()
+                .expect("Output path should be valid unicode"),
+        )?;
+        if finished {
+            println!("Rendered {}", output.display());
+        }
     }
+    Ok(())
+}
 
-    fn build(self) -> Vec<Sphere> {
-        self.spheres
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    if let Err(message) = run(&args) {
+        eprintln!("{}", message);
+        std::process::exit(1);
     }
 }
 
-struct LightBuilder {
-    lights: Vec<LightSource>,
+/// Render the still image, or what the first argument asks for: `animate` or `worker`
+fn run(args: &[String]) -> Result<(), String> {
+    match args.get(1).map(|a| a.as_str()) {
+        Some("animate") => return animate(args),
+        Some("worker") => return worker(args),
+        _ => {}
+    }
+    if let Some(endpoint) = arg_value(args, "--coordinator") {
+        return coordinate_render(args, endpoint);
+    }
+
+    let settings = RenderSettings::from_args(args)?;
+    let (scene, lights, frame) = stats::timed("scene", || demo_scene(0.));
+    let camera = camera_from_args(args, frame, settings.size)?;
+
+    render(
+        scene,
+        lights,
+        camera.as_ref(),
+        &settings,
+        arg_value(args, "--output").unwrap_or(OUTPUT),
+    )?;
+    Ok(())
 }
 
-impl LightBuilder {
-    fn new() -> Self {
-        Self { lights: vec![] }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        std::iter::once("tinyraytracer")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect()
     }
 
-    fn add(mut self, center: (f32, f32, f32), intensity: f32) -> Self {
-        self.lights.push(LightSource {
-            position: Vec3::new(center),
-            intensity,
-        });
-        self
+    fn settings(flags: &[&str]) -> Result<RenderSettings, String> {
+        RenderSettings::from_args(&args(flags))
     }
 
-    fn build(self) -> Vec<LightSource> {
-        self.lights
+    /// Pixel 0 always sees the same color, pixel 1 black and white in turns
+    fn accumulator(samples: usize) -> Accumulator {
+        let mut accumulator = Accumulator::new(2, 1);
+        for s in 0..samples {
+            let v = (s % 2) as f32;
+            accumulator.add(0, (0.5, 0.5, 0.5));
+            accumulator.add(1, (v, v, v));
+        }
+        accumulator
     }
-}
 
-fn main() {
-    let w_ivory = (0.6, 0.3, 0.1, 0.0);
-    let w_glass = (0., 0.5, 0.1, 0.8);
-    let w_rubber = (0.9, 0.1, 0.0, 0.0);
-    let w_mirror = (0., 10., 0.8, 0.0);
-
-    let ivory = Material::new((0.4, 0.4, 0.3), w_ivory, 50., 1.0);
-    let glass = Material::new((0.6, 0.7, 0.8), w_glass, 125., 1.5);
-    let red_rubber = Material::new((0.3, 0.1, 0.1), w_rubber, 10., 1.0);
-    let mirror = Material::new((1., 1., 1.), w_mirror, 1425., 1.0);
-
-    let spheres = SphereBuilder::new()
-        .add((-3., -0., -16.), 2.0, ivory)
-        .add((-1., -1.5, -12.), 2.0, glass)
-        .add((1.5, -0.5, -18.), 3.0, red_rubber)
-        .add((7., 5., -18.), 4., mirror)
-        .build();
-
-    let mut scene = spheres
-        .iter()
-        .map(|&v| Box::new(v) as SceneObject)
-        .collect::<Vec<SceneObject>>();
-
-    let plain = Rectangle2D::new(
-        Vec3::new((-8., -4., -8.)),
-        Vec3::new((3.,-4., -16.)),
-        Vec3::new((1., -0.05, 0.)),
-        red_rubber,
-    );
+    #[test]
+    fn adaptive_sampling_stops_converged_pixels() {
+        let settings =
+            settings(&["--size", "2x1", "--samples", "8", "--adaptive", "0.01"]).unwrap();
+        let camera = ThinLensCamera::from_frame(demo_scene(0.).2, 1., 2.);
+        let renderer = Renderer::new(&[], &[], &camera, &settings);
+        let image = Rect::new(0, 0, 2, 1);
 
-    scene.push(Box::new(dbg!(plain)));
+        // Every pixel gets the minimum
+        let few = accumulator(ADAPTIVE_MIN_SAMPLES as usize - 1);
+        assert!(settings.needs_sample(&few, 0) && settings.needs_sample(&few, 1));
+        assert_eq!(renderer.pending(&few, &image), 2);
 
-    let lights = LightBuilder::new()
-        .add((-20., 20., 20.), 1.5)
-        .add((30., 50., -25.), 1.3)
-        .add((30., 20., 30.), 1.3)
-        .build();
+        // Then only the noisy one goes on, up to the maximum
+        let enough = accumulator(ADAPTIVE_MIN_SAMPLES as usize);
+        assert!(!settings.needs_sample(&enough, 0) && settings.needs_sample(&enough, 1));
+        assert_eq!(renderer.pending(&enough, &image), 1);
+        assert_eq!(renderer.pending(&accumulator(8), &image), 0);
+    }
+
+    #[test]
+    fn without_a_threshold_every_pixel_gets_every_sample() {
+        let settings = settings(&["--samples", "8"]).unwrap();
+        assert!(settings.needs_sample(&accumulator(7), 0));
+        assert!(!settings.needs_sample(&accumulator(8), 1));
+    }
+
+    #[test]
+    fn adaptive_needs_more_samples_than_the_minimum() {
+        let error = settings(&["--adaptive", "0.01"]).err().unwrap();
+        assert!(error.contains("--adaptive"), "{}", error);
+        assert!(settings(&["--adaptive", "0.01", "--samples", "5"]).is_ok());
+    }
 
-    render(scene, lights, "static/assets/current.png");
+    /// Bad flags are errors that name the flag, not panics
+    #[test]
+    fn invalid_flags_are_errors() {
+        let invalid = [
+            ("--size", &["--size", "1024"][..]),
+            ("--size", &["--size", "0x768"]),
+            ("--samples", &["--samples", "many"]),
+            ("--crop", &["--crop", "1,2,3"]),
+            ("--crop", &["--size", "64x48", "--crop", "64,0,8,8"]),
+            ("tile order", &["--tile-order", "random"]),
+            ("--time-budget", &["--time-budget", "-1"]),
+            ("--save-interval", &["--save-interval", "soon"]),
+            ("AOV", &["--aov", "depth,colour"]),
+            (
+                "--denoise-iterations",
+                &["--denoise", "--denoise-iterations", "x"],
+            ),
+            ("--seed", &["--seed", "-3"]),
+        ];
+        for (flag, flags) in invalid.iter() {
+            let error = settings(flags).err().unwrap();
+            assert!(error.contains(flag), "{:?} gave {}", flags, error);
+        }
+
+        let camera = |flags: &[&str]| camera_from_args(&args(flags), demo_scene(0.).2, (4, 3));
+        let lens = [
+            "--aperture",
+            "0.5",
+            "--focus-distance",
+            "10",
+            "--shutter",
+            "0.1",
+        ];
+        assert!(camera(&lens).is_ok());
+        let invalid = [
+            ("projection", &["--projection", "stereographic"][..]),
+            ("--focus-distance", &["--aperture", "0.5"]),
+            (
+                "--aperture",
+                &["--aperture", "wide", "--focus-distance", "10"],
+            ),
+            ("--aperture-blades", &["--aperture-blades", "6.5"]),
+            ("--shutter", &["--shutter", "open"]),
+        ];
+        for (flag, flags) in invalid.iter() {
+            let error = camera(flags).err().unwrap();
+            assert!(error.contains(flag), "{:?} gave {}", flags, error);
+        }
+    }
 }
@@ -0,0 +1,755 @@
+//! Modern CPUs can do the same math on several numbers at once ([SIMD](https://en.wikipedia.org/wiki/Single_instruction,_multiple_data)):
+//! with AVX one instruction adds 8 pairs of `f32`s. A single [`Vec3`] only has 3 numbers, not enough to fill a register,
+//! so SIMD pays off when we do the same thing to 8 vectors at once.
+//!
+//! Primary rays of neighbouring pixels are like that: they start at the same point, go in almost the same direction and hit the same objects.
+//! We bundle [`LANES`] of them into a [`RayPacket`] and intersect the whole packet with an object in one go. The packet stores its rays
+//! "structure of arrays" style, all the x coordinates of the origins together, then all the y coordinates and so on, so a row of numbers goes straight into a register.
+//!
+//! Plain vectors work the same way: a [`Vec3Lanes`] stores 8 of them one coordinate at a time, and [`dot_all`], [`cross_all`] and friends
+//! go through a whole slice of those. We check for AVX once per slice, checking once per 8 vectors would cost more than SIMD saves.
+//!
+//! Not every CPU has AVX, so we check at runtime and fall back to plain loops (which the compiler vectorizes as far as it can).
+//! With the `f64` feature a register only fits 4 numbers, and we always use the plain loops.
+//!
+//! Spheres and [triangles](crate::blocks::Triangle) have SIMD kernels, every other object intersects the rays of a packet one by one
+//! (see [`RayCollision::packet_intersect`](crate::blocks::RayCollision::packet_intersect)).
+
+use crate::blocks::Ray;
+use crate::float::Float;
+use crate::vectors::Vec3;
+
+/// Number of rays in a packet, the number of `f32`s in an AVX register
+pub const LANES: usize = 8;
+
+/// One number per ray of a packet
+pub type Lanes = [Float; LANES];
+
+/// Up to [`LANES`] rays, stored one coordinate at a time. Packets with fewer rays repeat the last ray, the results for those lanes are meaningless.
+#[derive(Clone, Copy, Debug)]
+pub struct RayPacket {
+    pub origin: [Lanes; 3],
+    /// Unit norm directions
+    pub direction: [Lanes; 3],
+    pub time: Lanes,
+    pub t_min: Lanes,
+    pub t_max: Lanes,
+    len: usize,
+}
+
+impl RayPacket {
+    pub fn new(rays: &[Ray]) -> Self {
+        assert!(
+            !rays.is_empty() && rays.len() <= LANES,
+            "A packet holds 1 to {} rays, got {}",
+            LANES,
+            rays.len()
+        );
+
+        let mut packet = Self {
+            origin: [[0.; LANES]; 3],
+            direction: [[0.; LANES]; 3],
+            time: [0.; LANES],
+            t_min: [0.; LANES],
+            t_max: [0.; LANES],
+            len: rays.len(),
+        };
+        for lane in 0..LANES {
+            let ray = rays[lane.min(rays.len() - 1)];
+            for axis in 0..3 {
+                packet.origin[axis][lane] = ray.origin[axis];
+                packet.direction[axis][lane] = ray.direction[axis];
+            }
+            packet.time[lane] = ray.time;
+            packet.t_min[lane] = ray.t_min;
+            packet.t_max[lane] = ray.t_max;
+        }
+        packet
+    }
+
+    /// Number of actual rays in the packet
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The ray in `lane`
+    pub fn ray(&self, lane: usize) -> Ray {
+        let component = |v: &[Lanes; 3]| Vec3::new((v[0][lane], v[1][lane], v[2][lane]));
+        Ray {
+            origin: component(&self.origin),
+            direction: component(&self.direction),
+            time: self.time[lane],
+            t_min: self.t_min[lane],
+            t_max: self.t_max[lane],
+        }
+    }
+}
+
+/// Does this CPU have the AVX kernels? Checked once and remembered by the standard library.
+#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+fn has_avx() -> bool {
+    is_x86_feature_detected!("avx")
+}
+
+/// Run the AVX kernel if the CPU has AVX, the one in [`scalar`] otherwise
+macro_rules! dispatch {
+    ($kernel:ident($($arg:expr),*)) => {{
+        #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+        {
+            if has_avx() {
+                // Safe, we just checked that the CPU has AVX
+                return unsafe { avx::$kernel($($arg),*) };
+            }
+        }
+        scalar::$kernel($($arg),*)
+    }};
+}
+
+/// Distance along every ray of the packet to its first hit with the sphere within the ray's range, infinity if it misses.
+/// Same math as [`Sphere::ray_intersect`](crate::blocks::Sphere): if the origin is inside the sphere we get the far side.
+pub fn sphere(packet: &RayPacket, center: Vec3, radius: Float) -> Lanes {
+    dispatch!(sphere(packet, center, radius))
+}
+
+/// Distance along every ray of the packet to the triangle with corner `a` and sides `e1`, `e2` leaving it, infinity if it misses (or the hit is out of range).
+/// Same math as [`Triangle::ray_intersect`](crate::blocks::Triangle).
+pub fn triangle(packet: &RayPacket, a: Vec3, e1: Vec3, e2: Vec3) -> Lanes {
+    dispatch!(triangle(packet, a, e1, e2))
+}
+
+/// [`LANES`] vectors, stored one coordinate at a time like the rays of a [`RayPacket`]. Lane `i` of what [`dot_all`] and friends give
+/// is what the [`Vec3`] operation gives for the vectors in lane `i`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vec3Lanes(pub [Lanes; 3]);
+
+impl Vec3Lanes {
+    /// Fewer than [`LANES`] vectors repeat the last one, like a [`RayPacket`]
+    pub fn new(vectors: &[Vec3]) -> Self {
+        assert!(
+            !vectors.is_empty() && vectors.len() <= LANES,
+            "Vec3Lanes holds 1 to {} vectors, got {}",
+            LANES,
+            vectors.len()
+        );
+        let mut lanes = [[0.; LANES]; 3];
+        for lane in 0..LANES {
+            let v = vectors[lane.min(vectors.len() - 1)];
+            for (axis, coordinates) in lanes.iter_mut().enumerate() {
+                coordinates[lane] = v[axis];
+            }
+        }
+        Self(lanes)
+    }
+
+    /// The vector in `lane`
+    pub fn get(&self, lane: usize) -> Vec3 {
+        Vec3::new((self.0[0][lane], self.0[1][lane], self.0[2][lane]))
+    }
+}
+
+/// Sum of every pair of vectors, see [`Vec3Lanes`]. The slices have the same length.
+pub fn add_all(a: &[Vec3Lanes], b: &[Vec3Lanes]) -> Vec<Vec3Lanes> {
+    assert_eq!(a.len(), b.len(), "Adding different numbers of vectors");
+    dispatch!(add_all(a, b))
+}
+
+/// Dot product of every pair of vectors
+pub fn dot_all(a: &[Vec3Lanes], b: &[Vec3Lanes]) -> Vec<Lanes> {
+    assert_eq!(a.len(), b.len(), "Multiplying different numbers of vectors");
+    dispatch!(dot_all(a, b))
+}
+
+/// Cross product of every pair of vectors
+pub fn cross_all(a: &[Vec3Lanes], b: &[Vec3Lanes]) -> Vec<Vec3Lanes> {
+    assert_eq!(a.len(), b.len(), "Multiplying different numbers of vectors");
+    dispatch!(cross_all(a, b))
+}
+
+/// Every vector scaled to unit length, zero vectors stay zero like with [`Vec3::normalized`]
+pub fn normalize_all(vectors: &[Vec3Lanes]) -> Vec<Vec3Lanes> {
+    dispatch!(normalize_all(vectors))
+}
+
+/// The kernels as plain loops, one lane at a time. This is also the reference the SIMD kernels have to agree with.
+pub mod scalar {
+    use super::{Lanes, RayPacket, Vec3Lanes, LANES};
+    use crate::float::Float;
+    use crate::vectors::Vec3;
+
+    /// `f` of every lane
+    fn map(f: impl Fn(usize) -> Float) -> Lanes {
+        let mut out = [0.; LANES];
+        for (lane, out) in out.iter_mut().enumerate() {
+            *out = f(lane);
+        }
+        out
+    }
+
+    fn map_vectors(f: impl Fn(usize) -> Vec3) -> Vec3Lanes {
+        let mut lanes = [[0.; LANES]; 3];
+        for lane in 0..LANES {
+            let v = f(lane);
+            for (axis, coordinates) in lanes.iter_mut().enumerate() {
+                coordinates[lane] = v[axis];
+            }
+        }
+        Vec3Lanes(lanes)
+    }
+
+    pub fn add_all(a: &[Vec3Lanes], b: &[Vec3Lanes]) -> Vec<Vec3Lanes> {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| map_vectors(|lane| a.get(lane) + b.get(lane)))
+            .collect()
+    }
+
+    pub fn dot_all(a: &[Vec3Lanes], b: &[Vec3Lanes]) -> Vec<Lanes> {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| map(|lane| a.get(lane).dot(&b.get(lane))))
+            .collect()
+    }
+
+    pub fn cross_all(a: &[Vec3Lanes], b: &[Vec3Lanes]) -> Vec<Vec3Lanes> {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| map_vectors(|lane| a.get(lane).cross(&b.get(lane))))
+            .collect()
+    }
+
+    pub fn normalize_all(vectors: &[Vec3Lanes]) -> Vec<Vec3Lanes> {
+        vectors
+            .iter()
+            .map(|v| map_vectors(|lane| v.get(lane).normalized()))
+            .collect()
+    }
+
+    pub fn sphere(packet: &RayPacket, center: Vec3, radius: Float) -> Lanes {
+        let mut t = [Float::INFINITY; LANES];
+        for (lane, t) in t.iter_mut().enumerate() {
+            let ray = packet.ray(lane);
+            // `b` is where the center projects on the ray, `disc` how far the surface is from that point (squared)
+            let oc = ray.origin - center;
+            let b = oc.dot(&ray.direction);
+            let disc = b * b - (oc.dot(&oc) - radius * radius);
+            if disc < 0. {
+                continue;
+            }
+            let h = disc.sqrt();
+            if ray.in_range(-b - h) {
+                *t = -b - h;
+            } else if ray.in_range(-b + h) {
+                *t = -b + h;
+            }
+        }
+        t
+    }
+
+    pub fn triangle(packet: &RayPacket, a: Vec3, e1: Vec3, e2: Vec3) -> Lanes {
+        let mut t = [Float::INFINITY; LANES];
+        for (lane, t) in t.iter_mut().enumerate() {
+            let ray = packet.ray(lane);
+            let p = ray.direction.cross(&e2);
+            let det = e1.dot(&p);
+            if det.abs() < Float::EPSILON {
+                continue;
+            }
+            let inv_det = 1. / det;
+            let s = ray.origin - a;
+            let u = s.dot(&p) * inv_det;
+            let q = s.cross(&e1);
+            let v = ray.direction.dot(&q) * inv_det;
+            let distance = e2.dot(&q) * inv_det;
+            if u >= 0. && v >= 0. && u + v <= 1. && ray.in_range(distance) {
+                *t = distance;
+            }
+        }
+        t
+    }
+}
+
+/// The kernels with AVX intrinsics, 8 lanes per instruction. Every line does what the line with the same name does in [`scalar`],
+/// the branches become masks: we compute both sides and pick per lane with a blend.
+#[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+mod avx {
+    use super::{Lanes, RayPacket, Vec3Lanes};
+    use crate::vectors::Vec3;
+    use std::arch::x86_64::*;
+
+    /// Three registers, the x, y and z coordinates of 8 vectors
+    type Vec3x8 = [__m256; 3];
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn load(v: &[Lanes; 3]) -> Vec3x8 {
+        [
+            _mm256_loadu_ps(v[0].as_ptr()),
+            _mm256_loadu_ps(v[1].as_ptr()),
+            _mm256_loadu_ps(v[2].as_ptr()),
+        ]
+    }
+
+    /// The same vector in every lane
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn splat(v: Vec3) -> Vec3x8 {
+        [
+            _mm256_set1_ps(v.x()),
+            _mm256_set1_ps(v.y()),
+            _mm256_set1_ps(v.z()),
+        ]
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn sub(a: Vec3x8, b: Vec3x8) -> Vec3x8 {
+        [
+            _mm256_sub_ps(a[0], b[0]),
+            _mm256_sub_ps(a[1], b[1]),
+            _mm256_sub_ps(a[2], b[2]),
+        ]
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn dot(a: Vec3x8, b: Vec3x8) -> __m256 {
+        _mm256_add_ps(
+            _mm256_add_ps(_mm256_mul_ps(a[0], b[0]), _mm256_mul_ps(a[1], b[1])),
+            _mm256_mul_ps(a[2], b[2]),
+        )
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn cross(a: Vec3x8, b: Vec3x8) -> Vec3x8 {
+        [
+            _mm256_sub_ps(_mm256_mul_ps(a[1], b[2]), _mm256_mul_ps(a[2], b[1])),
+            _mm256_sub_ps(_mm256_mul_ps(a[2], b[0]), _mm256_mul_ps(a[0], b[2])),
+            _mm256_sub_ps(_mm256_mul_ps(a[0], b[1]), _mm256_mul_ps(a[1], b[0])),
+        ]
+    }
+
+    /// All ones in the lanes where `t_min < t < t_max`
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn in_range(t: __m256, t_min: __m256, t_max: __m256) -> __m256 {
+        _mm256_and_ps(
+            _mm256_cmp_ps::<_CMP_GT_OQ>(t, t_min),
+            _mm256_cmp_ps::<_CMP_LT_OQ>(t, t_max),
+        )
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn store(v: __m256) -> Lanes {
+        let mut out = [0.; super::LANES];
+        _mm256_storeu_ps(out.as_mut_ptr(), v);
+        out
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn store3(v: Vec3x8) -> Vec3Lanes {
+        Vec3Lanes([store(v[0]), store(v[1]), store(v[2])])
+    }
+
+    // Plain loops rather than iterator adapters: the closures would be separate functions, and might not get AVX
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn add_all(a: &[Vec3Lanes], b: &[Vec3Lanes]) -> Vec<Vec3Lanes> {
+        let mut out = Vec::with_capacity(a.len());
+        for (a, b) in a.iter().zip(b) {
+            let (a, b) = (load(&a.0), load(&b.0));
+            out.push(store3([
+                _mm256_add_ps(a[0], b[0]),
+                _mm256_add_ps(a[1], b[1]),
+                _mm256_add_ps(a[2], b[2]),
+            ]));
+        }
+        out
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn dot_all(a: &[Vec3Lanes], b: &[Vec3Lanes]) -> Vec<Lanes> {
+        let mut out = Vec::with_capacity(a.len());
+        for (a, b) in a.iter().zip(b) {
+            out.push(store(dot(load(&a.0), load(&b.0))));
+        }
+        out
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn cross_all(a: &[Vec3Lanes], b: &[Vec3Lanes]) -> Vec<Vec3Lanes> {
+        let mut out = Vec::with_capacity(a.len());
+        for (a, b) in a.iter().zip(b) {
+            out.push(store3(cross(load(&a.0), load(&b.0))));
+        }
+        out
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn normalize_all(vectors: &[Vec3Lanes]) -> Vec<Vec3Lanes> {
+        let (zero, one) = (_mm256_setzero_ps(), _mm256_set1_ps(1.));
+        let mut out = Vec::with_capacity(vectors.len());
+        for v in vectors {
+            let v = load(&v.0);
+            let length = _mm256_sqrt_ps(dot(v, v));
+            // Zero vectors would divide by zero, they get a length of 1 and stay zero
+            let zero_length = _mm256_cmp_ps::<_CMP_EQ_OQ>(length, zero);
+            let length = _mm256_blendv_ps(length, one, zero_length);
+            out.push(store3([
+                _mm256_div_ps(v[0], length),
+                _mm256_div_ps(v[1], length),
+                _mm256_div_ps(v[2], length),
+            ]));
+        }
+        out
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn sphere(packet: &RayPacket, center: Vec3, radius: f32) -> Lanes {
+        let zero = _mm256_setzero_ps();
+        let origin = load(&packet.origin);
+        let direction = load(&packet.direction);
+        let t_min = _mm256_loadu_ps(packet.t_min.as_ptr());
+        let t_max = _mm256_loadu_ps(packet.t_max.as_ptr());
+
+        let oc = sub(origin, splat(center));
+        let b = dot(oc, direction);
+        let c = _mm256_sub_ps(dot(oc, oc), _mm256_set1_ps(radius * radius));
+        let disc = _mm256_sub_ps(_mm256_mul_ps(b, b), c);
+        let h = _mm256_sqrt_ps(_mm256_max_ps(disc, zero));
+
+        let near = _mm256_sub_ps(_mm256_sub_ps(zero, b), h);
+        let far = _mm256_add_ps(_mm256_sub_ps(zero, b), h);
+
+        // Start with a miss everywhere, then the far hit where it's in range, then the near hit where it's in range
+        let mut t = _mm256_set1_ps(f32::INFINITY);
+        t = _mm256_blendv_ps(t, far, in_range(far, t_min, t_max));
+        t = _mm256_blendv_ps(t, near, in_range(near, t_min, t_max));
+        let hit = _mm256_cmp_ps::<_CMP_GE_OQ>(disc, zero);
+        store(_mm256_blendv_ps(_mm256_set1_ps(f32::INFINITY), t, hit))
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(super) unsafe fn triangle(packet: &RayPacket, a: Vec3, e1: Vec3, e2: Vec3) -> Lanes {
+        let zero = _mm256_setzero_ps();
+        let one = _mm256_set1_ps(1.);
+        let origin = load(&packet.origin);
+        let direction = load(&packet.direction);
+        let t_min = _mm256_loadu_ps(packet.t_min.as_ptr());
+        let t_max = _mm256_loadu_ps(packet.t_max.as_ptr());
+        let (e1, e2) = (splat(e1), splat(e2));
+
+        let p = cross(direction, e2);
+        let det = dot(e1, p);
+        let inv_det = _mm256_div_ps(one, det);
+        let s = sub(origin, splat(a));
+        let u = _mm256_mul_ps(dot(s, p), inv_det);
+        let q = cross(s, e1);
+        let v = _mm256_mul_ps(dot(direction, q), inv_det);
+        let distance = _mm256_mul_ps(dot(e2, q), inv_det);
+
+        // |det| >= epsilon, clearing the sign bit gives the absolute value
+        let abs_det = _mm256_andnot_ps(_mm256_set1_ps(-0.), det);
+        let mut hit = _mm256_cmp_ps::<_CMP_GE_OQ>(abs_det, _mm256_set1_ps(f32::EPSILON));
+        hit = _mm256_and_ps(hit, _mm256_cmp_ps::<_CMP_GE_OQ>(u, zero));
+        hit = _mm256_and_ps(hit, _mm256_cmp_ps::<_CMP_GE_OQ>(v, zero));
+        hit = _mm256_and_ps(hit, _mm256_cmp_ps::<_CMP_LE_OQ>(_mm256_add_ps(u, v), one));
+        hit = _mm256_and_ps(hit, in_range(distance, t_min, t_max));
+        store(_mm256_blendv_ps(_mm256_set1_ps(f32::INFINITY), distance, hit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::{HitPoint, Material, RayCollision, Sphere, Triangle};
+    use crate::sampling::Rng;
+
+    /// How far apart two distances may be, relative to the distance
+    const TOLERANCE: Float = 1e-3;
+
+    fn uniform(rng: &mut Rng, lo: Float, hi: Float) -> Float {
+        lo + (hi - lo) * rng.next_float()
+    }
+
+    fn point(rng: &mut Rng, lo: Float, hi: Float) -> Vec3 {
+        Vec3::new((
+            uniform(rng, lo, hi),
+            uniform(rng, lo, hi),
+            uniform(rng, lo, hi),
+        ))
+    }
+
+    fn material() -> Material {
+        Material::new((1., 1., 1.), (1., 0., 0., 0.), 1., 1.)
+    }
+
+    /// A random range along the ray: often the whole ray, sometimes cut short at one end or both
+    fn with_range(rng: &mut Rng, ray: Ray) -> Ray {
+        let t_min = if rng.next_float() < 0.5 {
+            0.
+        } else {
+            uniform(rng, 0., 4.)
+        };
+        let t_max = if rng.next_float() < 0.5 {
+            Float::INFINITY
+        } else {
+            t_min + uniform(rng, 0., 8.)
+        };
+        ray.set_t_min(t_min).set_t_max(t_max)
+    }
+
+    fn distance(ray: &Ray, hit: HitPoint) -> Float {
+        match hit {
+            HitPoint::Point(p) => (p - ray.origin).l2(),
+            HitPoint::None => Float::INFINITY,
+        }
+    }
+
+    /// `t` is within the tolerance of one end of the ray's range, rounding may put it on either side
+    fn near_range_end(ray: &Ray, t: Float) -> bool {
+        let close = |a: Float, b: Float| (a - b).abs() <= TOLERANCE * a.abs().max(1.);
+        close(t, ray.t_min) || (ray.t_max.is_finite() && close(t, ray.t_max))
+    }
+
+    /// The lanes of the packet agree: both miss, or both hit at about the same distance.
+    /// A hit and a miss only agree if `borderline` says the ray is so close to the edge that rounding decides.
+    fn assert_agree(
+        what: &str,
+        packet: &RayPacket,
+        actual: &[Float],
+        expected: &[Float],
+        borderline: impl Fn(&Ray) -> bool,
+    ) {
+        for lane in 0..packet.len() {
+            let (a, e) = (actual[lane], expected[lane]);
+            let agree = match (a.is_finite(), e.is_finite()) {
+                (false, false) => true,
+                (true, true) => (a - e).abs() <= TOLERANCE * e.abs().max(1.),
+                _ => false,
+            };
+            assert!(
+                agree || borderline(&packet.ray(lane)),
+                "{}: lane {} of {} hits at {}, expected {}",
+                what,
+                lane,
+                packet.len(),
+                a,
+                e
+            );
+        }
+    }
+
+    /// Rays from all over the place towards the sphere, from inside it, and grazing it
+    fn sphere_rays(rng: &mut Rng, center: Vec3, radius: Float) -> Vec<Ray> {
+        let len = 1 + (rng.next_u32() as usize) % LANES;
+        (0..len)
+            .map(|_| {
+                let ray = match rng.next_u32() % 3 {
+                    0 => {
+                        let origin = point(rng, -6., 6.);
+                        let target = center + point(rng, -1.3, 1.3) * radius;
+                        Ray::new(target - origin).set_origin(origin)
+                    }
+                    1 => {
+                        let origin = center + point(rng, -0.5, 0.5) * radius;
+                        Ray::new(point(rng, -1., 1.)).set_origin(origin)
+                    }
+                    _ => {
+                        // Along a tangent, through the point at `radius` from the center
+                        let direction = point(rng, -1., 1.).normalized();
+                        let side = direction.cross(&point(rng, -1., 1.)).normalized();
+                        let origin = center + side * radius - direction * uniform(rng, 1., 5.);
+                        Ray::new(direction).set_origin(origin)
+                    }
+                };
+                with_range(rng, ray)
+            })
+            .collect()
+    }
+
+    fn sphere_borderline(center: Vec3, radius: Float) -> impl Fn(&Ray) -> bool {
+        move |ray: &Ray| {
+            let oc = ray.origin - center;
+            let b = oc.dot(&ray.direction);
+            let closest = (oc - ray.direction * b).l2();
+            let h = (radius * radius - closest * closest).max(0.).sqrt();
+            (closest - radius).abs() <= TOLERANCE * radius
+                || near_range_end(ray, -b - h)
+                || near_range_end(ray, -b + h)
+        }
+    }
+
+    /// Rays towards points in and around the triangle, rays in its plane, and degenerate triangles with all corners on a line
+    fn triangle_case(rng: &mut Rng) -> ((Vec3, Vec3, Vec3), Vec<Ray>) {
+        let a = point(rng, -2., 2.);
+        let b = point(rng, -2., 2.);
+        let c = if rng.next_float() < 0.25 {
+            a + (b - a) * uniform(rng, -1., 2.)
+        } else {
+            point(rng, -2., 2.)
+        };
+
+        let len = 1 + (rng.next_u32() as usize) % LANES;
+        let rays = (0..len)
+            .map(|_| {
+                let (u, v) = (uniform(rng, -0.2, 1.2), uniform(rng, -0.2, 1.2));
+                let target = a + (b - a) * u + (c - a) * v;
+                let ray = if rng.next_float() < 0.125 {
+                    // In the plane of the triangle
+                    let origin =
+                        a + (b - a) * uniform(rng, -2., 2.) + (c - a) * uniform(rng, -2., 2.);
+                    Ray::new(target - origin).set_origin(origin)
+                } else {
+                    let origin = point(rng, -6., 6.);
+                    Ray::new(target - origin).set_origin(origin)
+                };
+                with_range(rng, ray)
+            })
+            .collect();
+        ((a, b, c), rays)
+    }
+
+    fn triangle_borderline((a, b, c): (Vec3, Vec3, Vec3)) -> impl Fn(&Ray) -> bool {
+        move |ray: &Ray| {
+            let (e1, e2) = (b - a, c - a);
+            let p = ray.direction.cross(&e2);
+            let det = e1.dot(&p);
+            if det.abs() < TOLERANCE {
+                return true;
+            }
+            let s = ray.origin - a;
+            let q = s.cross(&e1);
+            let (u, v) = (s.dot(&p) / det, ray.direction.dot(&q) / det);
+            let t = e2.dot(&q) / det;
+            let edge = u.abs().min(v.abs()).min((1. - u - v).abs());
+            edge <= TOLERANCE || near_range_end(ray, t)
+        }
+    }
+
+    /// The scalar kernels are the reference for the SIMD ones, so they have to agree with the objects themselves
+    #[test]
+    fn scalar_kernels_match_ray_intersect() {
+        let mut rng = Rng::new(1);
+        for _ in 0..2000 {
+            let center = point(&mut rng, -2., 2.);
+            let radius = uniform(&mut rng, 0.5, 2.);
+            let sphere = Sphere {
+                center,
+                radius,
+                material: material(),
+            };
+            let rays = sphere_rays(&mut rng, center, radius);
+            let packet = RayPacket::new(&rays);
+            let expected = rays
+                .iter()
+                .map(|ray| distance(ray, sphere.ray_intersect(ray)))
+                .collect::<Vec<Float>>();
+            let actual = scalar::sphere(&packet, center, radius);
+            assert_agree(
+                "sphere",
+                &packet,
+                &actual,
+                &expected,
+                sphere_borderline(center, radius),
+            );
+
+            let ((a, b, c), rays) = triangle_case(&mut rng);
+            let triangle = Triangle::new(a, b, c, material());
+            let packet = RayPacket::new(&rays);
+            let expected = rays
+                .iter()
+                .map(|ray| distance(ray, triangle.ray_intersect(ray)))
+                .collect::<Vec<Float>>();
+            let actual = scalar::triangle(&packet, a, b - a, c - a);
+            assert_agree(
+                "triangle",
+                &packet,
+                &actual,
+                &expected,
+                triangle_borderline((a, b, c)),
+            );
+        }
+    }
+
+    #[cfg(all(target_arch = "x86_64", not(feature = "f64")))]
+    #[test]
+    fn avx_kernels_match_scalar_kernels() {
+        if !has_avx() {
+            return;
+        }
+        let mut rng = Rng::new(2);
+        for _ in 0..2000 {
+            let center = point(&mut rng, -2., 2.);
+            let radius = uniform(&mut rng, 0.5, 2.);
+            let packet = RayPacket::new(&sphere_rays(&mut rng, center, radius));
+            let actual = unsafe { avx::sphere(&packet, center, radius) };
+            let expected = scalar::sphere(&packet, center, radius);
+            assert_agree(
+                "sphere",
+                &packet,
+                &actual,
+                &expected,
+                sphere_borderline(center, radius),
+            );
+
+            let ((a, b, c), rays) = triangle_case(&mut rng);
+            let packet = RayPacket::new(&rays);
+            let actual = unsafe { avx::triangle(&packet, a, b - a, c - a) };
+            let expected = scalar::triangle(&packet, a, b - a, c - a);
+            assert_agree(
+                "triangle",
+                &packet,
+                &actual,
+                &expected,
+                triangle_borderline((a, b, c)),
+            );
+        }
+    }
+
+    /// Every lane of the vector operations is the [`Vec3`] operation on that lane, whichever kernels this CPU runs
+    #[test]
+    fn vector_lanes_match_vec3() {
+        let close = |a: Float, b: Float| (a - b).abs() <= 1e-5 * a.abs().max(1.);
+        let same = |a: Vec3, b: Vec3| (0..3).all(|axis| close(a[axis], b[axis]));
+
+        let mut rng = Rng::new(3);
+        let mut lanes = || {
+            (0..50)
+                .map(|_| {
+                    let vectors = (0..LANES)
+                        .map(|_| point(&mut rng, -4., 4.))
+                        .collect::<Vec<Vec3>>();
+                    Vec3Lanes::new(&vectors)
+                })
+                .collect::<Vec<Vec3Lanes>>()
+        };
+        let (a, b) = (lanes(), lanes());
+        // A zero vector in the middle of a register
+        let mut with_zero = a.clone();
+        with_zero[0].0[0][3] = 0.;
+        with_zero[0].0[1][3] = 0.;
+        with_zero[0].0[2][3] = 0.;
+
+        let sums = add_all(&a, &b);
+        let dots = dot_all(&a, &b);
+        let crosses = cross_all(&a, &b);
+        let normalized = normalize_all(&with_zero);
+        for k in 0..a.len() {
+            for (lane, &dot) in dots[k].iter().enumerate() {
+                let (x, y) = (a[k].get(lane), b[k].get(lane));
+                assert!(same(sums[k].get(lane), x + y));
+                assert!(close(dot, x.dot(&y)));
+                assert!(same(crosses[k].get(lane), x.cross(&y)));
+                let v = with_zero[k].get(lane);
+                assert!(same(normalized[k].get(lane), v.normalized()));
+            }
+        }
+    }
+}
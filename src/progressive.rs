@@ -0,0 +1,266 @@
+//! Rendering with many rays per pixel takes a while. Instead of shooting all the rays of a pixel before moving to the next one,
+//! we go over the whole image in passes, adding one ray to every pixel in each pass. After every pass we have a complete (if noisy) image,
+//! so we can look at it, stop when it is good enough or when we run out of time.
+//!
+//! The [`Accumulator`] keeps the running sums. Saved to a checkpoint file, it lets us stop a render and pick it up later where it stopped.
+//!
+//! Not every pixel needs the same number of rays: a pixel of flat background is done after a couple of rays, the edge of a blurry glass sphere needs many more.
+//! The accumulator also keeps enough to [estimate](https://en.wikipedia.org/wiki/Standard_error) how far each pixel still is from its true color,
+//! so we can spend the rays where they make a difference (adaptive sampling).
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// First bytes of a checkpoint file, so we don't try to resume from some other file
+const CHECKPOINT_MAGIC: &[u8; 8] = b"TRCKPT03";
+
+/// Bytes before the pixels in a checkpoint file: the magic bytes, width, height and the settings fingerprint
+const HEADER_BYTES: usize = 24;
+
+/// Bytes per pixel in a checkpoint file: the color sum, the squared luminance sum and the sample count
+const PIXEL_BYTES: usize = 20;
+
+/// Sum of the colors of all the rays shot through every pixel so far, and how many rays that is.
+/// Pixels are stored row by row.
+#[derive(Clone, Debug)]
+pub struct Accumulator {
+    width: u32,
+    height: u32,
+    sum: Vec<(f32, f32, f32)>,
+    /// Sum of the squared luminance of the rays, for the variance
+    sum_squares: Vec<f32>,
+    samples: Vec<u32>,
+    /// Hash of whatever decides what the samples are (scene, camera, seed, ...), so we don't resume a render with samples of another one
+    fingerprint: u64,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        let pixels = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            sum: vec![(0., 0., 0.); pixels],
+            sum_squares: vec![0.; pixels],
+            samples: vec![0; pixels],
+            fingerprint: 0,
+        }
+    }
+
+    /// Remember what the samples are of, see [`Accumulator::fingerprint`]
+    pub fn with_fingerprint(mut self, fingerprint: u64) -> Self {
+        self.fingerprint = fingerprint;
+        self
+    }
+
+    /// Saved with the checkpoint. A render should only resume from a checkpoint with the fingerprint of its own settings.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Number of rays shot through the pixel
+    pub fn samples(&self, index: usize) -> u32 {
+        self.samples[index]
+    }
+
+    /// Number of rays every pixel has at least
+    pub fn min_samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    pub fn max_samples(&self) -> u32 {
+        self.samples.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Standard error of the pixel's mean luminance: roughly how far the pixel brightness is from where it would end up with infinitely many rays.
+    /// With fewer than two rays we can't tell, so the error is infinite.
+    pub fn error(&self, index: usize) -> f32 {
+        let n = self.samples[index];
+        if n < 2 {
+            return f32::INFINITY;
+        }
+
+        let n = n as f32;
+        let mean = luminance(self.sum[index]) / n;
+        let variance = ((self.sum_squares[index] - n * mean * mean) / (n - 1.)).max(0.);
+        (variance / n).sqrt()
+    }
+
+    pub fn add(&mut self, index: usize, color: (f32, f32, f32)) {
+        let s = &mut self.sum[index];
+        *s = (s.0 + color.0, s.1 + color.1, s.2 + color.2);
+        self.sum_squares[index] += luminance(color).powi(2);
+        self.samples[index] += 1;
+    }
+
+    /// Number of rays shot through each pixel
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.samples
+    }
+
+    /// Average color of the pixel, black if it has no rays yet
+    pub fn pixel(&self, index: usize) -> (f32, f32, f32) {
+        let (r, g, b) = self.sum[index];
+        match self.samples[index] {
+            0 => (0., 0., 0.),
+            n => {
+                let n = n as f32;
+                (r / n, g / n, b / n)
+            }
+        }
+    }
+
+    /// Average color of every pixel
+    pub fn average(&self) -> Vec<(f32, f32, f32)> {
+        (0..self.sum.len()).map(|index| self.pixel(index)).collect()
+    }
+
+    /// Write a checkpoint file: the magic bytes, width, height and fingerprint, then the sums and sample count of every pixel, all little endian.
+    /// We write to a temporary file and move it over the old checkpoint, so stopping halfway through a save doesn't lose the old one.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(HEADER_BYTES + self.sum.len() * PIXEL_BYTES);
+        bytes.extend_from_slice(CHECKPOINT_MAGIC);
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.fingerprint.to_le_bytes());
+        for index in 0..self.sum.len() {
+            let (r, g, b) = self.sum[index];
+            bytes.extend_from_slice(&r.to_le_bytes());
+            bytes.extend_from_slice(&g.to_le_bytes());
+            bytes.extend_from_slice(&b.to_le_bytes());
+            bytes.extend_from_slice(&self.sum_squares[index].to_le_bytes());
+            bytes.extend_from_slice(&self.samples[index].to_le_bytes());
+        }
+
+        let temporary = path.with_extension("tmp");
+        fs::File::create(&temporary)?.write_all(&bytes)?;
+        fs::rename(temporary, path)
+    }
+
+    /// Read a checkpoint written by [`Accumulator::save`]. The size in the header is checked against the file size before we allocate anything,
+    /// so a corrupt header can't make us allocate gigabytes.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut bytes = vec![];
+        fs::File::open(path)?.read_to_end(&mut bytes)?;
+
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < HEADER_BYTES || &bytes[..8] != CHECKPOINT_MAGIC {
+            return Err(invalid("Not a checkpoint file"));
+        }
+
+        // Four bytes starting at `offset`
+        let word = |bytes: &[u8], offset: usize| {
+            [bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]
+        };
+        let width = u32::from_le_bytes(word(&bytes, 8));
+        let height = u32::from_le_bytes(word(&bytes, 12));
+        let fingerprint = u64::from(u32::from_le_bytes(word(&bytes, 16)))
+            | u64::from(u32::from_le_bytes(word(&bytes, 20))) << 32;
+        let expected_len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(PIXEL_BYTES))
+            .and_then(|pixel_bytes| pixel_bytes.checked_add(HEADER_BYTES));
+        if expected_len != Some(bytes.len()) {
+            return Err(invalid(
+                "Checkpoint file size doesn't match the image size in its header",
+            ));
+        }
+
+        let mut accumulator = Self::new(width, height).with_fingerprint(fingerprint);
+        for (index, pixel) in bytes[HEADER_BYTES..].chunks_exact(PIXEL_BYTES).enumerate() {
+            let value = |k: usize| f32::from_le_bytes(word(pixel, k * 4));
+            accumulator.sum[index] = (value(0), value(1), value(2));
+            accumulator.sum_squares[index] = value(3);
+            accumulator.samples[index] = u32::from_le_bytes(word(pixel, 16));
+        }
+
+        Ok(accumulator)
+    }
+}
+
+/// How bright a color looks, the [Rec. 709 luma](https://en.wikipedia.org/wiki/Luma_(video)) weights. Our eyes are most sensitive to green.
+fn luminance((r, g, b): (f32, f32, f32)) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.checkpoint", name, std::process::id()))
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let mut accumulator = Accumulator::new(3, 2).with_fingerprint(0xdead_beef_0123_4567);
+        accumulator.add(0, (1., 0.5, 0.25));
+        accumulator.add(0, (0., 0.5, 1.));
+        accumulator.add(5, (2., 2., 2.));
+
+        let path = temporary_path("round-trip");
+        accumulator.save(&path).unwrap();
+        let loaded = Accumulator::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width(), loaded.height()), (3, 2));
+        assert_eq!(loaded.fingerprint(), 0xdead_beef_0123_4567);
+        assert_eq!(loaded.sample_counts(), accumulator.sample_counts());
+        assert_eq!(loaded.average(), accumulator.average());
+        assert_eq!(loaded.error(0), accumulator.error(0));
+    }
+
+    #[test]
+    fn error_of_the_mean() {
+        let mut accumulator = Accumulator::new(3, 1);
+        assert_eq!(accumulator.error(0), f32::INFINITY);
+        accumulator.add(0, (1., 1., 1.));
+        assert_eq!(accumulator.error(0), f32::INFINITY);
+
+        // Always the same color, nothing left to learn
+        for _ in 0..4 {
+            accumulator.add(1, (0.2, 0.4, 0.6));
+        }
+        assert!(accumulator.error(1) < 1e-3, "{}", accumulator.error(1));
+
+        // Black and white in turns: the sample variance is 1/3, the error of the mean of 4 is sqrt(1/3 / 4)
+        for v in [0., 1., 0., 1.] {
+            accumulator.add(2, (v, v, v));
+        }
+        let error = accumulator.error(2);
+        assert!((error - (1f32 / 12.).sqrt()).abs() < 1e-4, "{}", error);
+
+        // Four times the rays, about half the error
+        for v in [0., 1., 0., 1.].repeat(3) {
+            accumulator.add(2, (v, v, v));
+        }
+        let error = accumulator.error(2);
+        assert!((error - (1f32 / 60.).sqrt()).abs() < 1e-4, "{}", error);
+    }
+
+    /// A header claiming a huge image must not make us allocate it
+    #[test]
+    fn checkpoint_with_wrong_size_is_rejected() {
+        let mut bytes = CHECKPOINT_MAGIC.to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&[0; PIXEL_BYTES]);
+
+        let path = temporary_path("huge");
+        fs::write(&path, &bytes).unwrap();
+        let loaded = Accumulator::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
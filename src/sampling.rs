@@ -0,0 +1,434 @@
+//! Once we shoot more than one ray per pixel we need to decide where in the pixel (and where on the lens) each ray starts.
+//! Purely random positions tend to clump together and leave holes, so we use a [low discrepancy sequence](https://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/)
+//! that spreads the points evenly no matter how many of them we take.
+//!
+//! The sequences give points in the unit square, the rest of this module maps them onto the shapes we actually sample (disks, polygons, hemispheres, cones, triangles).
+//! Where there is a choice we keep evenly spread points evenly spread.
+//!
+//! Monte Carlo estimates divide every sample by the probability density (PDF) of picking it, so every mapping comes with its PDF.
+//! Directions are given in a local frame where the z axis is the normal (or the axis of the cone), use an [`Onb`](crate::vectors::Onb) to take them into the world.
+//!
+//! When we want plain random numbers instead of a sequence there is [`Rng`]. It is seeded, so the same seed gives the same image every time.
+
+use crate::float::consts::{FRAC_PI_4, PI};
+use crate::float::Float;
+use crate::vectors::Vec3;
+
+/// Multiplier of the PCG linear congruential step, from the reference implementation
+const PCG_MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+/// The R_d sequence: point `n` is `fract(shift + n * alpha)`, where `alpha_i = 1 / g^i` and `g` is the unique positive root of `x^(d+1) = x + 1`.
+/// For `d = 1` that's the golden ratio. Each dimension is a different irrational step, so the points never line up.
+pub struct RSequence {
+    alpha: Vec<f64>,
+}
+
+impl RSequence {
+    pub fn new(dimensions: usize) -> Self {
+        // Newton's method for x^(d+1) - x - 1 = 0, starting to the right of the root
+        let d = dimensions as i32;
+        let mut g = 2f64;
+        for _ in 0..32 {
+            g -= (g.powi(d + 1) - g - 1.) / (f64::from(d + 1) * g.powi(d) - 1.);
+        }
+
+        let alpha = (1..=d).map(|i| 1. / g.powi(i)).collect();
+        Self { alpha }
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.alpha.len()
+    }
+
+    /// The `index`-th point, with every coordinate shifted by `shift` (modulo 1).
+    /// Different pixels use different shifts so they don't all get the exact same pattern.
+    // Always in `f64`, which is the same type when we build with the `f64` feature
+    #[cfg_attr(
+        feature = "f64",
+        allow(clippy::useless_conversion, clippy::unnecessary_cast)
+    )]
+    pub fn sample(&self, index: u32, shift: &[Float]) -> Vec<Float> {
+        self.alpha
+            .iter()
+            .zip(shift.iter())
+            .map(|(a, &s)| (f64::from(s) + f64::from(index) * a).fract() as Float)
+            .collect()
+    }
+}
+
+/// Scramble an integer into a number in `[0, 1)`. Used to give every pixel its own shift of the sequence.
+/// This is the [`lowbias32`](https://nullprogram.com/blog/2018/07/31/) integer hash.
+pub fn hash_to_unit(key: u32) -> Float {
+    let mut x = key;
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    (x >> 8) as Float / (1u32 << 24) as Float
+}
+
+/// Scramble a 64 bit integer, the finalizer of [SplitMix64](https://prng.di.unimi.it/splitmix64.c). Seeds that differ in a single bit end up completely different.
+fn mix64(key: u64) -> u64 {
+    let mut z = key.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Scramble a few integers into one, e.g. to tell two sets of render settings apart. Changing any of them or their order gives a completely different hash.
+pub fn hash_u64s(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(mix64(values.len() as u64), |hash, &v| mix64(hash ^ v))
+}
+
+/// Map a point in the unit square to the unit disk, keeping evenly spread points evenly spread.
+/// This is the [concentric mapping](https://www.pbr-book.org/3ed-2018/Monte_Carlo_Integration/2D_Sampling_with_Multidimensional_Transformations#SamplingaUnitDisk)
+/// of Shirley and Chiu, it maps squares around the center to circles around the center.
+pub fn sample_disk(u: (Float, Float)) -> (Float, Float) {
+    let (a, b) = (2. * u.0 - 1., 2. * u.1 - 1.);
+    if a == 0. && b == 0. {
+        return (0., 0.);
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, 2. * FRAC_PI_4 - FRAC_PI_4 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Map a point in the unit square to a regular polygon inscribed in the unit circle, uniformly.
+/// The polygon is a fan of `sides` triangles around the center, the first coordinate picks the triangle and the rest of the point is spread inside it.
+pub fn sample_polygon(u: (Float, Float), sides: u32, rotation: Float) -> (Float, Float) {
+    let sides = sides.max(3);
+    let scaled = u.0 * sides as Float;
+    let triangle = (scaled as u32).min(sides - 1);
+    let along_edge = scaled - triangle as Float;
+
+    let corner = |k: u32| {
+        let angle = rotation + 2. * PI * k as Float / sides as Float;
+        (angle.cos(), angle.sin())
+    };
+    let (c0, c1) = (corner(triangle), corner(triangle + 1));
+
+    // The square root keeps the density uniform, there is more area far from the center
+    let dist = along_edge.sqrt();
+    let t = u.1;
+    (
+        dist * ((1. - t) * c0.0 + t * c1.0),
+        dist * ((1. - t) * c0.1 + t * c1.1),
+    )
+}
+
+/// Uniform over the unit disk: the area is `pi`
+pub fn disk_pdf() -> Float {
+    1. / PI
+}
+
+/// Direction on the hemisphere around `z`, every direction equally likely
+pub fn sample_hemisphere(u: (Float, Float)) -> Vec3 {
+    // On a sphere the height is uniformly distributed (Archimedes' hat-box theorem), so we pick the height and the angle around the axis
+    let z = u.0;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * u.1;
+    Vec3::new((r * phi.cos(), r * phi.sin(), z))
+}
+
+/// The hemisphere has a solid angle of `2 pi`
+pub fn hemisphere_pdf() -> Float {
+    1. / (2. * PI)
+}
+
+/// Direction on the hemisphere around `z`, more likely close to the axis: the density follows the cosine with `z`, like the light a diffuse surface receives.
+/// [Malley's method](https://www.pbr-book.org/3ed-2018/Monte_Carlo_Integration/2D_Sampling_with_Multidimensional_Transformations#Cosine-WeightedHemisphereSampling):
+/// a uniform point on the disk lifted up to the hemisphere.
+pub fn sample_cosine_hemisphere(u: (Float, Float)) -> Vec3 {
+    let (x, y) = sample_disk(u);
+    Vec3::new((x, y, (1. - x * x - y * y).max(0.).sqrt()))
+}
+
+/// `cos_theta` is the `z` of the direction
+pub fn cosine_hemisphere_pdf(cos_theta: Float) -> Float {
+    cos_theta.max(0.) / PI
+}
+
+/// Direction on the whole sphere, every direction equally likely
+pub fn sample_sphere(u: (Float, Float)) -> Vec3 {
+    let z = 1. - 2. * u.0;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * u.1;
+    Vec3::new((r * phi.cos(), r * phi.sin(), z))
+}
+
+/// The sphere has a solid angle of `4 pi`
+pub fn sphere_pdf() -> Float {
+    1. / (4. * PI)
+}
+
+/// Direction inside the cone around `z` whose half angle has the cosine `cos_max`, uniformly.
+/// A spherical light seen from a point covers such a cone, sampling it sends every shadow ray towards the light.
+pub fn sample_cone(u: (Float, Float), cos_max: Float) -> Vec3 {
+    let z = (1. - u.0) + u.0 * cos_max;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * u.1;
+    Vec3::new((r * phi.cos(), r * phi.sin(), z))
+}
+
+/// The cone's solid angle is `2 pi (1 - cos_max)`
+pub fn cone_pdf(cos_max: Float) -> Float {
+    1. / (2. * PI * (1. - cos_max))
+}
+
+/// Point on the triangle `a`, `b`, `c`, uniformly. The square root keeps the density uniform, like for [`sample_polygon`].
+pub fn sample_triangle(u: (Float, Float), a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    let su = u.0.sqrt();
+    let (s, t) = (1. - su, u.1 * su);
+    a + (b - a) * s + (c - a) * t
+}
+
+/// One over the area of the triangle
+pub fn triangle_pdf(a: Vec3, b: Vec3, c: Vec3) -> Float {
+    2. / (b - a).cross(&(c - a)).l2()
+}
+
+/// Random numbers from a seed, [PCG32](https://www.pcg-random.org/) by Melissa O'Neill: a 64 bit [linear congruential generator](https://en.wikipedia.org/wiki/Linear_congruential_generator)
+/// whose state is scrambled into the 32 bit output. It's tiny, fast and the numbers are good, which is all we need. The same seed always gives the same numbers.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+    /// Must be odd, different increments give different streams of numbers
+    increment: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self::with_stream(seed, 0)
+    }
+
+    /// The generator for the pixel with index `pixel`, e.g. to shift the sampling sequence of the pixel.
+    ///
+    /// Sharing one generator between all pixels makes every number depend on how many numbers were drawn before it, so the image changes
+    /// with the number of threads, the tile order, or which machine rendered which tile. Here the numbers only depend on the seed and the pixel,
+    /// the same two give the same numbers whoever asks and whenever they ask. The pixel picks the stream, the seed is scrambled into the starting state.
+    pub fn for_pixel(seed: u64, pixel: u32) -> Self {
+        Self::with_stream(mix64(seed), u64::from(pixel))
+    }
+
+    /// Generators with the same seed but different streams give unrelated numbers
+    pub fn with_stream(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(PCG_MULTIPLIER)
+            .wrapping_add(self.increment);
+        // XSH RR: xorshift the high bits down, then rotate by the top 5 bits
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// Uniform in `[0, 1)`
+    pub fn next_float(&mut self) -> Float {
+        (self.next_u32() >> 8) as Float / (1u32 << 24) as Float
+    }
+
+    /// A point in the unit square, for the sampling functions
+    pub fn next_2d(&mut self) -> (Float, Float) {
+        (self.next_float(), self.next_float())
+    }
+}
+
+/// Where the rays of a pixel start: two dimensions for the position inside the pixel, two for the position on the lens and one for the time.
+/// With a single ray per pixel it goes through the middle of everything. With more, every pixel walks the [`RSequence`] from its own shift,
+/// drawn from [`Rng::for_pixel`], so the same seed gives the same rays no matter who traces them or in which order.
+pub struct PixelSampler {
+    sequence: RSequence,
+    samples_per_pixel: u32,
+    seed: u64,
+}
+
+impl PixelSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            sequence: RSequence::new(5),
+            samples_per_pixel,
+            seed,
+        }
+    }
+
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The point of sample number `s` of the pixel with index `pixel`: pixel x and y, lens u and v, and time, all in `[0, 1)`
+    pub fn sample(&self, pixel: u32, s: u32) -> Vec<Float> {
+        if self.samples_per_pixel == 1 {
+            return vec![0.5; 5];
+        }
+        let mut rng = Rng::for_pixel(self.seed, pixel);
+        let shift = (0..5).map(|_| rng.next_float()).collect::<Vec<Float>>();
+        self.sequence.sample(s, &shift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: u32 = 100_000;
+
+    /// Monte Carlo estimate of the integral of `f` over the domain of `sample`: the mean of `f(x) / pdf(x)`.
+    /// With `f` one everywhere it is the measure of the domain, with `f` one on a part of it the measure of that part.
+    fn integrate<T>(
+        sample: impl Fn((Float, Float)) -> T,
+        pdf: impl Fn(&T) -> Float,
+        f: impl Fn(&T) -> Float,
+    ) -> Float {
+        let mut rng = Rng::new(1);
+        let sum: Float = (0..SAMPLES)
+            .map(|_| {
+                let x = sample(rng.next_2d());
+                f(&x) / pdf(&x)
+            })
+            .sum();
+        sum / SAMPLES as Float
+    }
+
+    fn assert_estimate(estimate: Float, expected: Float) {
+        assert!(
+            (estimate - expected).abs() < 0.02 * expected,
+            "Estimated {}, expected {}",
+            estimate,
+            expected
+        );
+    }
+
+    /// Every sample lands in the domain
+    fn assert_all<T>(sample: impl Fn((Float, Float)) -> T, inside: impl Fn(&T) -> bool) {
+        let mut rng = Rng::new(2);
+        for _ in 0..SAMPLES {
+            let u = rng.next_2d();
+            assert!(inside(&sample(u)), "Sample of {:?} left the domain", u);
+        }
+    }
+
+    fn is_unit(v: &Vec3) -> bool {
+        (v.l2() - 1.).abs() < 1e-4
+    }
+
+    #[test]
+    fn disk_is_covered_uniformly() {
+        assert_all(sample_disk, |&(x, y)| x * x + y * y <= 1. + 1e-5);
+        assert_estimate(integrate(sample_disk, |_| disk_pdf(), |_| 1.), PI);
+        let center = |&(x, y): &(Float, Float)| (x * x + y * y < 0.25) as u8 as Float;
+        assert_estimate(integrate(sample_disk, |_| disk_pdf(), center), PI / 4.);
+    }
+
+    #[test]
+    fn polygon_is_covered_uniformly() {
+        let (sides, rotation) = (6, 0.3);
+        let sample = |u| sample_polygon(u, sides, rotation);
+        let angle = 2. * PI / sides as Float;
+        let area = sides as Float / 2. * angle.sin();
+        // Distance from the center to the middle of the edges
+        let apothem = (angle / 2.).cos();
+
+        assert_all(sample, |&(x, y)| {
+            (0..sides).all(|k| {
+                let middle = rotation + angle * (k as Float + 0.5);
+                x * middle.cos() + y * middle.sin() <= apothem + 1e-5
+            })
+        });
+        let center = |&(x, y): &(Float, Float)| (x * x + y * y < 0.25) as u8 as Float;
+        assert_estimate(integrate(sample, |_| 1. / area, center), PI / 4.);
+    }
+
+    #[test]
+    fn hemisphere_is_covered_uniformly() {
+        assert_all(sample_hemisphere, |d| is_unit(d) && d.z() >= 0.);
+        assert_estimate(
+            integrate(sample_hemisphere, |_| hemisphere_pdf(), |_| 1.),
+            2. * PI,
+        );
+        // The cap above 60 degrees of latitude has half the hemisphere's solid angle
+        let cap = |d: &Vec3| (d.z() > 0.5) as u8 as Float;
+        assert_estimate(integrate(sample_hemisphere, |_| hemisphere_pdf(), cap), PI);
+    }
+
+    #[test]
+    fn cosine_hemisphere_follows_its_pdf() {
+        let pdf = |d: &Vec3| cosine_hemisphere_pdf(d.z());
+        assert_all(sample_cosine_hemisphere, |d| is_unit(d) && d.z() >= 0.);
+        assert_estimate(integrate(sample_cosine_hemisphere, pdf, |_| 1.), 2. * PI);
+        assert_estimate(integrate(sample_cosine_hemisphere, pdf, |d| d.z()), PI);
+    }
+
+    #[test]
+    fn sphere_is_covered_uniformly() {
+        assert_all(sample_sphere, is_unit);
+        assert_estimate(integrate(sample_sphere, |_| sphere_pdf(), |_| 1.), 4. * PI);
+        let cap = |d: &Vec3| (d.z() > 0.5) as u8 as Float;
+        assert_estimate(integrate(sample_sphere, |_| sphere_pdf(), cap), PI);
+    }
+
+    #[test]
+    fn cone_is_covered_uniformly() {
+        let cos_max = 0.8;
+        let sample = |u| sample_cone(u, cos_max);
+        assert_all(sample, |d| is_unit(d) && d.z() >= cos_max - 1e-5);
+        assert_estimate(
+            integrate(sample, |_| cone_pdf(cos_max), |_| 1.),
+            2. * PI * (1. - cos_max),
+        );
+        let inner = |d: &Vec3| (d.z() > 0.9) as u8 as Float;
+        assert_estimate(
+            integrate(sample, |_| cone_pdf(cos_max), inner),
+            2. * PI * 0.1,
+        );
+    }
+
+    #[test]
+    fn triangle_is_covered_uniformly() {
+        let (a, b, c) = (
+            Vec3::new((1., 0., 2.)),
+            Vec3::new((4., 1., 2.)),
+            Vec3::new((2., 3., -1.)),
+        );
+        let sample = |u| sample_triangle(u, a, b, c);
+        let pdf = |_: &Vec3| triangle_pdf(a, b, c);
+        let area = (b - a).cross(&(c - a)).l2() / 2.;
+
+        // Barycentric coordinates of the point, all of them between 0 and 1 inside the triangle
+        let normal = (b - a).cross(&(c - a));
+        let barycentric = |p: &Vec3| {
+            let weight = |from: Vec3, to: Vec3| {
+                (to - from).cross(&(*p - from)).dot(&normal) / normal.dot(&normal)
+            };
+            (weight(b, c), weight(c, a), weight(a, b))
+        };
+        assert_all(sample, |p| {
+            let (wa, wb, wc) = barycentric(p);
+            [wa, wb, wc].iter().all(|&w| w >= -1e-4) && (wa + wb + wc - 1.).abs() < 1e-4
+        });
+        assert_estimate(integrate(sample, pdf, |_| 1.), area);
+        // The corner at `a` cut halfway along its edges is a quarter of the triangle
+        let corner = |p: &Vec3| (barycentric(p).0 > 0.5) as u8 as Float;
+        assert_estimate(integrate(sample, pdf, corner), area / 4.);
+    }
+}
@@ -0,0 +1,387 @@
+//! Some shapes are much easier to describe by how far a point is from them than by where a ray hits them.
+//! A [signed distance field](https://iquilezles.org/articles/distfunctions/) (SDF) is a function that takes a point and returns the distance to the closest surface,
+//! negative when the point is inside. Blending two shapes, repeating a shape forever or twisting it are a couple of lines with distance functions,
+//! and a nightmare with ray equations.
+//!
+//! To find where a ray hits the surface we use [sphere tracing](https://github.com/ssloy/tinyraymarcher/wiki): if the closest surface is `d` units away,
+//! we can safely walk `d` units along the ray without passing through anything. We keep walking until we are close enough to the surface.
+
+use crate::blocks::{Aabb, HitPoint, Material, Ray, RayCollision, Span};
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::stats::Primitive;
+use crate::vectors::Vec3;
+
+/// Anything that can tell how far a point is from its surface (negative inside).
+pub trait DistanceField {
+    fn distance(&self, p: Vec3) -> Float;
+}
+
+/// Any closure from a point to a distance is a distance field
+impl<F: Fn(Vec3) -> Float> DistanceField for F {
+    fn distance(&self, p: Vec3) -> Float {
+        self(p)
+    }
+}
+
+/// Distance fields built out of basic shapes and operations on them. Shapes are centered at the origin, use [`SdfExpr::translate`] to move them.
+#[derive(Clone, Debug)]
+pub enum SdfExpr {
+    Sphere {
+        radius: Float,
+    },
+    /// Box with sides parallel to the axes, `half_extents` is the distance from the center to the sides
+    Box {
+        half_extents: Vec3,
+    },
+    /// Torus lying in the xz plane (the hole is along the y axis)
+    Torus {
+        major_radius: Float,
+        minor_radius: Float,
+    },
+    Translate {
+        offset: Vec3,
+        expr: Box<SdfExpr>,
+    },
+    Union(Box<SdfExpr>, Box<SdfExpr>),
+    /// Union that melts the two shapes together where they are closer than `k`
+    SmoothUnion {
+        a: Box<SdfExpr>,
+        b: Box<SdfExpr>,
+        k: Float,
+    },
+    /// Infinite copies of the shape, one every `period` units along each axis (a period of `0` means no repetition along that axis)
+    Repeat {
+        period: Vec3,
+        expr: Box<SdfExpr>,
+    },
+    /// Rotate each horizontal slice of the shape around the y axis by `rate` radians per unit of height
+    Twist {
+        rate: Float,
+        expr: Box<SdfExpr>,
+    },
+}
+
+impl SdfExpr {
+    pub fn sphere(radius: Float) -> Self {
+        SdfExpr::Sphere { radius }
+    }
+
+    pub fn cuboid(half_extents: Vec3) -> Self {
+        SdfExpr::Box { half_extents }
+    }
+
+    pub fn torus(major_radius: Float, minor_radius: Float) -> Self {
+        SdfExpr::Torus {
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn translate(self, offset: Vec3) -> Self {
+        SdfExpr::Translate {
+            offset,
+            expr: Box::new(self),
+        }
+    }
+
+    pub fn union(self, other: SdfExpr) -> Self {
+        SdfExpr::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfExpr, k: Float) -> Self {
+        SdfExpr::SmoothUnion {
+            a: Box::new(self),
+            b: Box::new(other),
+            k,
+        }
+    }
+
+    pub fn repeat(self, period: Vec3) -> Self {
+        SdfExpr::Repeat {
+            period,
+            expr: Box::new(self),
+        }
+    }
+
+    pub fn twist(self, rate: Float) -> Self {
+        SdfExpr::Twist {
+            rate,
+            expr: Box::new(self),
+        }
+    }
+}
+
+impl DistanceField for SdfExpr {
+    fn distance(&self, p: Vec3) -> Float {
+        match self {
+            SdfExpr::Sphere { radius } => p.l2() - radius,
+            SdfExpr::Box { half_extents } => {
+                // How far outside the box we are along each axis (negative if inside the slab)
+                let q = Vec3::new((
+                    p.x().abs() - half_extents.x(),
+                    p.y().abs() - half_extents.y(),
+                    p.z().abs() - half_extents.z(),
+                ));
+                let outside = q.max(&Vec3::orig()).l2();
+                let inside = q.x().max(q.y()).max(q.z()).min(0.);
+                outside + inside
+            }
+            SdfExpr::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x().powf(2.) + p.z().powf(2.)).sqrt() - major_radius;
+                (ring.powf(2.) + p.y().powf(2.)).sqrt() - minor_radius
+            }
+            SdfExpr::Translate { offset, expr } => expr.distance(p - *offset),
+            SdfExpr::Union(a, b) => a.distance(p).min(b.distance(p)),
+            SdfExpr::SmoothUnion { a, b, k } => {
+                // Polynomial smooth minimum https://iquilezles.org/articles/smin/
+                let (d1, d2) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (d2 - d1) / k).clamp(0., 1.);
+                d2 * (1. - h) + d1 * h - k * h * (1. - h)
+            }
+            SdfExpr::Repeat { period, expr } => {
+                let wrap = |v: Float, period: Float| {
+                    if period == 0. {
+                        v
+                    } else {
+                        v - period * (v / period).round()
+                    }
+                };
+                expr.distance(Vec3::new((
+                    wrap(p.x(), period.x()),
+                    wrap(p.y(), period.y()),
+                    wrap(p.z(), period.z()),
+                )))
+            }
+            SdfExpr::Twist { rate, expr } => {
+                let (s, c) = (rate * p.y()).sin_cos();
+                expr.distance(Vec3::new((
+                    c * p.x() - s * p.z(),
+                    p.y(),
+                    s * p.x() + c * p.z(),
+                )))
+            }
+        }
+    }
+}
+
+/// An object defined by a distance field, intersected by sphere tracing.
+pub struct SdfObject<F: DistanceField> {
+    pub field: F,
+    pub material: Material,
+    /// We are on the surface when the distance drops below this
+    pub epsilon: Float,
+    /// Give up after this many steps along the ray
+    pub max_steps: u32,
+    /// Give up after walking this far along the ray
+    pub max_distance: Float,
+    /// Fraction of the distance we walk at every step. Twisting and other distortions make the field overestimate distances, a value below `1` keeps us from walking through the surface
+    pub step_scale: Float,
+    /// Optional box around the surface. Rays that miss it are skipped and the march starts where a ray enters it
+    pub bounds: Option<Aabb>,
+}
+
+impl<F: DistanceField> SdfObject<F> {
+    pub fn new(field: F, material: Material) -> Self {
+        Self {
+            field,
+            material,
+            epsilon: 1e-4,
+            max_steps: 256,
+            max_distance: 100.,
+            step_scale: 1.,
+            bounds: None,
+        }
+    }
+
+    pub fn epsilon(mut self, epsilon: Float) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn max_distance(mut self, max_distance: Float) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    pub fn step_scale(mut self, step_scale: Float) -> Self {
+        self.step_scale = step_scale;
+        self
+    }
+
+    pub fn bounds(mut self, bounds: Aabb) -> Self {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    /// Part of the ray worth marching along, `None` if the ray misses the bounds.
+    fn march_range(&self, ray: &Ray) -> Option<(Float, Float)> {
+        match self.bounds {
+            Some(b) => {
+                let (enter, exit) = b.ray_range(ray)?;
+                Some((enter.max(0.), exit.min(self.max_distance)))
+            }
+            None => Some((0., self.max_distance)),
+        }
+    }
+
+    /// Walk along the ray from `t` until we get within epsilon of the surface. Works from inside the object as well, since we walk by the absolute distance.
+    /// A ray that starts on the surface has to move away from it first, otherwise it would hit the surface it starts on.
+    fn march(&self, ray: &Ray, mut t: Float, t_max: Float, steps: &mut u32) -> Option<Float> {
+        let mut left_surface = false;
+        while t <= t_max && *steps < self.max_steps {
+            *steps += 1;
+            let d = self.field.distance(ray.walk_dir(t)).abs();
+            if d < self.epsilon && left_surface {
+                return Some(t);
+            }
+            left_surface |= d >= self.epsilon;
+            t += (d * self.step_scale).max(self.epsilon);
+        }
+        None
+    }
+}
+
+impl<F: DistanceField> RayCollision for SdfObject<F> {
+    fn ray_intersect(&self, ray: &Ray) -> HitPoint {
+        let mut steps = 0;
+        self.march_range(ray)
+            .and_then(|(t0, t1)| self.march(ray, t0.max(ray.t_min), t1.min(ray.t_max), &mut steps))
+            .map_or(HitPoint::None, |t| HitPoint::Point(ray.walk_dir(t)))
+    }
+
+    /// Gradient of the distance field by [central differences](https://en.wikipedia.org/wiki/Finite_difference#Basic_types).
+    fn collision_normal(&self, hit_point: Vec3, _time: Float) -> Vec3 {
+        let h = self.epsilon;
+        let f = |dx: Float, dy: Float, dz: Float| self.field.distance(hit_point + Vec3::new((dx, dy, dz)));
+        Vec3::new((
+            f(h, 0., 0.) - f(-h, 0., 0.),
+            f(0., h, 0.) - f(0., -h, 0.),
+            f(0., 0., h) - f(0., 0., -h),
+        ))
+        .normalized()
+    }
+
+    fn collision_material(&self, _hit_point: Vec3, _time: Float) -> Material {
+        self.material
+    }
+
+    /// Distance fields have no natural parametrization, we use the longitude and latitude of the normal.
+    fn collision_uv(&self, hit_point: Vec3, time: Float) -> (Float, Float) {
+        let n = self.collision_normal(hit_point, time);
+        (
+            0.5 + n.z().atan2(n.x()) / (2. * PI),
+            0.5 + n.y().clamp(-1., 1.).asin() / PI,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    /// Keep marching after every crossing to find the next one. If the march starts inside the object, the first span enters where the march starts.
+    fn ray_intervals(&self, ray: &Ray) -> Vec<Span> {
+        let (mut t, t_max) = match self.march_range(ray) {
+            Some(range) => range,
+            None => return vec![],
+        };
+
+        let mut crossings = vec![];
+        if self.field.distance(ray.walk_dir(t)) < 0. {
+            crossings.push(t);
+        }
+
+        let mut steps = 0;
+        while let Some(hit) = self.march(ray, t, t_max, &mut steps) {
+            crossings.push(hit);
+            t = hit;
+        }
+
+        Span::from_crossings(&crossings)
+    }
+
+    fn primitive(&self) -> Primitive {
+        Primitive::Sdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    /// Distance along a ray from the origin straight down the `-z` axis to the surface, `None` if it misses
+    fn march_down<F: DistanceField>(object: &SdfObject<F>) -> Option<Float> {
+        match object.ray_intersect(&Ray::new(Vec3::new((0., 0., -1.)))) {
+            HitPoint::Point(p) => Some(-p.z()),
+            HitPoint::None => None,
+        }
+    }
+
+    #[test]
+    fn marching_stops_at_the_surface() {
+        let sphere = SdfExpr::sphere(1.).translate(Vec3::new((0., 0., -5.)));
+        let cube = SdfExpr::cuboid(Vec3::new((1., 2., 0.5))).translate(Vec3::new((0., 0., -5.)));
+
+        for (field, distance) in [(sphere, 4.), (cube, 4.5)] {
+            let t = march_down(&SdfObject::new(field, Material::default())).unwrap();
+            assert!((t - distance).abs() < 1e-3, "{} != {}", t, distance);
+        }
+    }
+
+    /// A field that underestimates distances a hundred times takes about a thousand steps to reach the sphere
+    #[test]
+    fn marching_gives_up() {
+        let sphere = SdfObject::new(
+            SdfExpr::sphere(1.).translate(Vec3::new((0., 0., -5.))),
+            Material::default(),
+        )
+        .step_scale(0.01);
+        assert!(march_down(&sphere).is_none());
+        assert!(march_down(&sphere.max_steps(5000)).is_some());
+
+        let far = SdfObject::new(
+            SdfExpr::sphere(1.).translate(Vec3::new((0., 0., -200.))),
+            Material::default(),
+        );
+        assert!(march_down(&far).is_none());
+        assert!(march_down(&far.max_distance(300.)).is_some());
+    }
+
+    /// The differences are over a step of `epsilon`, in `f32` that leaves the normal a few thousandths off
+    #[test]
+    fn normals_are_the_gradient() {
+        let center = Vec3::new((0.5, -0.5, 0.25));
+        let sphere = SdfObject::new(SdfExpr::sphere(1.).translate(center), Material::default());
+        let mut rng = Rng::new(1);
+        for _ in 0..100 {
+            let n = Vec3::new((
+                rng.next_float() - 0.5,
+                rng.next_float() - 0.5,
+                rng.next_float() - 0.5,
+            ))
+            .normalized();
+            let normal = sphere.collision_normal(center + n, 0.);
+            assert!((normal - n).l2() < 5e-3, "{:?} != {:?}", normal, n);
+        }
+
+        // The middle of the faces of a box
+        let cube = SdfObject::new(
+            SdfExpr::cuboid(Vec3::new((1., 1., 1.))),
+            Material::default(),
+        );
+        for axis in [(1., 0., 0.), (0., -1., 0.), (0., 0., 1.)] {
+            let n = Vec3::new(axis);
+            let normal = cube.collision_normal(n, 0.);
+            assert!((normal - n).l2() < 5e-3, "{:?} != {:?}", normal, n);
+        }
+    }
+}
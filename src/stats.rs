@@ -0,0 +1,386 @@
+//! Where does the render time go? While rendering we count the rays we cast (by what they are for), the intersection tests we run
+//! (by the kind of object tested), how deep the paths of the camera rays go and how long every phase of the render takes.
+//! After the render [`take`] hands us a [`RenderStats`] that prints as a summary and can be saved as JSON.
+//!
+//! The counters live in a thread local, so counting is a plain addition, no locks and no atomics.
+//! Every thread counts its own rays; with one thread rendering that's the whole render.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// What a ray was cast for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RayKind {
+    /// From the camera through a pixel
+    Primary,
+    /// From a hit point towards a light, to see whether something is in the way
+    Shadow,
+    Reflection,
+    Refraction,
+    /// From the camera again, once per pixel, to find the surfaces the denoiser's guides and the AOVs are made of
+    FirstHit,
+}
+
+impl RayKind {
+    pub const ALL: [RayKind; 5] = [
+        RayKind::Primary,
+        RayKind::Shadow,
+        RayKind::Reflection,
+        RayKind::Refraction,
+        RayKind::FirstHit,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RayKind::Primary => "primary",
+            RayKind::Shadow => "shadow",
+            RayKind::Reflection => "reflection",
+            RayKind::Refraction => "refraction",
+            RayKind::FirstHit => "first hit",
+        }
+    }
+}
+
+/// The kind of object an intersection test was run against, see [`RayCollision::primitive`](crate::blocks::RayCollision::primitive).
+/// Transformed objects count as the object inside, a CSG object or a signed distance field counts as one test however much work it does inside.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    Plane,
+    Rectangle,
+    Sphere,
+    Disk,
+    Triangle,
+    Cylinder,
+    Cone,
+    Torus,
+    Csg,
+    Sdf,
+    /// Objects defined outside this crate
+    Other,
+}
+
+impl Primitive {
+    pub const ALL: [Primitive; 11] = [
+        Primitive::Plane,
+        Primitive::Rectangle,
+        Primitive::Sphere,
+        Primitive::Disk,
+        Primitive::Triangle,
+        Primitive::Cylinder,
+        Primitive::Cone,
+        Primitive::Torus,
+        Primitive::Csg,
+        Primitive::Sdf,
+        Primitive::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Primitive::Plane => "plane",
+            Primitive::Rectangle => "rectangle",
+            Primitive::Sphere => "sphere",
+            Primitive::Disk => "disk",
+            Primitive::Triangle => "triangle",
+            Primitive::Cylinder => "cylinder",
+            Primitive::Cone => "cone",
+            Primitive::Torus => "torus",
+            Primitive::Csg => "csg",
+            Primitive::Sdf => "sdf",
+            Primitive::Other => "other",
+        }
+    }
+}
+
+/// Everything we counted since the last [`take`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    rays: [u64; RayKind::ALL.len()],
+    tests: [u64; Primitive::ALL.len()],
+    /// Number of camera paths that ended, and the sum of their depths
+    paths: u64,
+    path_depths: u64,
+    /// Wall time of every phase, in the order the phases first ran
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl RenderStats {
+    pub fn rays(&self, kind: RayKind) -> u64 {
+        self.rays[kind as usize]
+    }
+
+    pub fn total_rays(&self) -> u64 {
+        self.rays.iter().sum()
+    }
+
+    pub fn intersection_tests(&self, primitive: Primitive) -> u64 {
+        self.tests[primitive as usize]
+    }
+
+    pub fn total_intersection_tests(&self) -> u64 {
+        self.tests.iter().sum()
+    }
+
+    /// How many surfaces a camera ray hit along its deepest branch (reflection or refraction), on average. A ray that misses everything has depth 0.
+    pub fn average_path_depth(&self) -> f64 {
+        if self.paths == 0 {
+            return 0.;
+        }
+        self.path_depths as f64 / self.paths as f64
+    }
+
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    /// Everything as one JSON object, times are in seconds.
+    /// Ray kinds, primitives and phases are fixed names without quotes or backslashes, so we can write the JSON by hand.
+    pub fn to_json(&self) -> String {
+        let rays = RayKind::ALL
+            .iter()
+            .map(|&k| format!("\"{}\": {}", k.name(), self.rays(k)))
+            .collect::<Vec<String>>();
+        let tests = Primitive::ALL
+            .iter()
+            .filter(|&&p| self.intersection_tests(p) > 0)
+            .map(|&p| format!("\"{}\": {}", p.name(), self.intersection_tests(p)))
+            .collect::<Vec<String>>();
+        let phases = self
+            .phases
+            .iter()
+            .map(|(name, time)| format!("\"{}\": {}", name, time.as_secs_f64()))
+            .collect::<Vec<String>>();
+
+        format!(
+            "{{\n  \"rays\": {{{}}},\n  \"intersection_tests\": {{{}}},\n  \"paths\": {},\n  \"average_path_depth\": {},\n  \"phases\": {{{}}}\n}}\n",
+            rays.join(", "),
+            tests.join(", "),
+            self.paths,
+            self.average_path_depth(),
+            phases.join(", ")
+        )
+    }
+}
+
+/// The printed summary
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Rays cast: {}", self.total_rays())?;
+        for kind in RayKind::ALL {
+            writeln!(f, "  {:<12}{:>14}", kind.name(), self.rays(kind))?;
+        }
+
+        writeln!(f, "Intersection tests: {}", self.total_intersection_tests())?;
+        for primitive in Primitive::ALL {
+            let tests = self.intersection_tests(primitive);
+            if tests > 0 {
+                writeln!(f, "  {:<12}{:>14}", primitive.name(), tests)?;
+            }
+        }
+
+        writeln!(
+            f,
+            "Average path depth: {:.3} over {} camera rays",
+            self.average_path_depth(),
+            self.paths
+        )?;
+
+        let total = self.phases.iter().map(|(_, t)| *t).sum::<Duration>();
+        write!(f, "Wall time: {:.2?}", total)?;
+        for (name, time) in &self.phases {
+            write!(f, "\n  {:<12}{:>14.2?}", name, time)?;
+        }
+        Ok(())
+    }
+}
+
+/// The counters of one thread. Plain cells with a constant start value, so counting doesn't even check whether the thread local was set up.
+struct Counters {
+    rays: [Cell<u64>; RayKind::ALL.len()],
+    tests: [Cell<u64>; Primitive::ALL.len()],
+    /// Objects of every kind in the scene, and the rays tested against all of them since we last added them to `tests`
+    scene: [Cell<u64>; Primitive::ALL.len()],
+    scene_tests: Cell<u64>,
+    paths: Cell<u64>,
+    path_depths: Cell<u64>,
+    /// Deepest hit of the camera path we are tracing
+    path_depth: Cell<u32>,
+    phases: RefCell<Vec<(&'static str, Duration)>>,
+}
+
+thread_local! {
+    static COUNTERS: Counters = const {
+        Counters {
+            rays: [const { Cell::new(0) }; RayKind::ALL.len()],
+            tests: [const { Cell::new(0) }; Primitive::ALL.len()],
+            scene: [const { Cell::new(0) }; Primitive::ALL.len()],
+            scene_tests: Cell::new(0),
+            paths: Cell::new(0),
+            path_depths: Cell::new(0),
+            path_depth: Cell::new(0),
+            phases: RefCell::new(Vec::new()),
+        }
+    };
+}
+
+fn add(counter: &Cell<u64>, n: u64) {
+    counter.set(counter.get() + n);
+}
+
+pub fn count_rays(kind: RayKind, rays: usize) {
+    COUNTERS.with(|c| add(&c.rays[kind as usize], rays as u64));
+}
+
+impl Counters {
+    /// Turn the rays tested against the scene into tests of every kind of object
+    fn add_scene_tests(&self) {
+        let rays = self.scene_tests.take();
+        for (tests, objects) in self.tests.iter().zip(self.scene.iter()) {
+            add(tests, rays * objects.get());
+        }
+    }
+}
+
+/// The kinds of the objects in the scene we are about to render.
+/// Every ray is tested against every object, so [`count_scene_tests`] only counts rays and we multiply them by the objects of every kind.
+pub fn set_scene(primitives: impl Iterator<Item = Primitive>) {
+    COUNTERS.with(|c| {
+        c.add_scene_tests();
+        c.scene.iter().for_each(|objects| objects.set(0));
+        for primitive in primitives {
+            add(&c.scene[primitive as usize], 1);
+        }
+    });
+}
+
+/// `rays` rays were tested against every object of the scene, more than one for a [packet](crate::packet)
+pub fn count_scene_tests(rays: usize) {
+    COUNTERS.with(|c| add(&c.scene_tests, rays as u64));
+}
+
+/// The camera path we are tracing hit a surface at `depth` (the camera ray's hit has depth 1)
+pub fn reached_depth(depth: u32) {
+    COUNTERS.with(|c| c.path_depth.set(c.path_depth.get().max(depth)));
+}
+
+/// The camera path we were tracing is done, count its depth and start the next one
+pub fn end_path() {
+    COUNTERS.with(|c| {
+        add(&c.paths, 1);
+        add(&c.path_depths, u64::from(c.path_depth.replace(0)));
+    });
+}
+
+/// Run `f` and add its wall time to `phase`. A phase that runs more than once (like saving the image so far) adds up.
+pub fn timed<T>(phase: &'static str, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    let elapsed = started.elapsed();
+
+    COUNTERS.with(|c| {
+        let mut phases = c.phases.borrow_mut();
+        match phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, time)) => *time += elapsed,
+            None => phases.push((phase, elapsed)),
+        }
+    });
+    result
+}
+
+/// Everything counted on this thread so far, the counters start again from zero
+pub fn take() -> RenderStats {
+    COUNTERS.with(|c| {
+        c.add_scene_tests();
+        RenderStats {
+            rays: c.rays.each_ref().map(Cell::take),
+            tests: c.tests.each_ref().map(Cell::take),
+            paths: c.paths.take(),
+            path_depths: c.path_depths.take(),
+            phases: c.phases.take(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_hands_over_the_counts_and_starts_again() {
+        count_rays(RayKind::Primary, 3);
+        count_rays(RayKind::Shadow, 2);
+        reached_depth(2);
+        reached_depth(3);
+        end_path();
+        end_path();
+        timed("render", || ());
+        timed("save", || ());
+        timed("render", || ());
+
+        let stats = take();
+        assert_eq!(stats.rays(RayKind::Primary), 3);
+        assert_eq!(stats.rays(RayKind::Shadow), 2);
+        assert_eq!(stats.total_rays(), 5);
+        // The second path hit nothing, the depth of the first one doesn't leak into it
+        assert_eq!(stats.average_path_depth(), 1.5);
+        let phases = stats
+            .phases()
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        assert_eq!(phases, ["render", "save"]);
+
+        assert_eq!(take(), RenderStats::default());
+    }
+
+    #[test]
+    fn scene_tests_count_every_object() {
+        set_scene(
+            [Primitive::Sphere, Primitive::Sphere, Primitive::Plane]
+                .iter()
+                .copied(),
+        );
+        count_scene_tests(4);
+        // Rays tested against the first scene stay tests of its objects
+        set_scene([Primitive::Torus].iter().copied());
+        count_scene_tests(1);
+
+        let stats = take();
+        assert_eq!(stats.intersection_tests(Primitive::Sphere), 8);
+        assert_eq!(stats.intersection_tests(Primitive::Plane), 4);
+        assert_eq!(stats.intersection_tests(Primitive::Torus), 1);
+        assert_eq!(stats.total_intersection_tests(), 13);
+
+        // The scene stays for the next render
+        count_scene_tests(2);
+        assert_eq!(take().intersection_tests(Primitive::Torus), 2);
+    }
+
+    #[test]
+    fn json_has_every_counter() {
+        let mut stats = RenderStats {
+            paths: 4,
+            path_depths: 6,
+            phases: vec![
+                ("scene", Duration::from_millis(250)),
+                ("render", Duration::from_secs(2)),
+            ],
+            ..RenderStats::default()
+        };
+        stats.rays[RayKind::Primary as usize] = 4;
+        stats.rays[RayKind::Shadow as usize] = 7;
+        stats.tests[Primitive::Sphere as usize] = 12;
+
+        assert_eq!(
+            stats.to_json(),
+            r#"{
+  "rays": {"primary": 4, "shadow": 7, "reflection": 0, "refraction": 0, "first hit": 0},
+  "intersection_tests": {"sphere": 12},
+  "paths": 4,
+  "average_path_depth": 1.5,
+  "phases": {"scene": 0.25, "render": 2}
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Instead of going over the image one row at a time we can split it into small rectangles (tiles, or buckets) and render one tile at a time.
+//! Neighbouring pixels see the same objects, so rendering them together keeps the data we need warm in the cache.
+//! Tiles are also the natural unit of work to hand out to several threads or machines, and the order we render them in decides
+//! which part of the image shows up first.
+//!
+//! Rendering only part of the image (a crop window) is the same thing with fewer tiles.
+
+use std::cmp::Ordering;
+
+/// Rectangle of pixels, `(x, y)` is the top left corner
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Part of this rectangle inside `other`, `None` if they don't overlap
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        if right <= x || bottom <= y {
+            return None;
+        }
+        Some(Rect::new(x, y, right - x, bottom - y))
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Pixel coordinates inside the rectangle, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = *self;
+        (y..y + height).flat_map(move |j| (x..x + width).map(move |i| (i, j)))
+    }
+}
+
+/// Order to render the tiles in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TileOrder {
+    /// Row by row, left to right, like reading a page
+    Scanline,
+    /// From the center of the image outwards, the middle of the image is usually what we care about most
+    Spiral,
+    /// Along a [Hilbert curve](https://en.wikipedia.org/wiki/Hilbert_curve), consecutive tiles are next to each other (apart from jumps over the parts of the curve outside a non square image)
+    Hilbert,
+}
+
+/// Split `region` into tiles of `tile_size` x `tile_size` pixels (smaller at the right and bottom edges) in the given order.
+pub fn tiles(region: Rect, tile_size: u32, order: TileOrder) -> Vec<Rect> {
+    let tile_size = tile_size.max(1);
+    let columns = region.width.div_ceil(tile_size);
+    let rows = region.height.div_ceil(tile_size);
+
+    let mut grid = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect::<Vec<(u32, u32)>>();
+
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            // Ring around the center tile first, then the angle around the center
+            let center = ((columns as f32 - 1.) / 2., (rows as f32 - 1.) / 2.);
+            let key = |&(column, row): &(u32, u32)| {
+                let (dx, dy) = (column as f32 - center.0, row as f32 - center.1);
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            grid.sort_by(|a, b| {
+                let (ka, kb) = (key(a), key(b));
+                ka.partial_cmp(&kb).unwrap_or(Ordering::Equal)
+            });
+        }
+        TileOrder::Hilbert => {
+            let side = columns.max(rows).next_power_of_two();
+            grid.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+        }
+    }
+
+    grid.into_iter()
+        .map(|(column, row)| {
+            let x = region.x + column * tile_size;
+            let y = region.y + row * tile_size;
+            Rect::new(
+                x,
+                y,
+                tile_size.min(region.x + region.width - x),
+                tile_size.min(region.y + region.height - y),
+            )
+        })
+        .collect()
+}
+
+/// Distance along the Hilbert curve filling a `side` x `side` grid (`side` is a power of two) of the cell `(x, y)`.
+/// This is the iterative `xy2d` from the wikipedia article: at every scale we find the quadrant and rotate so the curve inside it starts in the corner.
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0u64;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        d += u64::from(s) * u64::from(s) * u64::from((3 * rx) ^ ry);
+
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [TileOrder; 3] = [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert];
+
+    /// Every pixel of `region` is in exactly one tile, and no tile reaches outside it
+    fn assert_covers_once(region: Rect, tile_size: u32, order: TileOrder) {
+        let tiles = tiles(region, tile_size, order);
+        let mut seen = vec![0u32; (region.width * region.height) as usize];
+        for tile in &tiles {
+            assert!(tile.width > 0 && tile.height > 0, "Empty tile {:?}", tile);
+            assert_eq!(
+                tile.intersect(&region),
+                Some(*tile),
+                "{:?} is outside {:?}",
+                tile,
+                region
+            );
+            for (i, j) in tile.pixels() {
+                seen[((j - region.y) * region.width + i - region.x) as usize] += 1;
+            }
+        }
+        assert!(
+            seen.iter().all(|&n| n == 1),
+            "{:?} tiles of size {} don't cover {:?} exactly once",
+            order,
+            tile_size,
+            region
+        );
+    }
+
+    #[test]
+    fn tiles_cover_the_region_once() {
+        let regions = [
+            Rect::new(0, 0, 64, 64),
+            Rect::new(0, 0, 100, 37),
+            Rect::new(0, 0, 37, 100),
+            Rect::new(13, 7, 50, 29),
+            Rect::new(5, 5, 1, 1),
+        ];
+        for &region in regions.iter() {
+            for &tile_size in [1, 7, 16, 200].iter() {
+                for &order in ORDERS.iter() {
+                    assert_covers_once(region, tile_size, order);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_region_has_no_tiles() {
+        for &order in ORDERS.iter() {
+            assert!(tiles(Rect::new(3, 3, 0, 10), 8, order).is_empty());
+        }
+    }
+
+    #[test]
+    fn spiral_starts_in_the_middle() {
+        let tiles = tiles(Rect::new(0, 0, 50, 50), 10, TileOrder::Spiral);
+        assert_eq!(tiles[0], Rect::new(20, 20, 10, 10));
+    }
+
+    #[test]
+    fn hilbert_tiles_are_neighbours() {
+        let tiles = tiles(Rect::new(0, 0, 64, 64), 8, TileOrder::Hilbert);
+        for pair in tiles.windows(2) {
+            let dx = (pair[0].x as i64 - pair[1].x as i64).abs();
+            let dy = (pair[0].y as i64 - pair[1].y as i64).abs();
+            assert_eq!(
+                dx + dy,
+                8,
+                "{:?} and {:?} are not next to each other",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+}
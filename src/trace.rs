@@ -0,0 +1,435 @@
+//! The heart of the ray tracer: follow a ray through the scene, find what it hits and work out the color it brings back.
+//! Colors come from the lights the hit point sees, plus the rays that bounce off (reflection) and go through (refraction) the surface.
+//!
+//! The command line renderer drives these functions over the pixels of an image, the benchmarks and tests call them directly.
+
+use crate::blocks::*;
+use crate::camera::Camera;
+use crate::float::Float;
+use crate::packet::{RayPacket, LANES};
+use crate::sampling::PixelSampler;
+use crate::stats::{self, RayKind};
+use crate::vectors::Vec3;
+
+/// Rays stop bouncing after this many bounces, the ray that bounced last only sees the lights
+pub const MAX_RAY_BOUNCES: u32 = 4;
+
+pub type SceneObject = Box<dyn RayCollision>;
+
+pub struct CollisionState {
+    pub hit_point: Vec3,
+    /// Shading normal, points back towards where the ray came from
+    pub normal: Vec3,
+    /// Normal as reported by the object, points out of closed objects
+    pub outward_normal: Vec3,
+    pub material: Material,
+    pub ray: Ray,
+    /// Index of the object we hit in the scene
+    pub object: usize,
+}
+
+impl CollisionState {
+    pub fn new(ray: Ray, hit_point: Vec3, normal: Vec3, material: Material, object: usize) -> Self {
+        let facing_normal = if normal.dot(&ray.direction) > 0. {
+            normal.mult(-1.)
+        } else {
+            normal
+        };
+
+        Self {
+            hit_point,
+            normal: facing_normal,
+            outward_normal: normal,
+            material,
+            ray,
+            object,
+        }
+    }
+
+    pub fn reflected_ray(&self) -> Ray {
+        let reflect_dir = self.ray.direction.reflect(self.normal);
+        Ray::leaving_surface(self.hit_point, self.normal, reflect_dir).set_time(self.ray.time)
+    }
+
+    pub fn refracted_ray(&self) -> Ray {
+        let refract_dir = self
+            .ray
+            .direction
+            .refract(self.outward_normal, self.material.refraction_index)
+            .normalized();
+        Ray::leaving_surface(self.hit_point, self.normal, refract_dir).set_time(self.ray.time)
+    }
+}
+
+/// This is the light ray simulation. We go over the objects in the scene and check if our light ray intersect with them.
+/// If there is an intersection, we get the point of intersection and assign the color of the object the ray intersect with.
+/// Next we use the point of intersection and the lighting source in the scene to determine how lighting should affect the color at intersection point.
+pub fn cast_ray(ray: Ray, scene: &[SceneObject]) -> Option<CollisionState> {
+    let mut dist = Float::MAX;
+    let mut hit_point: Option<Vec3> = None;
+    let mut normal = Vec3::orig();
+    let mut material = Material::default();
+    let mut object = 0;
+
+    stats::count_scene_tests(1);
+    for (index, s) in scene.iter().enumerate() {
+        match s.ray_intersect(&ray) {
+            // Hit is the point where our ray hits the sphere
+            HitPoint::Point(p) if (p - ray.origin).l2() < dist => {
+                let n = s.collision_normal(p, ray.time);
+                // We hit the back of a one sided surface, the ray goes through it
+                if s.sidedness() == Sidedness::OneSided && n.dot(&ray.direction) > 0. {
+                    continue;
+                }
+                dist = (p - ray.origin).l2();
+                material = s.collision_material(p, ray.time);
+                normal = n;
+                hit_point = Some(p);
+                object = index;
+            }
+            _ => continue,
+        }
+    }
+
+    // The question mark checks if hit_point is None or Some if it is None then function returns None otherwise it unpacks the Some
+    Some(CollisionState::new(ray, hit_point?, normal, material, object))
+}
+
+/// [`cast_ray`] for up to [`LANES`] rays at once. Primary rays of neighbouring pixels hit the same objects,
+/// so we intersect them as a [packet](crate::packet) and let the SIMD kernels do 8 rays per instruction.
+pub fn cast_packet(rays: &[Ray], scene: &[SceneObject]) -> Vec<Option<CollisionState>> {
+    let packet = RayPacket::new(rays);
+    // Distance, object index and normal of the nearest hit of every ray
+    let mut nearest: Vec<Option<(Float, usize, Vec3)>> = vec![None; rays.len()];
+
+    stats::count_scene_tests(rays.len());
+    for (index, s) in scene.iter().enumerate() {
+        let distances = s.packet_intersect(&packet);
+        for (lane, ray) in rays.iter().enumerate() {
+            let t = distances[lane];
+            if t == Float::INFINITY || nearest[lane].is_some_and(|(d, _, _)| t >= d) {
+                continue;
+            }
+            let n = s.collision_normal(ray.walk_dir(t), ray.time);
+            // We hit the back of a one sided surface, the ray goes through it
+            if s.sidedness() == Sidedness::OneSided && n.dot(&ray.direction) > 0. {
+                continue;
+            }
+            nearest[lane] = Some((t, index, n));
+        }
+    }
+
+    rays.iter()
+        .zip(nearest)
+        .map(|(&ray, hit)| {
+            let (t, object, normal) = hit?;
+            let p = ray.walk_dir(t);
+            let material = scene[object].collision_material(p, ray.time);
+            Some(CollisionState::new(ray, p, normal, material, object))
+        })
+        .collect()
+}
+
+/// Shadow is like a negative light, we "cast a ray of shadow" for a certain hit point and light source.
+/// If the shadow ray hits the object, we know that the object is in shadow and we can't see the light source. ([Github Copilot](https://copilot.github.com/) wrote this line for me, how cool is that?)
+/// The shadow ray stops at the light, objects behind the light don't cast shadows.
+///
+/// Like reflected and refracted rays, shadow rays start a little off the surface. [@ssloy explains](https://github.com/ssloy/tinyraytracer/wiki/Part-1:-understandable-raytracing#step-6-shadows):
+///"Why is that? It's just that our point lies on the surface of the object, and (except for the question of numerical errors) any ray from this point will intersect the object itself."
+pub fn light_is_shadowed(
+    hit_point: Vec3,
+    hit_normal: Vec3,
+    light_position: Vec3,
+    time: Float,
+    scene: &[SceneObject],
+) -> bool {
+    let ldir = (light_position - hit_point).normalized();
+    let ldist = (light_position - hit_point).l2();
+
+    let shadow_ray = Ray::leaving_surface(hit_point, hit_normal, ldir)
+        .set_time(time)
+        .set_t_max(ldist);
+
+    stats::count_rays(RayKind::Shadow, 1);
+    cast_ray(shadow_ray, scene).is_some()
+}
+
+/// Diffuse and specular light each light source adds at the collision point, shadowed lights add nothing.
+pub fn light_contributions(
+    collision: &CollisionState,
+    scene: &[SceneObject],
+    lights: &[LightSource],
+) -> Vec<(Float, Float)> {
+    let (normal, p, ray) = (collision.normal, collision.hit_point, collision.ray);
+
+    lights
+        .iter()
+        .map(|cur| {
+            let ldir = (cur.position - p).normalized();
+            let diff_coef = ldir.dot(&normal).max(0.);
+
+            if light_is_shadowed(p, normal, cur.position, ray.time, scene) {
+                return (0., 0.);
+            }
+
+            let spec_coef = ldir
+                .reflect(normal)
+                .dot(&ray.direction)
+                .max(0.)
+                .powf(collision.material.specular_exponent);
+
+            (cur.intensity * diff_coef, cur.intensity * spec_coef)
+        })
+        .collect()
+}
+
+fn get_light_adjustments(
+    collision: &CollisionState,
+    scene: &[SceneObject],
+    lights: &[LightSource],
+) -> (Float, Float) {
+    light_contributions(collision, scene, lights)
+        .iter()
+        .fold((0., 0.), |(diffuse, specular), (d, s)| {
+            (diffuse + d, specular + s)
+        })
+    // material.adjust_light(diffuse, specular)
+}
+
+/// Our ray of lights don't stay in the same spot. If the hit some reflective material, they bounce off it like a ball.
+/// The is a recursive process. We start with a ray of light and cast it through the scene. Every time a ray hits some object and bounces off, well that's a new ray.
+/// In real life ( I guess ) this process can go on until light losses energy, here we put a hard limit on the number of bounces.
+fn reflective_ray_cast(
+    ray: Ray,
+    kind: RayKind,
+    scene: &[SceneObject],
+    lights: &[LightSource],
+    depth: u32,
+) -> Material {
+    stats::count_rays(kind, 1);
+    shade(cast_ray(ray, scene), scene, lights, depth)
+}
+
+/// Color of a ray that hit (or missed) something, after `depth` bounces
+pub fn shade(
+    collision: Option<CollisionState>,
+    scene: &[SceneObject],
+    lights: &[LightSource],
+    depth: u32,
+) -> Material {
+    if collision.is_some() {
+        stats::reached_depth(depth + 1);
+    }
+
+    match collision {
+        Some(collision) if depth < MAX_RAY_BOUNCES => {
+            // refLECted ray cast
+            let reflected_ = reflective_ray_cast(
+                collision.reflected_ray(),
+                RayKind::Reflection,
+                scene,
+                lights,
+                depth + 1,
+            );
+
+            // refRACted ray cast
+            let refracted_ = reflective_ray_cast(
+                collision.refracted_ray(),
+                RayKind::Refraction,
+                scene,
+                lights,
+                depth + 1,
+            );
+
+            let (diff, spec) = get_light_adjustments(&collision, scene, lights);
+
+            collision
+                .material
+                .adjust_light(diff, spec)
+                .mix_reflection(reflected_)
+                .mix_refraction(refracted_)
+        }
+        Some(intersection) => {
+            let (diff, spec) = get_light_adjustments(&intersection, scene, lights);
+            intersection.material.adjust_light(diff, spec)
+        }
+        _ => Material::default(),
+    }
+}
+
+/// Colors of up to [`LANES`] camera rays. The rays go through the scene as one [packet](crate::packet), then every hit is shaded on its own.
+pub fn trace_packet(
+    rays: &[Ray],
+    scene: &[SceneObject],
+    lights: &[LightSource],
+) -> Vec<(Float, Float, Float)> {
+    if rays.is_empty() {
+        return vec![];
+    }
+
+    stats::count_rays(RayKind::Primary, rays.len());
+    cast_packet(rays, scene)
+        .into_iter()
+        .map(|collision| {
+            let color = shade(collision, scene, lights, 0).color();
+            stats::end_path();
+            color
+        })
+        .collect()
+}
+
+/// The ray of sample number `s` of the pixel `(i, j)` of an image `width` by `height` pixels, `None` if the camera doesn't see that pixel
+pub fn pixel_ray(
+    camera: &dyn Camera,
+    sampler: &PixelSampler,
+    (width, height): (u32, u32),
+    (i, j): (u32, u32),
+    s: u32,
+) -> Option<Ray> {
+    let sample = sampler.sample(j * width + i, s);
+    let rel_w = (i as Float + sample[0]) / width as Float;
+    let rel_h = (j as Float + sample[1]) / height as Float;
+    camera
+        .ray(rel_w, rel_h, (sample[2], sample[3]))
+        .map(|ray| ray.set_time(camera.shutter().time(sample[4])))
+}
+
+/// The simplest image of a scene: every pixel is the average of the sampler's rays through it, row by row from the top left.
+/// Pixels the camera doesn't see stay black. This is what the benchmarks and the golden image tests render,
+/// with one ray per pixel it goes through the center of the pixel at the middle of the shutter interval.
+pub fn render_pixels(
+    scene: &[SceneObject],
+    lights: &[LightSource],
+    camera: &dyn Camera,
+    size: (u32, u32),
+    sampler: &PixelSampler,
+) -> Vec<(Float, Float, Float)> {
+    let (width, height) = size;
+    let pixels = (0..height)
+        .flat_map(|j| (0..width).map(move |i| (i, j)))
+        .collect::<Vec<(u32, u32)>>();
+    let mut sum = vec![(0., 0., 0.); pixels.len()];
+
+    for s in 0..sampler.samples_per_pixel() {
+        let rays = pixels
+            .iter()
+            .map(|&pixel| pixel_ray(camera, sampler, size, pixel, s))
+            .collect::<Vec<Option<Ray>>>();
+
+        for (chunk, sums) in rays.chunks(LANES).zip(sum.chunks_mut(LANES)) {
+            let traced = chunk.iter().flatten().copied().collect::<Vec<Ray>>();
+            let mut colors = trace_packet(&traced, scene, lights).into_iter();
+            for (ray, (r, g, b)) in chunk.iter().zip(sums.iter_mut()) {
+                if ray.is_some() {
+                    let color = colors.next().unwrap();
+                    *r += color.0;
+                    *g += color.1;
+                    *b += color.2;
+                }
+            }
+        }
+    }
+
+    let n = sampler.samples_per_pixel().max(1) as Float;
+    sum.into_iter()
+        .map(|(r, g, b)| (r / n, g / n, b / n))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn sphere(center: (Float, Float, Float), radius: Float) -> SceneObject {
+        Box::new(Sphere {
+            center: Vec3::new(center),
+            radius,
+            material: Material::default(),
+        })
+    }
+
+    /// A one sided wall between the camera and a sphere, facing the camera or facing away
+    fn wall_and_sphere(facing: Float) -> Vec<SceneObject> {
+        vec![
+            Box::new(Sided::one_sided(Plane {
+                normal: Vec3::new((0., 0., facing)),
+                point: Vec3::new((0., 0., -5.)),
+            })),
+            sphere((0., 0., -10.), 1.),
+        ]
+    }
+
+    #[test]
+    fn rays_go_through_the_back_of_one_sided_surfaces() {
+        let rays = [(0., 0., -1.), (0.01, 0., -1.), (0., -0.01, -1.)]
+            .iter()
+            .map(|&d| Ray::new(Vec3::new(d)))
+            .collect::<Vec<Ray>>();
+
+        for &(facing, object) in &[(1., 0), (-1., 1)] {
+            let scene = wall_and_sphere(facing);
+            for &ray in &rays {
+                let hit = cast_ray(ray, &scene).expect("The ray hit nothing");
+                assert_eq!(hit.object, object, "Wall facing {}", facing);
+            }
+            for hit in cast_packet(&rays, &scene) {
+                let hit = hit.expect("The ray hit nothing");
+                assert_eq!(hit.object, object, "Wall facing {}", facing);
+            }
+        }
+    }
+
+    #[test]
+    fn objects_behind_the_light_cast_no_shadow() {
+        let (p, n) = (Vec3::orig(), Vec3::new((0., 0., 1.)));
+        let light = Vec3::new((0., 0., 5.));
+        let shadowed = |center| light_is_shadowed(p, n, light, 0., &[sphere(center, 1.)]);
+
+        assert!(!shadowed((0., 0., 10.)));
+        // Just behind the light
+        assert!(!shadowed((0., 0., 6.01)));
+        assert!(shadowed((0., 0., 2.5)));
+        // Around the light
+        assert!(shadowed((0., 0., 5.)));
+    }
+
+    /// Reflected and shadow rays start on the surface they leave, and must not hit it again.
+    /// The rounding errors grow with the size of the object, so we try a huge and a tiny sphere (the tests run in `f32` and with the `f64` feature).
+    #[test]
+    fn rays_leaving_a_sphere_miss_it() {
+        let mut rng = Rng::new(1);
+        for &radius in &[1e4, 1e-4] {
+            let center = Vec3::new((0., 0., -3. * radius));
+            let scene = vec![sphere((0., 0., -3. * radius), radius)];
+
+            for _ in 0..1000 {
+                // Somewhere on the side of the sphere facing the origin
+                let (x, y) = (rng.next_float() - 0.5, rng.next_float() - 0.5);
+                let target = center + Vec3::new((x, y, 0.)).mult(1.4 * radius);
+                let ray = Ray::new(target);
+                let hit = cast_ray(ray, &scene).expect("Missed the sphere");
+
+                let reflected = hit.reflected_ray();
+                assert!(
+                    cast_ray(reflected, &scene).is_none(),
+                    "The reflection at {:?} hit the sphere of radius {}",
+                    hit.hit_point,
+                    radius
+                );
+
+                // Straight up and almost along the surface
+                let (p, n) = (hit.hit_point, hit.normal);
+                let along = n.cross(&Vec3::new((0., 1., 0.))).normalized();
+                for light in [p + n.mult(radius), p + (along + n.mult(0.01)).mult(radius)] {
+                    assert!(
+                        !light_is_shadowed(p, n, light, 0., &scene),
+                        "The sphere of radius {} shadows itself at {:?}",
+                        radius,
+                        p
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,12 +1,21 @@
 //! We operate in a 3-D coordinate space. This is a helper struct for vector calculations.
-use std::ops::{Add, Sub};
+//!
+//! Along with vectors we have the things that move them around: matrices ([`Mat3`], [`Mat4`]), rotations as quaternions ([`Quat`]),
+//! and [`Transform`], a matrix together with its inverse, which places objects and cameras in the world.
+use crate::float::Float;
+use std::ops::{
+    Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign,
+};
 
 /// 3-D vector, this struct includes functions for conveniently perform
-#[derive(Clone, Copy, Debug)]
+///
+/// Vectors work with the usual operators: `a + b`, `a - b`, `-a`, `a * 2.`, `2. * a`, `a / 2.` and `a * b` multiplies component by component.
+/// `==` compares exactly, floating point math rarely gives exact results so use [`Vec3::approx_eq`] to compare computed vectors.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vec3 {
-    x: f32,
-    y: f32,
-    z: f32,
+    x: Float,
+    y: Float,
+    z: Float,
 }
 
 impl Vec3 {
@@ -20,7 +29,7 @@ impl Vec3 {
     }
 
     /// Create a new vector by specifying its coordinates
-    pub fn new(v: (f32, f32, f32)) -> Self {
+    pub fn new(v: (Float, Float, Float)) -> Self {
         Self {
             x: v.0,
             y: v.1,
@@ -28,12 +37,62 @@ impl Vec3 {
         }
     }
 
+    pub fn x(&self) -> Float {
+        self.x
+    }
+
+    pub fn y(&self) -> Float {
+        self.y
+    }
+
+    pub fn z(&self) -> Float {
+        self.z
+    }
+
+    /// Component-wise minimum of two vectors
+    pub fn min(&self, other: &Self) -> Self {
+        Self {
+            x: self.x.min(other.x),
+            y: self.y.min(other.y),
+            z: self.z.min(other.z),
+        }
+    }
+
+    /// Component-wise maximum of two vectors
+    pub fn max(&self, other: &Self) -> Self {
+        Self {
+            x: self.x.max(other.x),
+            y: self.y.max(other.y),
+            z: self.z.max(other.z),
+        }
+    }
+
+    /// Component-wise absolute value
+    pub fn abs(&self) -> Self {
+        Self {
+            x: self.x.abs(),
+            y: self.y.abs(),
+            z: self.z.abs(),
+        }
+    }
+
+    /// [Linear interpolation](https://en.wikipedia.org/wiki/Linear_interpolation): `self` at `t = 0`, `other` at `t = 1`
+    pub fn lerp(&self, other: &Self, t: Float) -> Self {
+        *self + (*other - *self) * t
+    }
+
+    /// Are all the components within `epsilon` of each other?
+    pub fn approx_eq(&self, other: &Self, epsilon: Float) -> bool {
+        let d = (*self - *other).abs();
+        d.x <= epsilon && d.y <= epsilon && d.z <= epsilon
+    }
+
     /// Get the [L2 norm](https://mathworld.wolfram.com/L2-Norm.html) of the vector.
     /// L_2 norm is the length of the vector, in 3-D space is basically the distance of a vector from the origin.
     /// Let say you have 2 vectors v1 and v2, running (v1-v2).l2() will give you the distance between those points.
     /// That is, the distance between v_1 and v_2 is the length of a vector from v_1 to v_2
-    pub fn l2(&self) -> f32 {
-        (self.x.powf(2.) + self.y.powf(2.) + self.z.powf(2.)).sqrt()
+    pub fn l2(&self) -> Float {
+        self.dot(self).sqrt()
     }
     /// This gives us the [Dot Product](https://mathworld.wolfram.com/DotProduct.html) of 2 vectors.
     /// This is a very useful quantity for projection of vectors.
@@ -41,7 +100,7 @@ impl Vec3 {
     /// What this means is that the dot-product of two vectors is a product of their lengths and the cosine of the angle between them. [Vector Projection](https://en.wikipedia.org/wiki/Vector_projection)
     ///
     /// ![](https://upload.wikimedia.org/wikipedia/commons/thumb/9/98/Projection_and_rejection.png/300px-Projection_and_rejection.png)
-    pub fn dot(&self, other: &Self) -> f32 {
+    pub fn dot(&self, other: &Self) -> Float {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
@@ -77,7 +136,7 @@ impl Vec3 {
     }
 
     /// Multiply a vector by a scalar
-    pub fn mult(&self, v: f32) -> Self {
+    pub fn mult(&self, v: Float) -> Self {
         Self {
             x: self.x * v,
             y: self.y * v,
@@ -91,9 +150,9 @@ impl Vec3 {
         *self - normal.mult(proj_to_normal).mult(2.)
     }
 
-    pub fn refract(&self, normal: Vec3, refract_index: f32) -> Self {
+    pub fn refract(&self, normal: Vec3, refract_index: Float) -> Self {
         let cosi = -self.dot(&normal);
-        let eta_i = 1f32;
+        let eta_i = 1.;
         let eta_t = refract_index;
         let mut n = normal;
 
@@ -114,6 +173,128 @@ impl Vec3 {
     }
 }
 
+impl From<(Float, Float, Float)> for Vec3 {
+    fn from(v: (Float, Float, Float)) -> Self {
+        Self::new(v)
+    }
+}
+
+impl From<[Float; 3]> for Vec3 {
+    fn from(v: [Float; 3]) -> Self {
+        Self::new((v[0], v[1], v[2]))
+    }
+}
+
+impl From<Vec3> for [Float; 3] {
+    fn from(v: Vec3) -> Self {
+        [v.x, v.y, v.z]
+    }
+}
+
+/// `v[0]` is x, `v[1]` is y and `v[2]` is z, anything else panics
+impl Index<usize> for Vec3 {
+    type Output = Float;
+
+    fn index(&self, index: usize) -> &Float {
+        match index {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", index),
+        }
+    }
+}
+
+impl IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, index: usize) -> &mut Float {
+        match index {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Vec3 index out of range: {}", index),
+        }
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+
+    fn neg(self) -> Vec3 {
+        self.mult(-1.)
+    }
+}
+
+impl Mul<Float> for Vec3 {
+    type Output = Vec3;
+
+    fn mul(self, v: Float) -> Vec3 {
+        self.mult(v)
+    }
+}
+
+impl Mul<Vec3> for Float {
+    type Output = Vec3;
+
+    fn mul(self, v: Vec3) -> Vec3 {
+        v.mult(self)
+    }
+}
+
+/// Component-wise product, handy for colors and scaling along each axis
+impl Mul for Vec3 {
+    type Output = Vec3;
+
+    fn mul(self, other: Self) -> Vec3 {
+        Vec3 {
+            x: self.x * other.x,
+            y: self.y * other.y,
+            z: self.z * other.z,
+        }
+    }
+}
+
+impl Div<Float> for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, v: Float) -> Vec3 {
+        Vec3 {
+            x: self.x / v,
+            y: self.y / v,
+            z: self.z / v,
+        }
+    }
+}
+
+impl AddAssign for Vec3 {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vec3 {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl MulAssign<Float> for Vec3 {
+    fn mul_assign(&mut self, v: Float) {
+        *self = *self * v;
+    }
+}
+
+impl MulAssign for Vec3 {
+    fn mul_assign(&mut self, other: Self) {
+        *self = *self * other;
+    }
+}
+
+impl DivAssign<Float> for Vec3 {
+    fn div_assign(&mut self, v: Float) {
+        *self = *self / v;
+    }
+}
+
 impl Sub for Vec3 {
     type Output = Vec3;
 
@@ -137,3 +318,1018 @@ impl Add for Vec3 {
         }
     }
 }
+
+/// Orthonormal basis: three unit vectors at right angles to each other. Shading happens in a local frame around the surface normal `w`,
+/// e.g. "directions within 30 degrees of the normal" is easy to write down when the normal is the z axis. The basis takes those local directions into the world and back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// A basis around the unit vector `normal`, which becomes `w`. We don't care where `u` and `v` point as long as they are orthogonal to the normal.
+    ///
+    /// This is the branchless construction from [Building an Orthonormal Basis, Revisited](https://jcgt.org/published/0006/01/01/) (Duff et al.),
+    /// which fixes the precision problems of [Frisvad's](https://backend.orbit.dtu.dk/ws/portalfiles/portal/126824972/onb_frisvad_jgt2012_v2.pdf) when the normal points almost straight down.
+    /// No cross products with a helper axis and no normalization: it's a few multiplications and it's continuous everywhere except where `z` changes sign.
+    pub fn from_normal(normal: Vec3) -> Self {
+        let n = normal;
+        let sign = Float::copysign(1., n.z);
+        let a = -1. / (sign + n.z);
+        let b = n.x * n.y * a;
+        Self {
+            u: Vec3::new((1. + sign * n.x * n.x * a, sign * b, -sign * n.x)),
+            v: Vec3::new((b, sign + n.y * n.y * a, -n.y)),
+            w: n,
+        }
+    }
+
+    /// A basis where `u` points along `first`, and `v` is in the plane of `first` and `second` (on the side of `second`).
+    /// This is the [Gram-Schmidt process](https://en.wikipedia.org/wiki/Gram%E2%80%93Schmidt_process): remove from `second` the part along `first`, what is left is orthogonal.
+    pub fn from_directions(first: Vec3, second: Vec3) -> Self {
+        let u = first.normalized();
+        let v = (second - second.project_on(&u)).normalized();
+        Self { u, v, w: u.cross(&v) }
+    }
+
+    /// From local coordinates (`z` along `w`) to the world
+    pub fn to_world(&self, local: Vec3) -> Vec3 {
+        self.u * local.x + self.v * local.y + self.w * local.z
+    }
+
+    /// From the world to local coordinates
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new((v.dot(&self.u), v.dot(&self.v), v.dot(&self.w)))
+    }
+}
+
+/// 3x3 matrix, enough for rotation and scaling. It acts on directions, for points that need to move around use [`Mat4`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3 {
+    /// Row major entries
+    m: [[Float; 3]; 3],
+}
+
+impl Mat3 {
+    pub fn new(m: [[Float; 3]; 3]) -> Self {
+        Self { m }
+    }
+
+    pub fn identity() -> Self {
+        Self::scaling(Vec3::new((1., 1., 1.)))
+    }
+
+    /// The matrix that sends the x, y and z axes to `x`, `y` and `z`
+    pub fn from_columns(x: Vec3, y: Vec3, z: Vec3) -> Self {
+        Self::new([[x.x, y.x, z.x], [x.y, y.y, z.y], [x.z, y.z, z.z]])
+    }
+
+    pub fn scaling(factors: Vec3) -> Self {
+        Self::new([
+            [factors.x, 0., 0.],
+            [0., factors.y, 0.],
+            [0., 0., factors.z],
+        ])
+    }
+
+    /// Rotation by `angle` radians around `axis`, see [`Mat4::rotation`]
+    pub fn rotation(axis: Vec3, angle: Float) -> Self {
+        Mat4::rotation(axis, angle).linear()
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Float {
+        self.m[row][col]
+    }
+
+    pub fn transpose(&self) -> Self {
+        let m = &self.m;
+        Self::new([
+            [m[0][0], m[1][0], m[2][0]],
+            [m[0][1], m[1][1], m[2][1]],
+            [m[0][2], m[1][2], m[2][2]],
+        ])
+    }
+
+    pub fn determinant(&self) -> Float {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Inverse matrix, `None` if the matrix is singular. The columns of the inverse are cross products of the rows:
+    /// each one is orthogonal to two of the rows, and scaled so its dot product with the third is 1.
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() < Float::EPSILON * Float::EPSILON {
+            return None;
+        }
+        let row = |r: usize| Vec3::new((self.m[r][0], self.m[r][1], self.m[r][2]));
+        let (r0, r1, r2) = (row(0), row(1), row(2));
+        Some(Self::from_columns(
+            r1.cross(&r2) / det,
+            r2.cross(&r0) / det,
+            r0.cross(&r1) / det,
+        ))
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+impl Mul for Mat3 {
+    type Output = Mat3;
+
+    fn mul(self, other: Self) -> Mat3 {
+        let mut m = [[0.; 3]; 3];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = (0..3).map(|k| self.m[r][k] * other.m[k][c]).sum();
+            }
+        }
+        Mat3::new(m)
+    }
+}
+
+/// The 3x3 matrix as the top left corner of a 4x4 one, it doesn't move points.
+impl From<Mat3> for Mat4 {
+    fn from(m: Mat3) -> Self {
+        let m = m.m;
+        Mat4::new([
+            [m[0][0], m[0][1], m[0][2], 0.],
+            [m[1][0], m[1][1], m[1][2], 0.],
+            [m[2][0], m[2][1], m[2][2], 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+}
+
+/// 4x4 matrix acting on [homogeneous coordinates](https://en.wikipedia.org/wiki/Homogeneous_coordinates).
+/// A 3x3 matrix can rotate and scale but it can't move things around, adding a 4th coordinate lets us express translation as a matrix product as well.
+/// Points get `w = 1` so they are moved by the translation, directions get `w = 0` so they are not.
+#[derive(Clone, Copy, Debug)]
+pub struct Mat4 {
+    /// Row major entries
+    m: [[Float; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[Float; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn identity() -> Self {
+        Self::new([
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Self::new([
+            [1., 0., 0., offset.x],
+            [0., 1., 0., offset.y],
+            [0., 0., 1., offset.z],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Scale each axis separately, uniform scaling is `scaling(Vec3::new((s, s, s)))`
+    pub fn scaling(factors: Vec3) -> Self {
+        Self::new([
+            [factors.x, 0., 0., 0.],
+            [0., factors.y, 0., 0.],
+            [0., 0., factors.z, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Rotation by `angle` radians (counter clockwise when looking against `axis`), using [Rodrigues' formula](https://en.wikipedia.org/wiki/Rodrigues%27_rotation_formula)
+    pub fn rotation(axis: Vec3, angle: Float) -> Self {
+        let Vec3 { x, y, z } = axis.normalized();
+        let (s, c) = angle.sin_cos();
+        let t = 1. - c;
+        Self::new([
+            [t * x * x + c, t * x * y - s * z, t * x * z + s * y, 0.],
+            [t * x * y + s * z, t * y * y + c, t * y * z - s * x, 0.],
+            [t * x * z - s * y, t * y * z + s * x, t * z * z + c, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Rotation by the Euler angles `x`, `y` and `z` (radians), see [`Quat::from_euler`]
+    pub fn rotation_euler(x: Float, y: Float, z: Float) -> Self {
+        Quat::from_euler(x, y, z).into()
+    }
+
+    /// Camera to world matrix of a camera at `eye` looking at `target`. In camera coordinates `x` is right, `y` is up and the camera looks along `z`
+    /// (the same as [`CameraFrame`](crate::camera::CameraFrame)). `up` doesn't have to be orthogonal to the view direction, it just decides which way is up.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        let forward = (target - eye).normalized();
+        let right = forward.cross(&up).normalized();
+        let up = right.cross(&forward);
+        Mat4::translation(eye) * Mat3::from_columns(right, up, forward).into()
+    }
+
+    /// [Perspective projection](https://en.wikipedia.org/wiki/3D_projection#Perspective_projection) from camera coordinates (looking along `z`) to the screen.
+    /// Points in view end up with `x` and `y` in `[-1, 1]` and their distance mapped to `z` in `[0, 1]`, `0` at `near` and `1` at `far`.
+    /// `fov` is the vertical field of view in radians, `aspect_ratio` is width over height.
+    ///
+    /// Things further away look smaller because we divide by the distance, which the matrix does by copying `z` into `w`.
+    /// `near` must be in front of the camera and `far` behind it, otherwise the matrix has no inverse.
+    pub fn perspective(fov: Float, aspect_ratio: Float, near: Float, far: Float) -> Self {
+        assert!(
+            near > 0. && far > near,
+            "The near plane must be in front of the camera and the far plane behind it, got near {} and far {}",
+            near,
+            far
+        );
+        let f = 1. / (fov / 2.).tan();
+        Self::new([
+            [f / aspect_ratio, 0., 0., 0.],
+            [0., f, 0., 0.],
+            [0., 0., far / (far - near), -far * near / (far - near)],
+            [0., 0., 1., 0.],
+        ])
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Float {
+        self.m[row][col]
+    }
+
+    /// The top left 3x3 corner: the rotation and scaling, without the translation
+    pub fn linear(&self) -> Mat3 {
+        let m = &self.m;
+        Mat3::new([
+            [m[0][0], m[0][1], m[0][2]],
+            [m[1][0], m[1][1], m[1][2]],
+            [m[2][0], m[2][1], m[2][2]],
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = self.m[c][r];
+            }
+        }
+        Self::new(m)
+    }
+
+    /// Inverse matrix by [cofactor expansion](https://en.wikipedia.org/wiki/Invertible_matrix#Analytic_solution), `None` if the matrix is singular
+    pub fn inverse(&self) -> Option<Self> {
+        let m = &self.m;
+        // 2x2 determinants of the two top rows and the two bottom rows
+        let s0 = m[0][0] * m[1][1] - m[1][0] * m[0][1];
+        let s1 = m[0][0] * m[1][2] - m[1][0] * m[0][2];
+        let s2 = m[0][0] * m[1][3] - m[1][0] * m[0][3];
+        let s3 = m[0][1] * m[1][2] - m[1][1] * m[0][2];
+        let s4 = m[0][1] * m[1][3] - m[1][1] * m[0][3];
+        let s5 = m[0][2] * m[1][3] - m[1][2] * m[0][3];
+
+        let c5 = m[2][2] * m[3][3] - m[3][2] * m[2][3];
+        let c4 = m[2][1] * m[3][3] - m[3][1] * m[2][3];
+        let c3 = m[2][1] * m[3][2] - m[3][1] * m[2][2];
+        let c2 = m[2][0] * m[3][3] - m[3][0] * m[2][3];
+        let c1 = m[2][0] * m[3][2] - m[3][0] * m[2][2];
+        let c0 = m[2][0] * m[3][1] - m[3][0] * m[2][1];
+
+        let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+        if det.abs() < Float::EPSILON * Float::EPSILON {
+            return None;
+        }
+        let inv = 1. / det;
+
+        Some(Self::new([
+            [
+                (m[1][1] * c5 - m[1][2] * c4 + m[1][3] * c3) * inv,
+                (-m[0][1] * c5 + m[0][2] * c4 - m[0][3] * c3) * inv,
+                (m[3][1] * s5 - m[3][2] * s4 + m[3][3] * s3) * inv,
+                (-m[2][1] * s5 + m[2][2] * s4 - m[2][3] * s3) * inv,
+            ],
+            [
+                (-m[1][0] * c5 + m[1][2] * c2 - m[1][3] * c1) * inv,
+                (m[0][0] * c5 - m[0][2] * c2 + m[0][3] * c1) * inv,
+                (-m[3][0] * s5 + m[3][2] * s2 - m[3][3] * s1) * inv,
+                (m[2][0] * s5 - m[2][2] * s2 + m[2][3] * s1) * inv,
+            ],
+            [
+                (m[1][0] * c4 - m[1][1] * c2 + m[1][3] * c0) * inv,
+                (-m[0][0] * c4 + m[0][1] * c2 - m[0][3] * c0) * inv,
+                (m[3][0] * s4 - m[3][1] * s2 + m[3][3] * s0) * inv,
+                (-m[2][0] * s4 + m[2][1] * s2 - m[2][3] * s0) * inv,
+            ],
+            [
+                (-m[1][0] * c3 + m[1][1] * c1 - m[1][2] * c0) * inv,
+                (m[0][0] * c3 - m[0][1] * c1 + m[0][2] * c0) * inv,
+                (-m[3][0] * s3 + m[3][1] * s1 - m[3][2] * s0) * inv,
+                (m[2][0] * s3 - m[2][1] * s1 + m[2][2] * s0) * inv,
+            ],
+        ]))
+    }
+
+    /// Apply the matrix to a point (`w = 1`)
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1. || w == 0. {
+            Vec3 { x, y, z }
+        } else {
+            Vec3 {
+                x: x / w,
+                y: y / w,
+                z: z / w,
+            }
+        }
+    }
+
+    /// Apply the matrix to a direction (`w = 0`), translation has no effect on directions
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Self) -> Mat4 {
+        let mut m = [[0.; 4]; 4];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[r][k] * other.m[k][c]).sum();
+            }
+        }
+        Mat4::new(m)
+    }
+}
+
+/// An affine transformation along with its inverse. Inverting a matrix is expensive, and we need the inverse all the time
+/// (to bring rays into an object's own coordinates, and to transform normals) so we compute it once.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    matrix: Mat4,
+    inverse: Mat4,
+}
+
+impl Transform {
+    /// Panics if the matrix is not invertible (e.g. scaling by zero)
+    pub fn new(matrix: Mat4) -> Self {
+        let inverse = matrix
+            .inverse()
+            .expect("Transform matrix must be invertible");
+        Self { matrix, inverse }
+    }
+
+    pub fn identity() -> Self {
+        Self::new(Mat4::identity())
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        Self::new(Mat4::translation(offset))
+    }
+
+    pub fn scale(factors: Vec3) -> Self {
+        Self::new(Mat4::scaling(factors))
+    }
+
+    pub fn rotate(axis: Vec3, angle: Float) -> Self {
+        Self::new(Mat4::rotation(axis, angle))
+    }
+
+    /// Rotate by the Euler angles `x`, `y` and `z` (radians), see [`Quat::from_euler`]
+    pub fn rotate_euler(x: Float, y: Float, z: Float) -> Self {
+        Self::new(Mat4::rotation_euler(x, y, z))
+    }
+
+    pub fn rotate_quat(rotation: Quat) -> Self {
+        Self::new(rotation.into())
+    }
+
+    /// Scale, then rotate, then move. This is how most scene formats place objects.
+    pub fn from_trs(translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+        Self::scale(scale)
+            .then(&Self::rotate_quat(rotation))
+            .then(&Self::translate(translation))
+    }
+
+    /// Translation, rotation and scale that [`from_trs`](Self::from_trs) turns back into this transform.
+    /// The scale is the length of each column of the matrix, a mirrored transform gets a negative `x` scale.
+    /// `None` if the transform shears, e.g. a rotation followed by scaling along other axes: the columns are not at right angles and no rotation and scale give them.
+    pub fn to_trs(&self) -> Option<(Vec3, Quat, Vec3)> {
+        let m = &self.matrix;
+        let translation = Vec3::new((m.get(0, 3), m.get(1, 3), m.get(2, 3)));
+
+        let linear = m.linear();
+        let column = |c: usize| Vec3::new((linear.get(0, c), linear.get(1, c), linear.get(2, c)));
+        let (x, y, z) = (column(0), column(1), column(2));
+        let sign = if linear.determinant() < 0. { -1. } else { 1. };
+        let scale = Vec3::new((sign * x.l2(), y.l2(), z.l2()));
+        let (x, y, z) = (x / scale.x(), y / scale.y(), z / scale.z());
+
+        let tolerance = Float::EPSILON.sqrt();
+        let sheared = [x.dot(&y), y.dot(&z), z.dot(&x)]
+            .iter()
+            .any(|d| d.abs() > tolerance);
+        if sheared {
+            return None;
+        }
+        let rotation = Quat::from_mat3(&Mat3::from_columns(x, y, z));
+        Some((translation, rotation, scale))
+    }
+
+    /// Camera to world, see [`Mat4::look_at`]
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        Self::new(Mat4::look_at(eye, target, up))
+    }
+
+    /// Camera to screen, see [`Mat4::perspective`]. The inverse takes a point on the screen back to a point in camera coordinates, which gives the direction of the ray through it.
+    pub fn perspective(fov: Float, aspect_ratio: Float, near: Float, far: Float) -> Self {
+        Self::new(Mat4::perspective(fov, aspect_ratio, near, far))
+    }
+
+    /// Apply `self` first and then `next`. `Transform::scale(s).then(&Transform::translate(t))` scales an object around the origin and then moves it.
+    pub fn then(&self, next: &Transform) -> Self {
+        Self {
+            matrix: next.matrix * self.matrix,
+            inverse: self.inverse * next.inverse,
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    /// Normals don't transform like other directions. Think of a circle squashed along y, its normals lean towards the y axis rather than away from it.
+    /// To stay orthogonal to the surface, normals are multiplied by the [inverse transpose](https://www.pbr-book.org/3ed-2018/Geometry_and_Transformations/Applying_Transformations#Normals).
+    /// The result is not unit norm.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+}
+
+/// A rotation stored as a [quaternion](https://en.wikipedia.org/wiki/Quaternions_and_spatial_rotation) `w + xi + yj + zk`.
+///
+/// Rotating by `angle` around the unit `axis` is the quaternion `cos(angle / 2) + sin(angle / 2) * axis`. Four numbers instead of the nine of a matrix,
+/// no [gimbal lock](https://en.wikipedia.org/wiki/Gimbal_lock) like Euler angles, and they blend nicely: [`Quat::slerp`] turns at a constant speed from one rotation to another,
+/// where blending matrices entry by entry squashes the object in between.
+///
+/// `a * b` is the rotation `b` followed by `a`, like matrices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    w: Float,
+    x: Float,
+    y: Float,
+    z: Float,
+}
+
+impl Quat {
+    pub fn new(w: Float, x: Float, y: Float, z: Float) -> Self {
+        Self { w, x, y, z }
+    }
+
+    /// No rotation
+    pub fn identity() -> Self {
+        Self::new(1., 0., 0., 0.)
+    }
+
+    /// Rotation by `angle` radians around `axis`, counter clockwise when looking against `axis` (like [`Mat4::rotation`])
+    pub fn from_axis_angle(axis: Vec3, angle: Float) -> Self {
+        let (s, c) = (angle / 2.).sin_cos();
+        let a = axis.normalized();
+        Self::new(c, a.x * s, a.y * s, a.z * s)
+    }
+
+    /// Rotate `x` radians around the x axis, then `y` around the y axis, then `z` around the z axis (all around the fixed world axes).
+    /// In aircraft terms with y up these are pitch, yaw and roll.
+    pub fn from_euler(x: Float, y: Float, z: Float) -> Self {
+        let axis = |x, y, z| Vec3::new((x, y, z));
+        Self::from_axis_angle(axis(0., 0., 1.), z)
+            * Self::from_axis_angle(axis(0., 1., 0.), y)
+            * Self::from_axis_angle(axis(1., 0., 0.), x)
+    }
+
+    /// The rotation part of a matrix. The matrix must be a pure rotation, scaling has to be removed first.
+    ///
+    /// We read the quaternion off the [diagonal and the skew symmetric part](https://www.euclideanspace.com/maths/geometry/rotations/conversions/matrixToQuaternion/),
+    /// starting from its largest component so we never divide by something close to zero.
+    pub fn from_mat3(m: &Mat3) -> Self {
+        let m = &m.m;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0. {
+            let s = (trace + 1.).sqrt() * 2.;
+            Self::new(
+                s / 4.,
+                (m[2][1] - m[1][2]) / s,
+                (m[0][2] - m[2][0]) / s,
+                (m[1][0] - m[0][1]) / s,
+            )
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1. + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.;
+            Self::new(
+                (m[2][1] - m[1][2]) / s,
+                s / 4.,
+                (m[0][1] + m[1][0]) / s,
+                (m[0][2] + m[2][0]) / s,
+            )
+        } else if m[1][1] > m[2][2] {
+            let s = (1. + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.;
+            Self::new(
+                (m[0][2] - m[2][0]) / s,
+                (m[0][1] + m[1][0]) / s,
+                s / 4.,
+                (m[1][2] + m[2][1]) / s,
+            )
+        } else {
+            let s = (1. + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.;
+            Self::new(
+                (m[1][0] - m[0][1]) / s,
+                (m[0][2] + m[2][0]) / s,
+                (m[1][2] + m[2][1]) / s,
+                s / 4.,
+            )
+        };
+        q.normalized()
+    }
+
+    /// Axis and angle (radians) of the rotation. With no rotation the axis is arbitrary.
+    pub fn to_axis_angle(&self) -> (Vec3, Float) {
+        let q = self.normalized();
+        let s = (1. - q.w * q.w).max(0.).sqrt();
+        let angle = 2. * q.w.clamp(-1., 1.).acos();
+        if s < Float::EPSILON {
+            (Vec3::new((1., 0., 0.)), angle)
+        } else {
+            (Vec3::new((q.x / s, q.y / s, q.z / s)), angle)
+        }
+    }
+
+    pub fn dot(&self, other: &Self) -> Float {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn length(&self) -> Float {
+        self.dot(self).sqrt()
+    }
+
+    /// Only unit quaternions are rotations, rounding errors pile up when we multiply many of them
+    pub fn normalized(&self) -> Self {
+        let l = self.length();
+        Self::new(self.w / l, self.x / l, self.y / l, self.z / l)
+    }
+
+    /// For unit quaternions this is the opposite rotation
+    pub fn conjugate(&self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn inverse(&self) -> Self {
+        let l2 = self.dot(self);
+        let c = self.conjugate();
+        Self::new(c.w / l2, c.x / l2, c.y / l2, c.z / l2)
+    }
+
+    /// Rotate a vector, `q * v * q^-1` written out for unit quaternions
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        let u = Vec3::new((self.x, self.y, self.z));
+        let t = u.cross(&v) * 2.;
+        v + t * self.w + u.cross(&t)
+    }
+
+    /// [Spherical linear interpolation](https://en.wikipedia.org/wiki/Slerp): `t = 0` is `self`, `t = 1` is `other`, and in between we turn at a constant speed.
+    /// `q` and `-q` are the same rotation, we flip one if needed so we go the short way around.
+    pub fn slerp(&self, other: &Self, t: Float) -> Self {
+        let (a, mut b) = (self.normalized(), other.normalized());
+        let mut cos = a.dot(&b);
+        if cos < 0. {
+            b = Self::new(-b.w, -b.x, -b.y, -b.z);
+            cos = -cos;
+        }
+
+        // Almost the same rotation, sin(angle) is close to zero so we blend linearly instead
+        let (wa, wb) = if cos > 1. - 1e-4 {
+            (1. - t, t)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+            (((1. - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+
+        Self::new(
+            wa * a.w + wb * b.w,
+            wa * a.x + wb * b.x,
+            wa * a.y + wb * b.y,
+            wa * a.z + wb * b.z,
+        )
+        .normalized()
+    }
+}
+
+/// The [Hamilton product](https://en.wikipedia.org/wiki/Quaternion#Hamilton_product)
+impl Mul for Quat {
+    type Output = Quat;
+
+    fn mul(self, o: Self) -> Quat {
+        Quat::new(
+            self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        )
+    }
+}
+
+impl From<Quat> for Mat3 {
+    fn from(q: Quat) -> Self {
+        let Quat { w, x, y, z } = q.normalized();
+        Mat3::new([
+            [
+                1. - 2. * (y * y + z * z),
+                2. * (x * y - w * z),
+                2. * (x * z + w * y),
+            ],
+            [
+                2. * (x * y + w * z),
+                1. - 2. * (x * x + z * z),
+                2. * (y * z - w * x),
+            ],
+            [
+                2. * (x * z - w * y),
+                2. * (y * z + w * x),
+                1. - 2. * (x * x + y * y),
+            ],
+        ])
+    }
+}
+
+impl From<Quat> for Mat4 {
+    fn from(q: Quat) -> Self {
+        Mat3::from(q).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+    use crate::sampling::{sample_sphere, Rng};
+
+    const EPSILON: Float = 1e-4;
+
+    fn assert_mat4_eq(a: &Mat4, b: &Mat4) {
+        for r in 0..4 {
+            for c in 0..4 {
+                assert!(
+                    (a.get(r, c) - b.get(r, c)).abs() < EPSILON,
+                    "{:?} != {:?} at ({}, {})",
+                    a,
+                    b,
+                    r,
+                    c
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn vec3_operators() {
+        let a = Vec3::new((1., -2., 4.));
+        let b = Vec3::new((0.5, 2., -1.));
+
+        assert_eq!(a + b, Vec3::new((1.5, 0., 3.)));
+        assert_eq!(a - b, Vec3::new((0.5, -4., 5.)));
+        assert_eq!(-a, Vec3::new((-1., 2., -4.)));
+        assert_eq!(a * 2., Vec3::new((2., -4., 8.)));
+        assert_eq!(2. * a, a * 2.);
+        assert_eq!(a * b, Vec3::new((0.5, -4., -4.)));
+        assert_eq!(a / 2., Vec3::new((0.5, -1., 2.)));
+
+        // Every assignment does what its operator does
+        let assigned = |op: fn(&mut Vec3)| {
+            let mut v = a;
+            op(&mut v);
+            v
+        };
+        assert_eq!(assigned(|v| *v += Vec3::new((0.5, 2., -1.))), a + b);
+        assert_eq!(assigned(|v| *v -= Vec3::new((0.5, 2., -1.))), a - b);
+        assert_eq!(assigned(|v| *v *= 2.), a * 2.);
+        assert_eq!(assigned(|v| *v *= Vec3::new((0.5, 2., -1.))), a * b);
+        assert_eq!(assigned(|v| *v /= 2.), a / 2.);
+    }
+
+    #[test]
+    fn vec3_helpers() {
+        let a = Vec3::new((1., -2., 4.));
+        let b = Vec3::new((0.5, 2., -1.));
+
+        assert_eq!(a.min(&b), Vec3::new((0.5, -2., -1.)));
+        assert_eq!(a.max(&b), Vec3::new((1., 2., 4.)));
+        assert_eq!(a.abs(), Vec3::new((1., 2., 4.)));
+        assert_eq!(a.lerp(&b, 0.), a);
+        assert_eq!(a.lerp(&b, 1.), b);
+        assert_eq!(a.lerp(&b, 0.5), Vec3::new((0.75, 0., 1.5)));
+        assert!(a.approx_eq(&(a + Vec3::new((1e-5, 0., -1e-5))), EPSILON));
+        assert!(!a.approx_eq(&b, EPSILON));
+    }
+
+    #[test]
+    fn vec3_conversions_and_indexing() {
+        let v = Vec3::new((1., -2., 4.));
+        assert_eq!(Vec3::from((1., -2., 4.)), v);
+        assert_eq!(Vec3::from([1., -2., 4.]), v);
+        assert_eq!(<[Float; 3]>::from(v), [1., -2., 4.]);
+
+        assert_eq!((v[0], v[1], v[2]), (v.x(), v.y(), v.z()));
+        assert_eq!((v.x(), v.y(), v.z()), (1., -2., 4.));
+        let mut w = v;
+        w[0] = 3.;
+        w[2] *= 2.;
+        assert_eq!(w, Vec3::new((3., -2., 8.)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Vec3 index out of range: 3")]
+    fn vec3_index_past_z_panics() {
+        let _ = Vec3::orig()[3];
+    }
+
+    #[test]
+    fn mat4_times_inverse_is_identity() {
+        let matrices = [
+            Mat4::translation(Vec3::new((1., -2., 3.))),
+            Mat4::scaling(Vec3::new((2., 0.5, -3.))),
+            Mat4::rotation(Vec3::new((1., 2., 3.)), 1.2),
+            Mat4::translation(Vec3::new((4., 0., -1.)))
+                * Mat4::rotation(Vec3::new((0., 1., 1.)), -0.7)
+                * Mat4::scaling(Vec3::new((1., 3., 0.25))),
+            Mat4::new([
+                [2., 1., 0., 1.],
+                [0., 3., 1., 0.],
+                [1., 0., 2., 1.],
+                [0., 1., 0., 4.],
+            ]),
+        ];
+        for m in matrices.iter() {
+            let inverse = m.inverse().expect("The matrix is invertible");
+            assert_mat4_eq(&(*m * inverse), &Mat4::identity());
+            assert_mat4_eq(&(inverse * *m), &Mat4::identity());
+        }
+    }
+
+    #[test]
+    fn singular_mat4_has_no_inverse() {
+        assert!(Mat4::scaling(Vec3::new((1., 0., 1.))).inverse().is_none());
+        let repeated_row = Mat4::new([
+            [1., 2., 3., 4.],
+            [1., 2., 3., 4.],
+            [0., 1., 0., 0.],
+            [0., 0., 0., 1.],
+        ]);
+        assert!(repeated_row.inverse().is_none());
+    }
+
+    #[test]
+    fn transformed_normals_stay_orthogonal_to_the_surface() {
+        let transform = Transform::scale(Vec3::new((1., 0.2, 3.)))
+            .then(&Transform::rotate(Vec3::new((1., 1., 0.)), 0.8))
+            .then(&Transform::translate(Vec3::new((5., -1., 2.))));
+
+        // Two directions in the surface and the normal to it
+        let (t1, t2) = (Vec3::new((1., 1., 0.)), Vec3::new((0., 1., -2.)));
+        let n = t1.cross(&t2);
+
+        let normal = transform.normal(n);
+        assert!(transform.vector(t1).dot(&normal).abs() < EPSILON);
+        assert!(transform.vector(t2).dot(&normal).abs() < EPSILON);
+        // Still on the same side of the surface
+        assert!(normal.dot(&transform.vector(n)) > 0.);
+    }
+
+    fn assert_mat3_eq(a: &Mat3, b: &Mat3) {
+        for r in 0..3 {
+            for c in 0..3 {
+                assert!(
+                    (a.get(r, c) - b.get(r, c)).abs() < EPSILON,
+                    "{:?} != {:?} at ({}, {})",
+                    a,
+                    b,
+                    r,
+                    c
+                );
+            }
+        }
+    }
+
+    fn assert_vec3_eq(a: Vec3, b: Vec3) {
+        assert!((a - b).l2() < EPSILON, "{:?} != {:?}", a, b);
+    }
+
+    /// `q` and `-q` are the same rotation
+    fn assert_same_rotation(a: &Quat, b: &Quat) {
+        assert!(
+            (a.dot(b).abs() - 1.).abs() < EPSILON,
+            "{:?} and {:?} are different rotations",
+            a,
+            b
+        );
+    }
+
+    /// Rotations all over the place, including the ones close to half a turn where `from_mat3` can't use the trace
+    fn rotations() -> Vec<Quat> {
+        let axes = [
+            Vec3::new((1., 0., 0.)),
+            Vec3::new((0., 1., 0.)),
+            Vec3::new((0., 0., 1.)),
+            Vec3::new((1., 2., 3.)),
+            Vec3::new((-2., 0.5, 1.)),
+        ];
+        let angles = [0.3, 1., 2.5, 3.1, PI - 1e-3, PI];
+        axes.iter()
+            .flat_map(|&axis| {
+                angles
+                    .iter()
+                    .map(move |&angle| Quat::from_axis_angle(axis, angle))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mat3_times_inverse_is_identity() {
+        let m = Mat3::new([[2., 1., 0.], [0., 3., 1.], [1., 0., 2.]]);
+        let inverse = m.inverse().expect("The matrix is invertible");
+        assert_mat3_eq(&(m * inverse), &Mat3::identity());
+        assert_mat3_eq(&(inverse * m), &Mat3::identity());
+        assert!(Mat3::new([[1., 2., 3.], [2., 4., 6.], [0., 1., 0.]])
+            .inverse()
+            .is_none());
+    }
+
+    #[test]
+    fn quat_matrix_round_trip() {
+        for q in rotations() {
+            let m = Mat3::from(q);
+            assert_same_rotation(&Quat::from_mat3(&m), &q);
+            assert_mat3_eq(&Mat3::from(Quat::from_mat3(&m)), &m);
+
+            let v = Vec3::new((0.3, -1., 2.));
+            assert_vec3_eq(m.transform_vector(v), q.rotate(v));
+        }
+    }
+
+    #[test]
+    fn quat_matches_rotation_matrix() {
+        let axis = Vec3::new((1., 2., 3.));
+        assert_mat3_eq(
+            &Mat3::from(Quat::from_axis_angle(axis, 0.8)),
+            &Mat3::rotation(axis, 0.8),
+        );
+    }
+
+    #[test]
+    fn axis_angle_round_trip() {
+        let axis = Vec3::new((1., 2., 3.)).normalized();
+        for &angle in [0.3, 1., 2.5, PI - 1e-3].iter() {
+            let (a, alpha) = Quat::from_axis_angle(axis, angle).to_axis_angle();
+            assert_vec3_eq(a, axis);
+            assert!((alpha - angle).abs() < 1e-3, "{} != {}", alpha, angle);
+        }
+        let (_, angle) = Quat::identity().to_axis_angle();
+        assert!(angle.abs() < EPSILON);
+    }
+
+    #[test]
+    fn slerp_goes_from_start_to_end() {
+        let rotations = rotations();
+        for (a, b) in rotations.iter().zip(rotations.iter().rev()) {
+            assert_same_rotation(&a.slerp(b, 0.), a);
+            assert_same_rotation(&a.slerp(b, 1.), b);
+        }
+
+        // Half way around the z axis from 0 to 90 degrees is 45 degrees
+        let z = Vec3::new((0., 0., 1.));
+        let half = Quat::identity().slerp(&Quat::from_axis_angle(z, FRAC_PI_2), 0.5);
+        assert_same_rotation(&half, &Quat::from_axis_angle(z, FRAC_PI_4));
+    }
+
+    #[test]
+    fn slerp_takes_the_short_way() {
+        let z = Vec3::new((0., 0., 1.));
+        let (a, b) = (
+            Quat::from_axis_angle(z, 0.2),
+            Quat::from_axis_angle(z, -0.2),
+        );
+        let flipped = Quat::new(-b.w, -b.x, -b.y, -b.z);
+        assert_same_rotation(&a.slerp(&flipped, 0.5), &Quat::identity());
+    }
+
+    #[test]
+    fn perspective_inverse_goes_back_to_camera_coordinates() {
+        let projection = Transform::perspective(1., 1.5, 0.1, 100.);
+        let p = Vec3::new((1., -2., 10.));
+        assert_vec3_eq(projection.inverse().point(projection.point(p)), p);
+    }
+
+    #[test]
+    #[should_panic(expected = "near plane")]
+    fn perspective_rejects_near_plane_at_the_camera() {
+        Transform::perspective(1., 1.5, 0., 100.);
+    }
+
+    #[test]
+    #[should_panic(expected = "near plane")]
+    fn perspective_rejects_far_plane_before_near_plane() {
+        Transform::perspective(1., 1.5, 10., 1.);
+    }
+
+    #[test]
+    fn trs_round_trip() {
+        let (t, r, s) = (
+            Vec3::new((1., -2., 3.)),
+            Quat::from_axis_angle(Vec3::new((1., 2., 3.)), 2.),
+            Vec3::new((-2., 0.5, 3.)),
+        );
+        let (t2, r2, s2) = Transform::from_trs(t, r, s).to_trs().unwrap();
+        assert_vec3_eq(t2, t);
+        assert_same_rotation(&r2, &r);
+        assert_vec3_eq(s2, s);
+    }
+
+    fn assert_orthonormal(basis: &Onb) {
+        for (a, b) in [(basis.u, basis.v), (basis.v, basis.w), (basis.w, basis.u)].iter() {
+            assert!((a.l2() - 1.).abs() < EPSILON, "{:?} isn't unit length", a);
+            assert!(
+                a.dot(b).abs() < EPSILON,
+                "{:?} and {:?} aren't orthogonal",
+                a,
+                b
+            );
+        }
+        // Right handed, like the world axes: local coordinates keep their handedness
+        assert_vec3_eq(basis.u.cross(&basis.v), basis.w);
+    }
+
+    #[test]
+    fn onb_is_orthonormal() {
+        let mut rng = Rng::new(3);
+        let mut normals = (0..1000)
+            .map(|_| sample_sphere(rng.next_2d()))
+            .collect::<Vec<_>>();
+        // The axes, and straight down where a naive construction divides by almost zero
+        normals.extend(
+            [
+                (0., 0., 1.),
+                (0., 0., -1.),
+                (1., 0., 0.),
+                (1e-4, 0., -1.),
+                (0., -1e-4, -1.),
+            ]
+            .iter()
+            .map(|&n| Vec3::new(n).normalized()),
+        );
+
+        for &n in normals.iter() {
+            let basis = Onb::from_normal(n);
+            assert_vec3_eq(basis.w, n);
+            assert_orthonormal(&basis);
+            let v = Vec3::new((0.3, -2., 1.5));
+            assert_vec3_eq(basis.to_local(basis.to_world(v)), v);
+
+            let basis = Onb::from_directions(n, Vec3::new((1., 2., 3.)));
+            assert_vec3_eq(basis.u, n);
+            assert_orthonormal(&basis);
+        }
+    }
+}
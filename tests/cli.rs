@@ -0,0 +1,142 @@
+//! Tests of the command line renderer: we run the binary the way a user would and look at the images it saves.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Render the demo scene small, with a few rays per pixel, into `name.png` under the test scratch directory
+fn render(name: &str, args: &[&str]) -> PathBuf {
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("cli")
+        .join(format!("{}.png", name));
+    fs::create_dir_all(output.parent().unwrap()).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_tinyraytracer"))
+        .args(["--size", "64x48", "--samples", "4", "--output"])
+        .arg(&output)
+        .args(args)
+        .status()
+        .expect("Failed running the renderer");
+    assert!(status.success(), "Rendering {} failed", name);
+    output
+}
+
+fn pixels(path: &Path) -> Vec<u8> {
+    image::open(path).unwrap().to_rgb8().into_raw()
+}
+
+/// The random numbers only depend on the seed, so the same seed gives the same image and another seed a different one
+#[test]
+fn seed_picks_the_image() {
+    let first = pixels(&render("seed-1", &["--seed", "1"]));
+    let again = pixels(&render("seed-1-again", &["--seed", "1"]));
+    let other = pixels(&render("seed-2", &["--seed", "2"]));
+
+    assert_eq!(first.len(), 64 * 48 * 3);
+    assert!(first == again, "The same seed gave different images");
+    assert!(first != other, "Different seeds gave the same image");
+}
+
+/// Run `tinyraytracer animate` on two tiny frames into `dir`, returning what it printed
+fn animate(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_tinyraytracer"))
+        .args(["animate", "--frames", "1-2"])
+        .args(["--size", "32x24", "--samples", "2"])
+        .arg("--output-dir")
+        .arg(dir)
+        .args(args)
+        .output()
+        .expect("Failed running the renderer");
+    assert!(output.status.success(), "Animating failed");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// A frame that ran out of time isn't finished: it must not be skipped, and the next run picks it up from its checkpoint
+#[test]
+fn interrupted_frames_are_rendered_again() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("cli")
+        .join("animate");
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    let frame = |n: u32| dir.join(format!("frame_{:04}.png", n));
+
+    animate(&dir, &["--checkpoint", "--time-budget", "0"]);
+    assert!(!frame(1).exists(), "An unfinished frame was saved as done");
+    assert!(frame(1).with_extension("checkpoint").exists());
+    assert!(frame(1).with_extension("partial.png").exists());
+
+    let resumed = animate(&dir, &[]);
+    assert!(
+        resumed.contains("Resuming"),
+        "The checkpoint wasn't used:\n{}",
+        resumed
+    );
+    for n in 1..=2 {
+        assert!(frame(n).exists());
+        assert!(!frame(n).with_extension("checkpoint").exists());
+        assert!(!frame(n).with_extension("partial.png").exists());
+    }
+
+    let again = animate(&dir, &[]);
+    assert!(
+        !again.contains("Rendered"),
+        "Finished frames were rendered again:\n{}",
+        again
+    );
+}
+
+/// Opening the lens blurs what's out of focus, opening the shutter blurs the bouncing sphere
+#[test]
+fn lens_and_shutter_flags_blur_the_image() {
+    let sharp = pixels(&render("pinhole", &[]));
+    let lens = pixels(&render("lens", &["--aperture", "0.5", "--focus-distance", "16"]));
+    let shutter = pixels(&render("shutter", &["--shutter", "0.25"]));
+
+    assert!(lens != sharp, "The lens didn't change the image");
+    assert!(shutter != sharp, "The shutter didn't change the image");
+}
+
+/// Run the renderer expecting it to refuse, returning what it printed on stderr
+fn refused(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_tinyraytracer"))
+        .args(args)
+        .output()
+        .expect("Failed running the renderer");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    stderr
+}
+
+/// Bad flags and checkpoints we can't resume from are reported, the renderer doesn't crash on them
+#[test]
+fn invalid_input_is_an_error() {
+    assert!(refused(&["--samples", "many"]).contains("--samples"));
+    assert!(refused(&["--projection", "stereographic"]).contains("projection"));
+    assert!(refused(&["animate", "--frames", "0-2"]).contains("frame 0"));
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cli");
+    fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("refused.png");
+    let output = output.to_str().unwrap();
+    let checkpoint = dir.join("refused.checkpoint");
+
+    let image = ["--size", "16x12", "--samples", "2", "--output", output];
+    let resume = [&image[..], &["--resume"]].concat();
+
+    fs::write(&checkpoint, "not a checkpoint").unwrap();
+    let corrupt = refused(&resume);
+    assert!(corrupt.contains("Failed reading checkpoint"), "{}", corrupt);
+
+    // A checkpoint of the same image with another seed
+    let status = Command::new(env!("CARGO_BIN_EXE_tinyraytracer"))
+        .args(image)
+        .args(["--checkpoint", "--time-budget", "0", "--seed", "1"])
+        .status()
+        .expect("Failed running the renderer");
+    assert!(status.success());
+    let other = refused(&resume);
+    assert!(other.contains("different render"), "{}", other);
+}
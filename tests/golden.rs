@@ -0,0 +1,333 @@
+//! Golden image tests: we render a few reference scenes at low resolution and compare them with the images in `tests/goldens`.
+//! Any change to the math shows up as a changed image, so these catch what a look at `current.png` would miss.
+//!
+//! The images don't have to match exactly. Building with the `f64` feature or without AVX moves a few silhouette pixels around,
+//! so we check that the images are close overall ([RMSE](https://en.wikipedia.org/wiki/Root-mean-square_deviation)) and that only a handful of pixels are way off.
+//!
+//! When a test fails, the rendered image and a difference image (brighter is more different) are saved under `target/tmp/golden`.
+//! If the change is on purpose, look at them, then bless the new images with `BLESS=1 cargo test --test golden`.
+
+use std::path::{Path, PathBuf};
+use tinyraytracer::animation::Track;
+use tinyraytracer::blocks::*;
+use tinyraytracer::camera::{Camera, CameraFrame, OrthographicCamera, Shutter, ThinLensCamera};
+use tinyraytracer::csg::Csg;
+use tinyraytracer::demo::demo_scene;
+use tinyraytracer::float::consts::FRAC_2_PI;
+use tinyraytracer::float::Float;
+use tinyraytracer::sampling::PixelSampler;
+use tinyraytracer::sdf::{SdfExpr, SdfObject};
+use tinyraytracer::trace::{render_pixels, SceneObject};
+use tinyraytracer::vectors::{Transform, Vec3};
+
+/// Small enough to render in a blink, big enough to see what went wrong
+const SIZE: (u32, u32) = (128, 96);
+
+/// How different the rendered image may be from the golden one. Channel values are in `0..=255`.
+struct Tolerance {
+    max_rmse: f64,
+    /// A pixel is an outlier if one of its channels is more than this off
+    outlier_diff: u8,
+    /// Share of the pixels that may be outliers
+    max_outliers: f64,
+}
+
+const TOLERANCE: Tolerance = Tolerance {
+    max_rmse: 1.,
+    outlier_diff: 32,
+    max_outliers: 0.002,
+};
+
+struct Comparison {
+    rmse: f64,
+    max_diff: u8,
+    outliers: usize,
+}
+
+fn compare(
+    actual: &image::RgbImage,
+    golden: &image::RgbImage,
+    tolerance: &Tolerance,
+) -> Comparison {
+    let mut squares = 0.;
+    let mut max_diff = 0;
+    let mut outliers = 0;
+    for (a, g) in actual.pixels().zip(golden.pixels()) {
+        let diffs = (0..3).map(|c| a[c].abs_diff(g[c])).collect::<Vec<u8>>();
+        squares += diffs.iter().map(|&d| f64::from(d).powi(2)).sum::<f64>();
+        let pixel_max = diffs.iter().copied().max().unwrap_or(0);
+        max_diff = max_diff.max(pixel_max);
+        if pixel_max > tolerance.outlier_diff {
+            outliers += 1;
+        }
+    }
+
+    let channels = 3 * actual.width() * actual.height();
+    Comparison {
+        rmse: (squares / f64::from(channels)).sqrt(),
+        max_diff,
+        outliers,
+    }
+}
+
+/// Every channel is the difference times 4, so small differences are visible too
+fn diff_image(actual: &image::RgbImage, golden: &image::RgbImage) -> image::RgbImage {
+    image::ImageBuffer::from_fn(actual.width(), actual.height(), |i, j| {
+        let (a, g) = (actual.get_pixel(i, j), golden.get_pixel(i, j));
+        image::Rgb([0, 1, 2].map(|c| a[c].abs_diff(g[c]).saturating_mul(4)))
+    })
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("goldens")
+        .join(format!("{}.png", name))
+}
+
+/// Render the scene and compare it with the golden image `name`, or make it the golden image when blessing
+fn check_golden(name: &str, scene: &[SceneObject], lights: &[LightSource], camera: &dyn Camera) {
+    let (width, height) = SIZE;
+    let colors = render_pixels(scene, lights, camera, SIZE, &PixelSampler::new(1, 0));
+    let actual = image::ImageBuffer::from_fn(width, height, |i, j| {
+        Material::to_pixel(colors[(j * width + i) as usize])
+    });
+    check_image(name, &actual);
+}
+
+/// Compare a rendered image with the golden image `name`, or make it the golden image when blessing
+fn check_image(name: &str, actual: &image::RgbImage) {
+    let (width, height) = SIZE;
+    let path = golden_path(name);
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        actual.save(&path).expect("Failed saving golden image");
+        return;
+    }
+
+    let golden = match image::open(&path) {
+        Ok(golden) => golden.to_rgb8(),
+        Err(e) => panic!(
+            "No golden image at {} ({}), bless one with `BLESS=1 cargo test --test golden`",
+            path.display(),
+            e
+        ),
+    };
+    assert_eq!(
+        golden.dimensions(),
+        SIZE,
+        "{} has the wrong size",
+        path.display()
+    );
+
+    let comparison = compare(actual, &golden, &TOLERANCE);
+    let pixels = (width * height) as f64;
+    if comparison.rmse <= TOLERANCE.max_rmse
+        && comparison.outliers as f64 <= TOLERANCE.max_outliers * pixels
+    {
+        return;
+    }
+
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let actual_path = out_dir.join(format!("{}.actual.png", name));
+    let diff_path = out_dir.join(format!("{}.diff.png", name));
+    actual
+        .save(&actual_path)
+        .expect("Failed saving rendered image");
+    diff_image(actual, &golden)
+        .save(&diff_path)
+        .expect("Failed saving difference image");
+
+    panic!(
+        "{} doesn't match its golden image: RMSE {:.3} (at most {}), max channel difference {}, {} pixels off by more than {} (at most {:.0})\n\
+         rendered: {}\ndifference: {}\nIf this is on purpose, bless it with `BLESS=1 cargo test --test golden`",
+        name,
+        comparison.rmse,
+        TOLERANCE.max_rmse,
+        comparison.max_diff,
+        comparison.outliers,
+        TOLERANCE.outlier_diff,
+        TOLERANCE.max_outliers * pixels,
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+fn aspect_ratio() -> Float {
+    SIZE.0 as Float / SIZE.1 as Float
+}
+
+/// The perspective camera of the command line renderer
+fn perspective(frame: CameraFrame) -> ThinLensCamera {
+    ThinLensCamera::from_frame(frame, 2. * FRAC_2_PI, aspect_ratio())
+}
+
+#[test]
+fn demo() {
+    let (scene, lights, frame) = demo_scene(0.);
+    check_golden("demo", &scene, &lights, &perspective(frame));
+}
+
+/// Half a second into the animation: the ivory sphere is up in the air, the first light dims and the camera has moved a bit to the right
+#[test]
+fn demo_animated() {
+    let (scene, lights, frame) = demo_scene(0.5);
+    check_golden("demo_animated", &scene, &lights, &perspective(frame));
+}
+
+#[test]
+fn demo_orthographic() {
+    let (scene, lights, frame) = demo_scene(0.);
+    let camera = OrthographicCamera::new(frame, 20., aspect_ratio());
+    check_golden("demo_orthographic", &scene, &lights, &camera);
+}
+
+/// The demo scene the way the command line renderer draws it: several rays per pixel from a fixed seed, progressive passes over the tiles
+#[test]
+fn demo_cli() {
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let output = out_dir.join("demo_cli.rendered.png");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_tinyraytracer"))
+        .args(["--size", &format!("{}x{}", SIZE.0, SIZE.1)])
+        .args(["--samples", "8", "--seed", "3", "--tile-order", "hilbert"])
+        .arg("--output")
+        .arg(&output)
+        .status()
+        .expect("Failed running the renderer");
+    assert!(status.success(), "The renderer failed");
+
+    let actual = image::open(&output)
+        .expect("Failed reading the rendered image")
+        .to_rgb8();
+    check_image("demo_cli", &actual);
+}
+
+/// Every kind of object we have, on a floor in front of a wall. The shutter is open from time 0 to 1 and we look in the middle of it,
+/// so the moving objects are halfway along their tracks.
+#[test]
+fn shapes() {
+    let ivory = Material::new((0.4, 0.4, 0.3), (0.6, 0.3, 0.1, 0.0), 50., 1.0);
+    let glass = Material::new((0.6, 0.7, 0.8), (0., 0.5, 0.1, 0.8), 125., 1.5);
+    let red_rubber = Material::new((0.3, 0.1, 0.1), (0.9, 0.1, 0.0, 0.0), 10., 1.0);
+    let mirror = Material::new((1., 1., 1.), (0., 10., 0.8, 0.0), 1425., 1.0);
+
+    let sphere = |center: (Float, Float, Float), radius: Float, material: Material| Sphere {
+        center: Vec3::new(center),
+        radius,
+        material,
+    };
+    let blob = SdfExpr::sphere(1.)
+        .smooth_union(
+            SdfExpr::sphere(0.8).translate(Vec3::new((1.2, 0., 0.))),
+            0.5,
+        )
+        .translate(Vec3::new((4., -2., -14.)));
+
+    let scene: Vec<SceneObject> = vec![
+        Box::new(Rectangle2D::new(
+            Vec3::new((-10., -4., -6.)),
+            Vec3::new((0., -4., -16.)),
+            Vec3::new((1., 0., 0.)),
+            red_rubber,
+        )),
+        Box::new(Triangle::new(
+            Vec3::new((-7., -4., -18.)),
+            Vec3::new((-3., -4., -18.)),
+            Vec3::new((-5., 1., -18.)),
+            ivory,
+        )),
+        Box::new(Disk::new(
+            Vec3::new((5., 3., -20.)),
+            Vec3::new((0., 0., 1.)),
+            2.,
+            mirror,
+        )),
+        Box::new(Cylinder::new(
+            Vec3::new((-3., -4., -12.)),
+            Vec3::new((-3., -1., -12.)),
+            1.,
+            ivory,
+        )),
+        Box::new(Cone::new(
+            Vec3::new((0., -4., -18.)),
+            Vec3::new((0., 0., -18.)),
+            1.5,
+            red_rubber,
+        )),
+        Box::new(Torus::new(
+            Vec3::new((-1., 2.5, -16.)),
+            Vec3::new((0.3, 1., 0.2)),
+            1.5,
+            0.5,
+            ivory,
+        )),
+        Box::new(Csg::difference(
+            sphere((1.5, -2.5, -10.), 1.5, glass),
+            sphere((2.2, -2., -9.), 1., glass),
+        )),
+        Box::new(SdfObject::new(blob, ivory).bounds(Aabb::from_points(&[
+            Vec3::new((2.5, -3.5, -15.5)),
+            Vec3::new((6.5, -0.5, -12.5)),
+        ]))),
+        // The wall faces the camera, one sided it still looks the same
+        Box::new(Sided::one_sided(Plane {
+            normal: Vec3::new((0., 0., 1.)),
+            point: Vec3::new((0., 0., -30.)),
+        })),
+        // A squashed sphere, tilted
+        Box::new(Transformed::new(
+            sphere((0., 0., 0.), 1., red_rubber),
+            Transform::scale(Vec3::new((1.5, 0.5, 0.5)))
+                .then(&Transform::rotate(Vec3::new((0., 0., 1.)), 0.5))
+                .then(&Transform::translate(Vec3::new((-6., 2., -14.)))),
+        )),
+        Box::new(MovingSphere::new(
+            Track::new(vec![
+                (0., Vec3::new((5., -3., -9.))),
+                (1., Vec3::new((6., -3., -11.))),
+            ]),
+            0.7,
+            red_rubber,
+        )),
+        // A cylinder tipping over
+        Box::new(MotionTransformed::new(
+            Cylinder::new(
+                Vec3::new((0., -0.8, 0.)),
+                Vec3::new((0., 0.8, 0.)),
+                0.5,
+                mirror,
+            ),
+            Track::new(vec![
+                (0., Transform::translate(Vec3::new((-7., -3., -10.)))),
+                (
+                    1.,
+                    Transform::rotate(Vec3::new((0., 0., 1.)), 1.5)
+                        .then(&Transform::translate(Vec3::new((-7., -3., -10.)))),
+                ),
+            ]),
+        )),
+    ];
+    let lights = vec![
+        LightSource {
+            position: Vec3::new((-20., 20., 20.)),
+            intensity: 1.5,
+        },
+        LightSource {
+            position: Vec3::new((30., 20., 30.)),
+            intensity: 1.3,
+        },
+    ];
+
+    let frame = CameraFrame::look_at(
+        Vec3::new((0., 1., -3.)),
+        Vec3::new((0., -1., -15.)),
+        Vec3::new((0., 1., 0.)),
+    );
+    let camera = ThinLensCamera {
+        shutter: Shutter::new(0., 1.),
+        ..perspective(frame)
+    };
+    check_golden("shapes", &scene, &lights, &camera);
+}